@@ -14,8 +14,7 @@ fn main() {
     for _ in 0..1000 {
         let _v = igzip::compress(
             Cursor::new(&data),
-            igzip::CompressionLevel::Three,
-            isal::igzip::Codec::Gzip,
+            igzip::CompressOptions::new(igzip::CompressionLevel::Three, igzip::Codec::Gzip),
         )
         .unwrap();
     }
@@ -0,0 +1,5 @@
+// Hand written, igzip_lib.h doesn't declare the CRC functions isa-l exports from crc.h
+extern "C" {
+    #[doc = " @brief Generate CRC from the IEEE gzip/zlib standard, runs appropriate version.\n\n This function determines what instruction sets are enabled and selects the\n appropriate version at runtime. Both the initial and final CRC are inverted,\n so an `init_crc` of 0 starts a new CRC and a previous result continues it.\n\n @param init_crc: initial CRC value, 32 bits\n @param buf: buffer to calculate CRC on\n @param len: buffer length in bytes (64-bit data)\n\n @returns 32 bit CRC"]
+    pub fn crc32_gzip_refl(init_crc: u32, buf: *const ::std::os::raw::c_uchar, len: u64) -> u32;
+}
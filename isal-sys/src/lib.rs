@@ -2,4 +2,5 @@
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
 
+pub mod crc;
 pub mod igzip_lib;
@@ -14,6 +14,12 @@ pub enum Error {
     // isal decompression errors
     DecompressionError(DecompCode),
 
+    // Output buffer too small, with the number of bytes it needs to be
+    OutputTooSmall(usize),
+
+    // Data following the end of the stream, at this offset in the input
+    TrailingData(u64),
+
     // Anything else not covered, exit code, message
     Other((Option<isize>, String)),
 }
@@ -38,6 +44,15 @@ impl fmt::Display for Error {
         match self {
             Error::Io(err) => write!(f, "{err}"),
             Error::Other((exit_code, msg)) => write!(f, "{msg} (Exit code: {exit_code:?})"),
+            Error::OutputTooSmall(required) => {
+                write!(f, "Output buffer too small, {required} bytes needed")
+            }
+            Error::TrailingData(offset) => {
+                write!(
+                    f,
+                    "Trailing data after the end of the stream at offset {offset}"
+                )
+            }
             _ => write!(f, "{self:?}"),
         }
     }
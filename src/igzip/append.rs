@@ -0,0 +1,365 @@
+//! Appending to existing gzip files without rewriting them
+use crate::igzip::index::{IndexBuilder, IndexedGzipReader, DEFAULT_SPAN};
+use crate::igzip::*;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Options for `GzipAppender::open_with`
+#[derive(Debug, Clone)]
+pub struct AppendOptions {
+    level: CompressionLevel,
+    single_member: bool,
+    gzip_header: Option<GzipHeader>,
+}
+
+impl AppendOptions {
+    pub fn new(level: CompressionLevel) -> Self {
+        Self {
+            level,
+            single_member: false,
+            gzip_header: None,
+        }
+    }
+
+    /// Continue the last member rather than adding a new one, default false.
+    ///
+    /// The last deflate blocks of the member are recompressed along with the new data,
+    /// from the last block boundary found, usually a little over `index::DEFAULT_SPAN`
+    /// of data from its end. They are held in memory with the new data, compressed, and
+    /// only written over the end of the member once the `GzipAppender` is finished, so
+    /// the file is left as it was should anything fail before then.
+    pub fn single_member(mut self, single_member: bool) -> Self {
+        self.single_member = single_member;
+        self
+    }
+
+    /// Header for a new member, default `GzipHeader::default()`
+    pub fn gzip_header(mut self, header: GzipHeader) -> Self {
+        self.gzip_header = Some(header);
+        self
+    }
+}
+
+// Where the data goes, by mode
+enum Output {
+    // a new member, written straight to the end of the file
+    Member(write::Encoder<File>),
+    // the last member continued, written over its end when finished
+    Continued(Continued),
+}
+
+// The last member from its last block boundary on, `pos` in the file, and its
+// trailer, updated as data is written
+struct Continued {
+    file: File,
+    pos: u64,
+    encoder: write::Encoder<Vec<u8>>,
+    crc: u32,
+    size: u32,
+}
+
+/// Appends data to a gzip file, by default as a new member written by `write::Encoder`.
+///
+/// Opening checks every member of the file, trailers included, so the data is only
+/// ever added to a valid file; a missing file is created.
+///
+/// Example
+/// -------
+/// ```no_run
+/// use std::io::Write;
+/// use isal::igzip::{append::GzipAppender, CompressionLevel};
+///
+/// let mut appender = GzipAppender::open("app.log.gz", CompressionLevel::Three).unwrap();
+/// appender.write_all(b"another line\n").unwrap();
+/// appender.finish().unwrap();
+/// ```
+pub struct GzipAppender {
+    // only taken by `finish`
+    output: Option<Output>,
+    finished: bool,
+}
+
+impl GzipAppender {
+    /// Open `path` to add a new member compressed at `level`.
+    pub fn open<P: AsRef<Path>>(path: P, level: CompressionLevel) -> io::Result<Self> {
+        Self::open_with(path, AppendOptions::new(level))
+    }
+
+    /// Open `path` to append to with `options`.
+    pub fn open_with<P: AsRef<Path>>(path: P, options: AppendOptions) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        // Decompress each member in turn, indexing them when the last must be continued
+        let span = match options.single_member {
+            true => DEFAULT_SPAN,
+            false => u64::MAX,
+        };
+        let file_len = file.metadata()?.len();
+        let mut last = None;
+        let mut start = 0;
+        while start < file_len {
+            file.seek(SeekFrom::Start(start))?;
+            let (index, len) = IndexBuilder::new().span(span).build_at(&mut file, start)?;
+            last = Some((index, start + len));
+            start += len;
+        }
+
+        let (index, end) = match last {
+            Some(last) if options.single_member => last,
+            _ => {
+                let header = options.gzip_header.unwrap_or_default();
+                let opts = CompressOptions::new(options.level, Codec::Gzip).gzip_header(header);
+                file.seek(SeekFrom::End(0))?;
+                return Ok(Self {
+                    output: Some(Output::Member(write::Encoder::new(file, opts))),
+                    finished: false,
+                });
+            }
+        };
+
+        let mut trailer = [0u8; 8];
+        file.seek(SeekFrom::Start(end - 8))?;
+        file.read_exact(&mut trailer)?;
+        let crc = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+        let size = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+
+        // Data from the last block boundary on is compressed again
+        let checkpoint = index.checkpoints().last().unwrap().clone();
+        let mut tail = vec![];
+        let mut reader = IndexedGzipReader::new(&mut file, index);
+        reader.seek(SeekFrom::Start(checkpoint.offset()))?;
+        reader.read_to_end(&mut tail)?;
+
+        // Blocks before the boundary stay as they are, though the boundary may be in
+        // the middle of a byte, then bridged to the next
+        let pos = checkpoint.bit_offset() / 8;
+        let bits = checkpoint.bit_offset() % 8;
+        let mut bridge = vec![];
+        if bits != 0 {
+            let mut byte = [0u8; 1];
+            file.seek(SeekFrom::Start(pos))?;
+            file.read_exact(&mut byte)?;
+            bridge = stitch::bridge(byte[0], bits as u32);
+        }
+
+        let mut opts = CompressOptions::new(options.level, Codec::Deflate);
+        if !checkpoint.window().is_empty() {
+            opts = opts.dictionary(checkpoint.window());
+        }
+        let mut encoder = write::Encoder::new(bridge, opts);
+        encoder.write_all(&tail)?;
+        let continued = Continued {
+            file,
+            pos,
+            encoder,
+            crc,
+            size,
+        };
+        Ok(Self {
+            output: Some(Output::Continued(continued)),
+            finished: false,
+        })
+    }
+
+    /// Mutable reference to the file, not advisable to modify during writing.
+    pub fn get_ref_mut(&mut self) -> &mut File {
+        match self.output.as_mut().unwrap() {
+            Output::Member(encoder) => encoder.get_ref_mut(),
+            Output::Continued(continued) => &mut continued.file,
+        }
+    }
+
+    // Reference to the file
+    pub fn get_ref(&self) -> &File {
+        match self.output.as_ref().unwrap() {
+            Output::Member(encoder) => encoder.get_ref(),
+            Output::Continued(continued) => &continued.file,
+        }
+    }
+
+    /// End the member, writing the trailer, without giving up the file. Does nothing
+    /// if already finished.
+    ///
+    /// When continuing the last member, this is when the file is written to.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        match self.output.as_mut().unwrap() {
+            Output::Member(encoder) => encoder.try_finish()?,
+            Output::Continued(continued) => {
+                continued.encoder.try_finish()?;
+                let mut tail = continued.encoder.get_ref().clone();
+                tail.extend_from_slice(&continued.crc.to_le_bytes());
+                tail.extend_from_slice(&continued.size.to_le_bytes());
+                // Truncated only once written over, in case the new end is shorter
+                let file = &mut continued.file;
+                file.seek(SeekFrom::Start(continued.pos))?;
+                file.write_all(&tail)?;
+                file.set_len(continued.pos + tail.len() as u64)?;
+            }
+        }
+        self.finished = true;
+        Ok(())
+    }
+
+    /// End the member and return the file
+    pub fn finish(mut self) -> io::Result<File> {
+        self.try_finish()?;
+        match self.output.take().unwrap() {
+            Output::Member(encoder) => encoder.finish(),
+            Output::Continued(continued) => Ok(continued.file),
+        }
+    }
+}
+
+impl io::Write for GzipAppender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.finished && !buf.is_empty() {
+            return Err(io::Error::other("write after the member was finished"));
+        }
+        match self.output.as_mut().unwrap() {
+            Output::Member(encoder) => encoder.write(buf),
+            Output::Continued(continued) => {
+                let n = continued.encoder.write(buf)?;
+                continued.crc = crc32_combine(continued.crc, crc32(&buf[..n]), n as u64);
+                // ISIZE is the length modulo 2^32
+                continued.size = continued.size.wrapping_add(n as u32);
+                Ok(n)
+            }
+        }
+    }
+
+    /// Sync flush, writing out all data given so far, and flush the file; does
+    /// nothing when continuing the last member, which is only written when finished.
+    fn flush(&mut self) -> io::Result<()> {
+        match self.output.as_mut().unwrap() {
+            Output::Member(encoder) => encoder.flush(),
+            Output::Continued(_) => Ok(()),
+        }
+    }
+}
+
+impl Drop for GzipAppender {
+    fn drop(&mut self) {
+        // Errors can't be reported from here, call `finish` to see them
+        if self.output.is_some() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::igzip::tests::{gen_log_data, same_same};
+
+    // File in the temp dir, removed when dropped
+    struct TempPath(std::path::PathBuf);
+
+    impl TempPath {
+        fn new(name: &str) -> Self {
+            let name = format!("isal-append-{}-{name}.gz", std::process::id());
+            Self(std::env::temp_dir().join(name))
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn new_members() -> Result<()> {
+        let path = TempPath::new("new_members");
+        let (first, second) = (gen_log_data(0..50_000), gen_log_data(50_000..60_000));
+
+        let mut appender = GzipAppender::open(&path.0, CompressionLevel::Three)?;
+        appender.write_all(&first)?;
+        appender.finish()?;
+        let mut appender = GzipAppender::open(&path.0, CompressionLevel::One)?;
+        appender.write_all(&second)?;
+        drop(appender);
+
+        let compressed = std::fs::read(&path.0)?;
+        let decompressed = decompress(compressed.as_slice(), Codec::Gzip)?;
+        assert!(same_same(&decompressed, &[first.clone(), second].concat()));
+
+        // Only the first member for a single member decoder
+        let mut decompressed = vec![];
+        flate2::read::GzDecoder::new(compressed.as_slice()).read_to_end(&mut decompressed)?;
+        assert!(same_same(&decompressed, &first));
+        Ok(())
+    }
+
+    #[test]
+    fn single_member() -> Result<()> {
+        let path = TempPath::new("single_member");
+        let data = gen_log_data(0..200_000);
+        let opts = CompressOptions::new(CompressionLevel::Three, Codec::Gzip);
+        std::fs::write(&path.0, compress(&data[..3_000_000], opts)?)?;
+
+        let mut expected = data[..3_000_000].to_vec();
+        for part in data[3_000_000..].chunks(700_000) {
+            let opts = AppendOptions::new(CompressionLevel::One).single_member(true);
+            let mut appender = GzipAppender::open_with(&path.0, opts)?;
+            appender.write_all(part)?;
+            appender.finish()?;
+            expected.extend_from_slice(part);
+
+            // All in one member, as a single member decoder sees it
+            let compressed = std::fs::read(&path.0)?;
+            let mut decompressed = vec![];
+            flate2::read::GzDecoder::new(compressed.as_slice()).read_to_end(&mut decompressed)?;
+            assert!(same_same(&decompressed, &expected));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn single_member_failed() -> Result<()> {
+        let path = TempPath::new("single_member_failed");
+        let data = gen_log_data(0..100_000);
+        let compressed = compress(data.as_slice(), CompressOptions::default())?;
+        std::fs::write(&path.0, &compressed)?;
+
+        let opts = AppendOptions::new(CompressionLevel::One).single_member(true);
+        let mut appender = GzipAppender::open_with(&path.0, opts)?;
+        appender.write_all(&gen_log_data(100_000..110_000))?;
+        // Writing to the file fails from here on
+        *appender.get_ref_mut() = File::open(&path.0)?;
+        assert!(appender.try_finish().is_err());
+        drop(appender);
+
+        let after = std::fs::read(&path.0)?;
+        assert_eq!(after, compressed);
+        assert!(same_same(
+            &decompress(after.as_slice(), Codec::Gzip)?,
+            &data
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid() -> Result<()> {
+        let path = TempPath::new("invalid");
+        let data = gen_log_data(0..10_000);
+        let mut compressed = compress(data.as_slice(), CompressOptions::default())?;
+        let n = compressed.len();
+        compressed[n - 8] ^= 1;
+        std::fs::write(&path.0, &compressed)?;
+
+        assert!(GzipAppender::open(&path.0, CompressionLevel::Three).is_err());
+        // Nothing was written
+        assert_eq!(std::fs::read(&path.0)?, compressed);
+        Ok(())
+    }
+}
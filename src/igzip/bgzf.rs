@@ -0,0 +1,488 @@
+//! BGZF, the blocked gzip format of BAM, VCF and tabix-indexed files
+//!
+//! A BGZF file is a series of gzip members of at most 64KiB each, whose extra field
+//! carries a `BC` subfield with the size of the member, ending with an empty member
+//! as EOF marker. Knowing where each member starts allows random access through
+//! virtual offsets: the offset of a member in the file shifted up 16 bits, or'ed
+//! with an offset into its decompressed data.
+use crate::igzip::*;
+use std::io::{self, SeekFrom};
+
+/// Largest BGZF block, compressed or not
+pub const BGZF_MAX_BLOCK_SIZE: usize = 64 * 1024;
+
+/// Data compressed into each block by `Writer`, leaving room for the header, trailer
+/// and stored blocks should it not compress, as htslib does
+pub const BGZF_BLOCK_DATA_LEN: usize = 0xff00;
+
+/// The empty block ending a BGZF file
+pub const BGZF_EOF: [u8; 28] = [
+    0x1f, 0x8b, 8, 4, 0, 0, 0, 0, 0, 0xff, 6, 0, b'B', b'C', 2, 0, 0x1b, 0, 3, 0, 0, 0, 0, 0, 0, 0,
+    0, 0,
+];
+
+// Block header up to BSIZE: no time stamp, unknown OS and an extra field of just the
+// BC subfield
+const BGZF_HEADER: [u8; 16] = [
+    0x1f, 0x8b, 8, 4, 0, 0, 0, 0, 0, 0xff, 6, 0, b'B', b'C', 2, 0,
+];
+const BGZF_HEADER_LEN: usize = BGZF_HEADER.len() + 2;
+const GZIP_TRAILER_LEN: usize = 8;
+
+/// Length of the BGZF block starting `data`, from the BSIZE in the BC subfield of its
+/// extra field; `None` if it isn't one or its header hasn't been read in full.
+pub(crate) fn block_size(data: &[u8]) -> Option<usize> {
+    if data.len() < GZIP_HEADER_LEN + 2 || data[3] & header::FEXTRA == 0 {
+        return None;
+    }
+    let xlen = u16::from_le_bytes([data[10], data[11]]) as usize;
+    let mut extra = data.get(12..12 + xlen)?;
+    while extra.len() >= 4 {
+        let len = u16::from_le_bytes([extra[2], extra[3]]) as usize;
+        if extra[..2] == *b"BC" && len == 2 && extra.len() >= 6 {
+            return Some(u16::from_le_bytes([extra[4], extra[5]]) as usize + 1);
+        }
+        extra = extra.get(4 + len..)?;
+    }
+    None
+}
+
+/// BGZF compression for output streams implementing `std::io::Write`.
+///
+/// Data is compressed in blocks of `BGZF_BLOCK_DATA_LEN` bytes with `compress_into`;
+/// `flush` ends the current block early. The EOF marker is written by `try_finish` or
+/// `finish`, or when the `Writer` is dropped.
+///
+/// Example
+/// -------
+/// ```
+/// use std::io::{Read, Write};
+/// use isal::igzip::{bgzf, CompressionLevel};
+///
+/// let mut writer = bgzf::Writer::new(vec![], CompressionLevel::Three);
+/// writer.write_all(b"first record\n").unwrap();
+/// writer.flush().unwrap();
+/// let offset = writer.virtual_offset();
+/// writer.write_all(b"second record\n").unwrap();
+/// let compressed = writer.finish().unwrap();
+///
+/// let mut reader = bgzf::Reader::new(std::io::Cursor::new(compressed));
+/// reader.seek_virtual(offset).unwrap();
+/// let mut rest = String::new();
+/// reader.read_to_string(&mut rest).unwrap();
+/// assert_eq!(rest, "second record\n");
+/// ```
+pub struct Writer<W: io::Write> {
+    // only taken by `finish`
+    inner: Option<W>,
+    level: CompressionLevel,
+    buf: Vec<u8>,
+    out_buf: Vec<u8>,
+    // offset in the output of the block being filled
+    block_address: u64,
+    finished: bool,
+}
+
+impl<W: io::Write> Writer<W> {
+    /// Create a new `Writer` compressing each block at `level`.
+    pub fn new(writer: W, level: CompressionLevel) -> Self {
+        Self {
+            inner: Some(writer),
+            level,
+            buf: Vec::with_capacity(BGZF_BLOCK_DATA_LEN),
+            out_buf: vec![0u8; BGZF_MAX_BLOCK_SIZE],
+            block_address: 0,
+            finished: false,
+        }
+    }
+
+    /// Mutable reference to underlying writer, not advisable to modify during writing.
+    pub fn get_ref_mut(&mut self) -> &mut W {
+        self.inner.as_mut().unwrap()
+    }
+
+    // Reference to underlying writer
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    /// Virtual offset of the next byte written, for indexing
+    pub fn virtual_offset(&self) -> u64 {
+        self.block_address << 16 | self.buf.len() as u64
+    }
+
+    /// Write out what's left as a last block, then the EOF marker, without giving up the
+    /// inner writer. Does nothing if already finished.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.write_block()?;
+        self.get_ref_mut().write_all(&BGZF_EOF)?;
+        self.block_address += BGZF_EOF.len() as u64;
+        self.finished = true;
+        Ok(())
+    }
+
+    /// Write the EOF marker and return the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.inner.take().unwrap())
+    }
+
+    // Compress the buffered data as one block and write it out
+    fn write_block(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        // Header and trailer are fixed, so deflate straight in between; even stored,
+        // the data fits in a block
+        let opts = CompressOptions::new(self.level, Codec::Deflate).stored_fallback(true);
+        let body = &mut self.out_buf[BGZF_HEADER_LEN..BGZF_MAX_BLOCK_SIZE - GZIP_TRAILER_LEN];
+        let n = compress_into(&self.buf, body, opts)?;
+
+        let len = BGZF_HEADER_LEN + n + GZIP_TRAILER_LEN;
+        self.out_buf[..BGZF_HEADER.len()].copy_from_slice(&BGZF_HEADER);
+        self.out_buf[BGZF_HEADER.len()..BGZF_HEADER_LEN]
+            .copy_from_slice(&((len - 1) as u16).to_le_bytes());
+        let trailer = &mut self.out_buf[BGZF_HEADER_LEN + n..len];
+        trailer[..4].copy_from_slice(&crc32(&self.buf).to_le_bytes());
+        trailer[4..].copy_from_slice(&(self.buf.len() as u32).to_le_bytes());
+
+        self.inner
+            .as_mut()
+            .unwrap()
+            .write_all(&self.out_buf[..len])?;
+        self.block_address += len as u64;
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+impl<W: io::Write> io::Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.finished && !buf.is_empty() {
+            return Err(io::Error::other("write after the BGZF EOF marker"));
+        }
+        let n = std::cmp::min(buf.len(), BGZF_BLOCK_DATA_LEN - self.buf.len());
+        self.buf.extend_from_slice(&buf[..n]);
+        if self.buf.len() == BGZF_BLOCK_DATA_LEN {
+            self.write_block()?;
+        }
+        Ok(n)
+    }
+
+    /// End the current block, so all data given so far is written, and flush the
+    /// inner writer.
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_block()?;
+        self.get_ref_mut().flush()
+    }
+}
+
+impl<W: io::Write> Drop for Writer<W> {
+    fn drop(&mut self) {
+        // Errors can't be reported from here, call `finish` to see them
+        if self.inner.is_some() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+/// BGZF decompression for input streams implementing `std::io::Read`, a block at a
+/// time with `decompress_into`.
+///
+/// Implements `std::io::BufRead` over the current block. With an inner reader
+/// implementing `std::io::Seek`, `seek_virtual` moves to any virtual offset, such
+/// as those from `Writer::virtual_offset` or an index.
+///
+/// Example
+/// -------
+/// ```
+/// use std::io::{Read, Write};
+/// use isal::igzip::{bgzf, CompressionLevel};
+///
+/// let mut writer = bgzf::Writer::new(vec![], CompressionLevel::Three);
+/// writer.write_all(b"Hello, World!").unwrap();
+/// let compressed = writer.finish().unwrap();
+///
+/// let mut reader = bgzf::Reader::new(compressed.as_slice());
+/// let mut decompressed = vec![];
+/// reader.read_to_end(&mut decompressed).unwrap();
+/// assert_eq!(decompressed, b"Hello, World!");
+/// ```
+pub struct Reader<R: io::Read> {
+    inner: R,
+    in_buf: Vec<u8>,
+    block: Vec<u8>,
+    pos: usize,
+    // offset in the input of the current block, and of the next
+    block_address: u64,
+    next_address: u64,
+}
+
+impl<R: io::Read> Reader<R> {
+    /// Create a new `Reader`, the inner reader being at the start of a block.
+    pub fn new(reader: R) -> Self {
+        Self {
+            inner: reader,
+            in_buf: vec![0u8; BGZF_MAX_BLOCK_SIZE],
+            block: vec![],
+            pos: 0,
+            block_address: 0,
+            next_address: 0,
+        }
+    }
+
+    /// Mutable reference to underlying reader, not advisable to modify during reading.
+    pub fn get_ref_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    // Reference to underlying reader
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Give up the underlying reader
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Virtual offset of the next byte read
+    pub fn virtual_offset(&self) -> u64 {
+        // At the end of a block, that is the start of the next
+        match self.pos == self.block.len() {
+            true => self.next_address << 16,
+            false => self.block_address << 16 | self.pos as u64,
+        }
+    }
+
+    // Read and decompress the next block, returning false at the end of the input
+    fn read_block(&mut self) -> io::Result<bool> {
+        self.block_address = self.next_address;
+        self.block.clear();
+        self.pos = 0;
+
+        // Fixed part of the header, then the extra field holding BSIZE
+        let n = read_up_to(&mut self.inner, &mut self.in_buf[..GZIP_HEADER_LEN + 2])?;
+        match n {
+            0 => return Ok(false),
+            n if n < GZIP_HEADER_LEN + 2 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            _ => (),
+        }
+        if self.in_buf[3] & header::FEXTRA == 0 {
+            return Err(Error::DecompressionError(DecompCode::InvalidWrapper).into());
+        }
+        let xlen = u16::from_le_bytes([self.in_buf[10], self.in_buf[11]]) as usize;
+        let header_len = GZIP_HEADER_LEN + 2 + xlen;
+        // no room left in a block for the data and trailer
+        if header_len + GZIP_TRAILER_LEN > BGZF_MAX_BLOCK_SIZE {
+            return Err(Error::DecompressionError(DecompCode::InvalidWrapper).into());
+        }
+        self.inner
+            .read_exact(&mut self.in_buf[GZIP_HEADER_LEN + 2..header_len])?;
+        let size = match block_size(&self.in_buf[..header_len]) {
+            Some(size) if size >= header_len + GZIP_TRAILER_LEN => size,
+            _ => return Err(Error::DecompressionError(DecompCode::InvalidWrapper).into()),
+        };
+        self.inner.read_exact(&mut self.in_buf[header_len..size])?;
+        self.next_address = self.block_address + size as u64;
+
+        // Size the block from ISIZE, the checksum then confirms it
+        let input = &self.in_buf[..size];
+        let len = u32::from_le_bytes(input[size - 4..].try_into().unwrap()) as usize;
+        if len > BGZF_MAX_BLOCK_SIZE {
+            return Err(Error::DecompressionError(DecompCode::InvalidWrapper).into());
+        }
+        self.block.resize(len, 0);
+        let n = decompress_into(input, &mut self.block, Codec::Gzip)?;
+        if n != len {
+            return Err(Error::DecompressionError(DecompCode::IncorrectChecksum).into());
+        }
+        Ok(true)
+    }
+}
+
+impl<R: io::Read + io::Seek> Reader<R> {
+    /// Move to a virtual offset: the offset of a block in the input shifted up 16 bits,
+    /// or'ed with an offset into its decompressed data.
+    pub fn seek_virtual(&mut self, offset: u64) -> io::Result<()> {
+        let (address, pos) = (offset >> 16, (offset & 0xffff) as usize);
+        if address != self.block_address || self.block.is_empty() {
+            self.inner.seek(SeekFrom::Start(address))?;
+            self.next_address = address;
+            self.read_block()?;
+        }
+        if pos > self.block.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "virtual offset beyond the end of its block",
+            ));
+        }
+        self.pos = pos;
+        Ok(())
+    }
+}
+
+// Read until `buf` is full or the input ends, returning the number of bytes read
+fn read_up_to<R: io::Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match reader.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(read) => n += read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(n)
+}
+
+impl<R: io::Read> io::BufRead for Reader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // Empty blocks, like the EOF marker, are skipped
+        while self.pos == self.block.len() {
+            if !self.read_block()? {
+                break;
+            }
+        }
+        Ok(&self.block[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = std::cmp::min(self.pos + amt, self.block.len());
+    }
+}
+
+impl<R: io::Read> io::Read for Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        use io::BufRead;
+        let available = self.fill_buf()?;
+        let n = std::cmp::min(buf.len(), available.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::igzip::tests::{gen_large_data, same_same};
+    use std::io::{Cursor, Read, Write};
+
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let data = gen_large_data();
+        let mut writer = Writer::new(vec![], CompressionLevel::Three);
+        writer.write_all(&data)?;
+        let compressed = writer.finish()?;
+        assert!(compressed.ends_with(&BGZF_EOF));
+
+        let mut decompressed = vec![];
+        Reader::new(compressed.as_slice()).read_to_end(&mut decompressed)?;
+        assert!(same_same(&data, &decompressed));
+
+        // Any gzip decoder reads it as a multi-member file
+        assert!(same_same(
+            &data,
+            &decompress(compressed.as_slice(), Codec::Gzip)?
+        ));
+        let mut decompressed = vec![];
+        flate2::read::MultiGzDecoder::new(compressed.as_slice()).read_to_end(&mut decompressed)?;
+        assert!(same_same(&data, &decompressed));
+        Ok(())
+    }
+
+    #[test]
+    fn blocks() -> Result<()> {
+        // incompressible data, stored blocks still fit
+        let data: Vec<u8> = (0..BGZF_BLOCK_DATA_LEN * 3)
+            .map(|_| rand::random::<u8>())
+            .collect();
+        let mut writer = Writer::new(vec![], CompressionLevel::Three);
+        writer.write_all(&data)?;
+        let compressed = writer.finish()?;
+
+        let mut pos = 0;
+        let mut n_blocks = 0;
+        while pos < compressed.len() {
+            let size = block_size(&compressed[pos..]).unwrap();
+            assert!(size <= BGZF_MAX_BLOCK_SIZE);
+            pos += size;
+            n_blocks += 1;
+        }
+        assert_eq!(pos, compressed.len());
+        assert_eq!(n_blocks, 4);
+        assert_eq!(block_size(&BGZF_EOF), Some(BGZF_EOF.len()));
+
+        let mut decompressed = vec![];
+        Reader::new(compressed.as_slice()).read_to_end(&mut decompressed)?;
+        assert!(same_same(&data, &decompressed));
+        Ok(())
+    }
+
+    #[test]
+    fn virtual_offsets() -> Result<()> {
+        let data = gen_large_data();
+        let mut writer = Writer::new(vec![], CompressionLevel::One);
+        let mut offsets = vec![];
+        for (i, chunk) in data.chunks(10_000).enumerate() {
+            offsets.push((i * 10_000, writer.virtual_offset()));
+            writer.write_all(chunk)?;
+        }
+        let compressed = writer.finish()?;
+
+        let mut reader = Reader::new(Cursor::new(compressed));
+        for &(pos, offset) in offsets.iter().rev().step_by(7) {
+            reader.seek_virtual(offset)?;
+            let end = std::cmp::min(pos + 100, data.len());
+            let mut buf = vec![0u8; end - pos];
+            reader.read_exact(&mut buf)?;
+            assert_eq!(buf, &data[pos..end]);
+        }
+
+        // Reading back passes through the same offsets
+        reader.seek_virtual(0)?;
+        let mut pos = 0;
+        for &(next, offset) in &offsets {
+            let mut skip = vec![0u8; next - pos];
+            reader.read_exact(&mut skip)?;
+            assert_eq!(reader.virtual_offset(), offset);
+            pos = next;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn malformed() {
+        // extra field too long for any block
+        let mut input = vec![
+            0x1f,
+            0x8b,
+            8,
+            header::FEXTRA,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0xff,
+            0xff,
+            0xff,
+        ];
+        input.resize(80_000, 0);
+        assert!(Reader::new(input.as_slice())
+            .read_to_end(&mut vec![])
+            .is_err());
+
+        // BSIZE smaller than the header
+        let mut input = BGZF_EOF.to_vec();
+        input[16..18].copy_from_slice(&10u16.to_le_bytes());
+        assert!(Reader::new(input.as_slice())
+            .read_to_end(&mut vec![])
+            .is_err());
+    }
+}
@@ -0,0 +1,206 @@
+//! Walking the blocks of deflate data without producing any output, to find where
+//! they begin and end and how much each decompresses to.
+use std::io;
+
+/// A deflate block found by `walk_block`
+pub(crate) struct Block {
+    /// Offset in bits of its end, where the next block begins
+    pub end: u64,
+    /// Length of the data it decompresses to
+    pub len: u64,
+    /// BFINAL is set, it is the last of its stream
+    pub last: bool,
+}
+
+// Base lengths and extra bits of the length codes from 257, and extra bits of the
+// distance codes
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// Order the code length code lengths of a dynamic block come in
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+fn invalid_deflate(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Walk the block beginning `pos` bits into `data`, failing with
+/// `io::ErrorKind::UnexpectedEof` if it doesn't end within `data`.
+pub(crate) fn walk_block(data: &[u8], pos: u64) -> io::Result<Block> {
+    let mut reader = BitReader { data, pos };
+    let last = reader.bits(1)? == 1;
+    let len = match reader.bits(2)? {
+        0 => {
+            reader.skip((8 - reader.pos % 8) % 8)?;
+            let len = reader.bits(16)?;
+            if len != !reader.bits(16)? & 0xffff {
+                return Err(invalid_deflate("invalid stored block length"));
+            }
+            reader.skip(len as u64 * 8)?;
+            len as u64
+        }
+        1 => {
+            let mut lengths = [8u8; 288];
+            lengths[144..256].fill(9);
+            lengths[256..280].fill(7);
+            let (litlen, dist) = (Huffman::new(&lengths)?, Huffman::new(&[5; 30])?);
+            walk_codes(&mut reader, &litlen, &dist)?
+        }
+        2 => {
+            let (litlen, dist) = dynamic_codes(&mut reader)?;
+            walk_codes(&mut reader, &litlen, &dist)?
+        }
+        _ => return Err(invalid_deflate("invalid deflate block type")),
+    };
+    Ok(Block {
+        end: reader.pos,
+        len,
+        last,
+    })
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> io::Result<(Huffman, Huffman)> {
+    let hlit = reader.bits(5)? as usize + 257;
+    let hdist = reader.bits(5)? as usize + 1;
+    let hclen = reader.bits(4)? as usize + 4;
+    let mut code_lengths = [0u8; 19];
+    for &i in &CODE_LENGTH_ORDER[..hclen] {
+        code_lengths[i] = reader.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths)?;
+
+    let mut lengths = vec![];
+    while lengths.len() < hlit + hdist {
+        let (len, repeat) = match reader.decode(&code_lengths)? {
+            16 => match lengths.last() {
+                Some(&last) => (last, 3 + reader.bits(2)?),
+                None => return Err(invalid_deflate("repeat of no code length")),
+            },
+            17 => (0, 3 + reader.bits(3)?),
+            18 => (0, 11 + reader.bits(7)?),
+            len => (len as u8, 1),
+        };
+        lengths.extend(std::iter::repeat_n(len, repeat as usize));
+    }
+    if lengths.len() > hlit + hdist {
+        return Err(invalid_deflate("too many code lengths"));
+    }
+    let (litlen, dist) = lengths.split_at(hlit);
+    Ok((Huffman::new(litlen)?, Huffman::new(dist)?))
+}
+
+// Skip over the codes of a block up to its end of block code, adding up the length
+// of what they decompress to
+fn walk_codes(reader: &mut BitReader, litlen: &Huffman, dist: &Huffman) -> io::Result<u64> {
+    let mut len = 0u64;
+    loop {
+        match reader.decode(litlen)? {
+            0..=255 => len += 1,
+            256 => return Ok(len),
+            symbol => {
+                let code = symbol - 257;
+                let extra = *LENGTH_EXTRA
+                    .get(code)
+                    .ok_or_else(|| invalid_deflate("invalid length code"))?;
+                len += (LENGTH_BASE[code] as u32 + reader.bits(extra as u32)?) as u64;
+                let extra = DIST_EXTRA
+                    .get(reader.decode(dist)?)
+                    .ok_or_else(|| invalid_deflate("invalid distance code"))?;
+                reader.skip(*extra as u64)?;
+            }
+        }
+    }
+}
+
+// Deflate data read a few bits at a time, least significant first
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: u64,
+}
+
+impl BitReader<'_> {
+    fn remaining(&self) -> u64 {
+        (self.data.len() as u64 * 8).saturating_sub(self.pos)
+    }
+
+    // The next `n` bits, up to 32, zero past the end of the data
+    fn peek(&self, n: u32) -> u32 {
+        let start = (self.pos / 8) as usize;
+        let bytes = match self.data.get(start..start + 8) {
+            Some(bytes) => bytes.try_into().unwrap(),
+            None => {
+                let mut bytes = [0u8; 8];
+                let available = self.data.get(start..).unwrap_or_default();
+                bytes[..available.len()].copy_from_slice(available);
+                bytes
+            }
+        };
+        let bits = u64::from_le_bytes(bytes) >> (self.pos % 8);
+        (bits & ((1u64 << n) - 1)) as u32
+    }
+
+    fn skip(&mut self, n: u64) -> io::Result<()> {
+        if n > self.remaining() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "deflate data is cut short",
+            ));
+        }
+        self.pos += n;
+        Ok(())
+    }
+
+    fn bits(&mut self, n: u32) -> io::Result<u32> {
+        let bits = self.peek(n);
+        self.skip(n as u64)?;
+        Ok(bits)
+    }
+
+    fn decode(&mut self, code: &Huffman) -> io::Result<usize> {
+        let (symbol, len) = code.table[self.peek(code.max_len) as usize];
+        if len == 0 {
+            return Err(invalid_deflate("invalid Huffman code in deflate data"));
+        }
+        self.skip(len as u64)?;
+        Ok(symbol as usize)
+    }
+}
+
+// Canonical Huffman code, looked up by the next `max_len` bits: symbol and code length
+struct Huffman {
+    table: Vec<(u16, u8)>,
+    max_len: u32,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> io::Result<Self> {
+        let max_len = lengths.iter().copied().max().unwrap_or(0) as u32;
+        let mut table = vec![(0, 0); 1 << max_len];
+        let mut code = 0u32;
+        for len in 1..=max_len {
+            for (symbol, _) in lengths.iter().enumerate().filter(|(_, &l)| l as u32 == len) {
+                if code >> len != 0 {
+                    return Err(invalid_deflate("oversubscribed Huffman code"));
+                }
+                // codes are packed starting from their most significant bit
+                let reversed = code.reverse_bits() >> (32 - len);
+                for entry in table.iter_mut().skip(reversed as usize).step_by(1 << len) {
+                    *entry = (symbol as u16, len as u8);
+                }
+                code += 1;
+            }
+            code <<= 1;
+        }
+        Ok(Self { table, max_len })
+    }
+}
@@ -0,0 +1,384 @@
+//! Encoder and Decoder implementing `std::io::Read`, over readers implementing `std::io::BufRead`
+//!
+//! Input is taken straight from the reader's buffer, consuming exactly what was
+//! (de)compressed, so whatever follows the stream is left in the reader.
+use crate::igzip::poll::{ReadDecoder, ReadEncoder};
+use crate::igzip::*;
+use std::io;
+
+/// Streaming compression for input streams implementing `std::io::BufRead`.
+///
+/// Example
+/// -------
+/// ```
+/// use std::io;
+/// use isal::igzip::{bufread::Encoder, CompressOptions, CompressionLevel, decompress, Codec};
+/// let data = b"Hello, World!".to_vec();
+///
+/// let opts = CompressOptions::new(CompressionLevel::Three, Codec::Gzip);
+/// let mut encoder = Encoder::new(data.as_slice(), opts);
+/// let mut compressed = vec![];
+///
+/// // Number of compressed bytes written to `output`
+/// let n = io::copy(&mut encoder, &mut compressed).unwrap();
+/// assert_eq!(n as usize, compressed.len());
+///
+/// let decompressed = decompress(compressed.as_slice(), Codec::Gzip).unwrap();
+/// assert_eq!(decompressed.as_slice(), data);
+/// ```
+pub struct Encoder<R: io::BufRead> {
+    inner: R,
+    encoder: ReadEncoder,
+}
+
+impl<R: io::BufRead> Encoder<R> {
+    /// Create a new `Encoder` which implements the `std::io::Read` trait.
+    ///
+    /// Unless set in `options`, the flush mode defaults to `FlushFlags::NoFlush`.
+    /// With `FlushFlags::SyncFlush` or `FlushFlags::FullFlush`, every buffer filled by
+    /// the inner reader ends on a byte boundary, at the cost of compression ratio.
+    pub fn new(reader: R, options: CompressOptions) -> Encoder<R> {
+        Self {
+            inner: reader,
+            encoder: ReadEncoder::new(options),
+        }
+    }
+
+    /// Mutable reference to underlying reader, not advisable to modify during reading.
+    pub fn get_ref_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    // Reference to underlying reader
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Give up the underlying reader
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: io::BufRead> io::Read for Encoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.encoder.is_finished() {
+            return Ok(0);
+        }
+        loop {
+            let input = self.inner.fill_buf()?;
+            let (consumed, read) = self.encoder.step(input, buf)?;
+            self.inner.consume(consumed);
+            if let Some(n) = read {
+                return Ok(n);
+            }
+        }
+    }
+}
+
+/// Streaming decompression for input streams implementing `std::io::BufRead`.
+///
+/// Only the bytes making up the stream are consumed from the reader, with
+/// `DecompressOptions::multi_member(false)` or `TrailingData::Stop` anything
+/// after it is still there to be read once decompression is done.
+///
+/// Example
+/// -------
+/// ```
+/// use std::io::{self, Read};
+/// use isal::igzip::{bufread::Decoder, CompressOptions, DecompressOptions, compress, Codec};
+/// let data = b"Hello, World!".to_vec();
+///
+/// let mut input = compress(data.as_slice(), CompressOptions::default()).unwrap();
+/// input.extend(b"more data");
+///
+/// let opts = DecompressOptions::new(Codec::Gzip).multi_member(false);
+/// let mut decoder = Decoder::new(input.as_slice(), opts);
+/// let mut decompressed = vec![];
+/// io::copy(&mut decoder, &mut decompressed).unwrap();
+/// assert_eq!(decompressed.as_slice(), data);
+///
+/// let mut rest = vec![];
+/// decoder.into_inner().read_to_end(&mut rest).unwrap();
+/// assert_eq!(rest, b"more data");
+/// ```
+pub struct Decoder<R: io::BufRead> {
+    inner: R,
+    decoder: ReadDecoder,
+}
+
+impl<R: io::BufRead> Decoder<R> {
+    /// Create a new `Decoder`, accepting either a `Codec` or `DecompressOptions`.
+    pub fn new(reader: R, options: impl Into<DecompressOptions>) -> Decoder<R> {
+        Self {
+            inner: reader,
+            decoder: ReadDecoder::new(options),
+        }
+    }
+
+    /// Mutable reference to underlying reader, not advisable to modify during reading.
+    pub fn get_ref_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    // Reference to underlying reader
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Give up the underlying reader, positioned just past what was decompressed
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Header of the current gzip member, `None` until it has been read or
+    /// if the codec isn't `Codec::Gzip`
+    pub fn header(&self) -> Option<&GzipHeader> {
+        self.decoder.decomp().header()
+    }
+
+    /// Header of the current zlib stream, `None` until it has been read or
+    /// if the codec isn't `Codec::Zlib`
+    pub fn zlib_header(&self) -> Option<&ZlibHeader> {
+        self.decoder.decomp().zlib_header()
+    }
+
+    /// Trailers which didn't match the data under `ChecksumPolicy::Report`, across all members
+    pub fn checksum_mismatches(&self) -> &[ChecksumMismatch] {
+        self.decoder.decomp().checksum_mismatches()
+    }
+
+    /// Offset in the input just past the last gzip member (or zlib/deflate stream)
+    /// to end, `None` until one has.
+    pub fn member_end(&self) -> Option<u64> {
+        self.decoder.decomp().member_end()
+    }
+}
+
+impl<R: io::BufRead> io::Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let input = self.inner.fill_buf()?;
+            let (consumed, read) = self.decoder.step(input, buf)?;
+            self.inner.consume(consumed);
+            if let Some(n) = read {
+                return Ok(n);
+            }
+        }
+    }
+}
+
+/// Deflate compression
+/// Basically a wrapper to `Encoder` which sets the codec for you.
+pub struct DeflateEncoder<R: io::BufRead> {
+    inner: Encoder<R>,
+}
+
+impl<R: io::BufRead> DeflateEncoder<R> {
+    pub fn new(reader: R, level: CompressionLevel) -> Self {
+        Self {
+            inner: Encoder::new(reader, CompressOptions::new(level, Codec::Deflate)),
+        }
+    }
+}
+
+impl<R: io::BufRead> io::Read for DeflateEncoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+/// Deflate decompression
+/// Basically a wrapper to `Decoder` which sets the codec for you.
+pub struct DeflateDecoder<R: io::BufRead> {
+    inner: Decoder<R>,
+}
+
+impl<R: io::BufRead> DeflateDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            inner: Decoder::new(reader, Codec::Deflate),
+        }
+    }
+}
+
+impl<R: io::BufRead> io::Read for DeflateDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+/// Zlib compression
+/// Basically a wrapper to `Encoder` which sets the codec for you.
+pub struct ZlibEncoder<R: io::BufRead> {
+    inner: Encoder<R>,
+}
+
+impl<R: io::BufRead> ZlibEncoder<R> {
+    pub fn new(reader: R, level: CompressionLevel) -> Self {
+        Self {
+            inner: Encoder::new(reader, CompressOptions::new(level, Codec::Zlib)),
+        }
+    }
+}
+
+impl<R: io::BufRead> io::Read for ZlibEncoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+/// Zlib decompression
+/// Basically a wrapper to `Decoder` which sets the codec for you.
+pub struct ZlibDecoder<R: io::BufRead> {
+    inner: Decoder<R>,
+}
+
+impl<R: io::BufRead> ZlibDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            inner: Decoder::new(reader, Codec::Zlib),
+        }
+    }
+}
+
+impl<R: io::BufRead> io::Read for ZlibDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+/// Gzip compression
+/// Basically a wrapper to `Encoder` which sets the codec for you.
+pub struct GzipEncoder<R: io::BufRead> {
+    inner: Encoder<R>,
+}
+
+impl<R: io::BufRead> GzipEncoder<R> {
+    pub fn new(reader: R, level: CompressionLevel) -> Self {
+        Self {
+            inner: Encoder::new(reader, CompressOptions::new(level, Codec::Gzip)),
+        }
+    }
+}
+
+impl<R: io::BufRead> io::Read for GzipEncoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+/// Gzip decompression
+/// Basically a wrapper to `Decoder` which sets the codec for you.
+pub struct GzipDecoder<R: io::BufRead> {
+    inner: Decoder<R>,
+}
+
+impl<R: io::BufRead> GzipDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            inner: Decoder::new(reader, Codec::Gzip),
+        }
+    }
+}
+
+impl<R: io::BufRead> io::Read for GzipDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::igzip::tests::{gen_large_data, same_same};
+    use std::io::{self, BufRead, BufReader, Read};
+
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let data = gen_large_data();
+        for codec in [Codec::Gzip, Codec::Zlib, Codec::Deflate] {
+            let opts = CompressOptions::new(CompressionLevel::Three, codec);
+            let mut encoder = Encoder::new(BufReader::with_capacity(1000, data.as_slice()), opts);
+            let mut compressed = vec![];
+            io::copy(&mut encoder, &mut compressed)?;
+            assert!(same_same(&data, &decompress(compressed.as_slice(), codec)?));
+
+            let reader = BufReader::with_capacity(7, compressed.as_slice());
+            let mut decoder = Decoder::new(reader, codec);
+            let mut decompressed = vec![];
+            io::copy(&mut decoder, &mut decompressed)?;
+            assert!(same_same(&data, &decompressed));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn stops_at_stream_end() -> Result<()> {
+        // a container of compressed sections, each followed by a length-prefixed record
+        let data = gen_large_data();
+        let mut container = vec![];
+        for codec in [Codec::Gzip, Codec::Zlib, Codec::Deflate] {
+            let opts = CompressOptions::new(CompressionLevel::One, codec);
+            container.extend(compress(data.as_slice(), opts)?);
+            container.extend([3, b'e', b'n', b'd']);
+        }
+
+        let mut reader = BufReader::with_capacity(BUF_SIZE, container.as_slice());
+        for codec in [Codec::Gzip, Codec::Zlib, Codec::Deflate] {
+            let opts = DecompressOptions::new(codec).multi_member(false);
+            let mut decoder = Decoder::new(&mut reader, opts);
+            let mut decompressed = vec![];
+            io::copy(&mut decoder, &mut decompressed)?;
+            assert!(same_same(&data, &decompressed));
+
+            let mut record = [0u8; 4];
+            reader.read_exact(&mut record)?;
+            assert_eq!(&record, &[3, b'e', b'n', b'd']);
+        }
+        assert!(reader.fill_buf()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn multi_member_trailing_data() -> Result<()> {
+        let mut compressed = compress(b"foo".as_slice(), CompressOptions::default())?;
+        compressed.extend(compress(b"bar".as_slice(), CompressOptions::default())?);
+        compressed.extend(b"trailer");
+
+        let opts = DecompressOptions::new(Codec::Gzip).trailing_data(TrailingData::Stop);
+        let mut decoder = Decoder::new(compressed.as_slice(), opts);
+        let mut decompressed = vec![];
+        io::copy(&mut decoder, &mut decompressed)?;
+        assert_eq!(decompressed, b"foobar");
+        assert_eq!(decoder.into_inner(), b"trailer");
+
+        let mut decoder = GzipDecoder::new(compressed.as_slice());
+        assert!(io::copy(&mut decoder, &mut vec![]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn flate2_compat() -> Result<()> {
+        let data = gen_large_data();
+        let mut encoder = GzipEncoder::new(data.as_slice(), CompressionLevel::Three);
+        let mut compressed = vec![];
+        encoder.read_to_end(&mut compressed)?;
+
+        let mut decompressed = vec![];
+        flate2::read::GzDecoder::new(compressed.as_slice()).read_to_end(&mut decompressed)?;
+        assert!(same_same(&data, &decompressed));
+
+        let mut compressed = vec![];
+        flate2::bufread::ZlibEncoder::new(data.as_slice(), flate2::Compression::fast())
+            .read_to_end(&mut compressed)?;
+        let mut decompressed = vec![];
+        ZlibDecoder::new(compressed.as_slice()).read_to_end(&mut decompressed)?;
+        assert!(same_same(&data, &decompressed));
+        Ok(())
+    }
+}
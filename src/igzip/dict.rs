@@ -0,0 +1,187 @@
+//! Compression dictionaries processed once and shared between streams
+use crate::igzip::*;
+use std::fmt;
+
+/// A compression dictionary hashed ahead of time, cheap to apply to new streams.
+///
+/// Setting a raw dictionary hashes it on every new stream, which adds up when
+/// compressing many short streams. `ProcessedDict` does that work once; it is
+/// immutable and can be shared between threads behind an `Arc`.
+///
+/// The dictionary is processed for one `CompressionLevel`, applying it to a stream
+/// of another level falls back to setting the raw dictionary.
+///
+/// Example
+/// -------
+/// ```
+/// use std::sync::Arc;
+/// use isal::igzip::{ProcessedDict, CompressOptions, DecompressOptions, CompressionLevel, Codec, compress, decompress};
+///
+/// let dictionary = b"{\"id\": , \"status\": \"ok\"}".to_vec();
+/// let processed = Arc::new(ProcessedDict::new(&dictionary, CompressionLevel::Three).unwrap());
+///
+/// for id in 0..3 {
+///     let data = format!("{{\"id\": {}, \"status\": \"ok\"}}", id).into_bytes();
+///     let opts = CompressOptions::new(CompressionLevel::Three, Codec::Deflate).processed_dict(processed.clone());
+///     let compressed = compress(data.as_slice(), opts).unwrap();
+///
+///     let opts = DecompressOptions::new(Codec::Deflate).dictionary(dictionary.clone());
+///     let decompressed = decompress(compressed.as_slice(), opts).unwrap();
+///     assert_eq!(decompressed, data);
+/// }
+/// ```
+#[derive(Clone)]
+pub struct ProcessedDict {
+    dict: Box<isal::isal_dict>,
+    level: CompressionLevel,
+    id: u32,
+}
+
+impl fmt::Debug for ProcessedDict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProcessedDict")
+            .field("level", &self.level)
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ProcessedDict {
+    /// Process `dictionary` for compression at `level`, only the last 32KiB of which will be used.
+    pub fn new(dictionary: &[u8], level: CompressionLevel) -> Result<Self> {
+        // process_dict only reads the level from the stream
+        let mut zstream =
+            ZStream::with_level_buf_size(level, LevelBufSize::Min, ZStreamKind::Stateful);
+        let mut dict: Box<isal::isal_dict> = Box::new(unsafe { mem::zeroed() });
+        let ret = unsafe {
+            isal::isal_deflate_process_dict(
+                &mut zstream.stream,
+                dict.as_mut(),
+                dictionary.as_ptr() as *mut _,
+                dictionary.len() as _,
+            )
+        };
+        match CompCode::try_from(ret)? {
+            CompCode::CompOk => Ok(Self {
+                dict,
+                level,
+                id: adler32(dictionary),
+            }),
+            r => Err(Error::CompressionError(r)),
+        }
+    }
+
+    /// The compression level this dictionary was processed for
+    pub fn level(&self) -> CompressionLevel {
+        self.level
+    }
+
+    /// Adler32 of the full dictionary, as announced in zlib headers
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// The portion of the dictionary that is used
+    pub fn history(&self) -> &[u8] {
+        &self.dict.history[..self.dict.hist_size as usize]
+    }
+
+    // isa-l only reads from it, but takes a mut pointer
+    #[inline]
+    pub(crate) fn as_ptr(&self) -> *mut isal::isal_dict {
+        self.dict.as_ref() as *const _ as *mut _
+    }
+}
+
+/// Dictionary attached to a compression stream, re-applied on every reset
+#[derive(Clone, Debug)]
+pub(crate) enum Dictionary {
+    Raw(Arc<[u8]>),
+    Processed(Arc<ProcessedDict>),
+}
+
+impl Dictionary {
+    /// Adler32 id for the zlib header
+    pub fn id(&self) -> u32 {
+        match self {
+            Self::Raw(dictionary) => adler32(dictionary),
+            Self::Processed(dictionary) => dictionary.id(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::igzip::tests::{dictionary, gen_large_data, same_same};
+    use std::io::{self, Cursor};
+    use std::thread;
+
+    #[test]
+    fn processed_dict_other_level() -> Result<()> {
+        let data = gen_large_data();
+        let processed = ProcessedDict::new(&dictionary(), CompressionLevel::One)?;
+
+        let opts =
+            CompressOptions::new(CompressionLevel::Three, Codec::Gzip).processed_dict(processed);
+        let compressed = compress(Cursor::new(&data), opts)?;
+
+        let opts = DecompressOptions::new(Codec::Gzip).dictionary(dictionary());
+        let decompressed = decompress(compressed.as_slice(), opts)?;
+        assert!(same_same(&data, &decompressed));
+        Ok(())
+    }
+
+    #[test]
+    fn processed_dict_shared_across_threads() -> Result<()> {
+        let processed = Arc::new(ProcessedDict::new(&dictionary(), CompressionLevel::Three)?);
+        assert_eq!(processed.history(), dictionary().as_slice());
+
+        let handles = (0..4)
+            .map(|i| {
+                let processed = processed.clone();
+                thread::spawn(move || -> Result<()> {
+                    let data = format!("{{\"id\": {}, \"temp\": 21.{}, \"status\": \"ok\"}}", i, i);
+                    let mut compressed = vec![];
+                    let opts = CompressOptions::new(CompressionLevel::Three, Codec::Zlib)
+                        .processed_dict(processed);
+                    let mut encoder = write::Encoder::new(&mut compressed, opts);
+                    io::copy(&mut data.as_bytes(), &mut encoder)?;
+                    encoder.finish()?;
+
+                    let opts = DecompressOptions::new(Codec::Zlib).dictionary(dictionary());
+                    let decompressed = decompress(compressed.as_slice(), opts)?;
+                    assert_eq!(decompressed, data.as_bytes());
+                    Ok(())
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap()?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn zstream_set_processed_dict() -> Result<()> {
+        let data = b"{\"id\": 42, \"temp\": 21.5, \"status\": \"ok\"}";
+        let processed = ProcessedDict::new(&dictionary(), CompressionLevel::Three)?;
+
+        let mut zst = ZStream::new(CompressionLevel::Three, ZStreamKind::Stateful);
+        zst.set_processed_dict(&processed)?;
+
+        let mut compressed = vec![0u8; 256];
+        zst.stream.end_of_stream = 1;
+        zst.stream.next_in = data.as_ptr() as *mut _;
+        zst.stream.avail_in = data.len() as _;
+        zst.stream.next_out = compressed.as_mut_ptr();
+        zst.stream.avail_out = compressed.len() as _;
+        zst.deflate()?;
+        compressed.truncate(zst.stream.total_out as usize);
+
+        let opts = DecompressOptions::new(Codec::Deflate).dictionary(dictionary());
+        let decompressed = decompress(compressed.as_slice(), opts)?;
+        assert_eq!(data, decompressed.as_slice());
+        Ok(())
+    }
+}
@@ -0,0 +1,8 @@
+//! Encoders and Decoders implementing futures-io's `AsyncRead` and `AsyncWrite`,
+//! enabled with the `futures-io` feature.
+//!
+//! Built on the same `Compress`/`Decompress` streams as the blocking ones, output not
+//! yet taken by an inner writer returning `Poll::Pending` is kept for the next poll.
+//! Inner readers and writers must be `Unpin`, pin them with `Box::pin` otherwise.
+pub mod bufread;
+pub mod write;
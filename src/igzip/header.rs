@@ -0,0 +1,775 @@
+//! Gzip member and zlib stream headers
+use crate::igzip::*;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Operating system byte meaning 'unknown', as written in isa-l's generic header
+const OS_UNKNOWN: u8 = 255;
+
+// Header flag bits (FLG)
+pub(crate) const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+
+// zlib FLG bit announcing a preset dictionary
+const FDICT: u8 = 0x20;
+
+// Starting size of name, comment and extra buffers when parsing, grown as needed
+const PARSE_BUF_SIZE: usize = 256;
+
+/// A gzip member header, with optional file name, comment, modification time and extra field.
+///
+/// Attach to compression with `CompressOptions::gzip_header`; without one a generic
+/// header is written. Leaving the modification time unset (zero) keeps the output
+/// byte-reproducible.
+///
+/// When decompressing, the header of the current member is available from the
+/// Decoders' `header()`, or can be read directly with `GzipHeader::parse`.
+///
+/// Example
+/// -------
+/// ```
+/// use std::time::{Duration, UNIX_EPOCH};
+/// use isal::igzip::{GzipHeader, CompressOptions, CompressionLevel, Codec, compress, decompress};
+///
+/// let header = GzipHeader::new()
+///     .name("hello.txt")
+///     .comment("greetings")
+///     .mtime(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+///
+/// let opts = CompressOptions::new(CompressionLevel::Three, Codec::Gzip).gzip_header(header);
+/// let compressed = compress(b"Hello, World!".as_slice(), opts).unwrap();
+///
+/// let decompressed = decompress(compressed.as_slice(), Codec::Gzip).unwrap();
+/// assert_eq!(decompressed.as_slice(), b"Hello, World!");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GzipHeader {
+    text: bool,
+    mtime: u32,
+    xflags: u8,
+    os: u8,
+    extra: Option<Vec<u8>>,
+    name: Option<Vec<u8>>,
+    comment: Option<Vec<u8>>,
+    header_crc: bool,
+}
+
+impl Default for GzipHeader {
+    fn default() -> Self {
+        Self {
+            text: false,
+            mtime: 0,
+            xflags: 0,
+            os: OS_UNKNOWN,
+            extra: None,
+            name: None,
+            comment: None,
+            header_crc: false,
+        }
+    }
+}
+
+impl GzipHeader {
+    /// Create a header with no optional fields, equivalent to the generic header
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hint that the content is probably text (FTEXT)
+    pub fn text(mut self, text: bool) -> Self {
+        self.text = text;
+        self
+    }
+
+    /// Set the modification time; times before the Unix epoch or beyond what fits
+    /// in 32 bits are recorded as zero, meaning no time stamp.
+    pub fn mtime(mut self, mtime: SystemTime) -> Self {
+        self.mtime = mtime
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .and_then(|d| u32::try_from(d.as_secs()).ok())
+            .unwrap_or(0);
+        self
+    }
+
+    /// Set the modification time in seconds since the Unix epoch
+    pub fn mtime_unix(mut self, mtime: u32) -> Self {
+        self.mtime = mtime;
+        self
+    }
+
+    /// Set the extra flags byte (XFL)
+    pub fn xflags(mut self, xflags: u8) -> Self {
+        self.xflags = xflags;
+        self
+    }
+
+    /// Set the operating system byte, 255 (unknown) by default
+    pub fn os(mut self, os: u8) -> Self {
+        self.os = os;
+        self
+    }
+
+    /// Set the extra field (FEXTRA), at most 65535 bytes are kept.
+    pub fn extra(mut self, extra: impl Into<Vec<u8>>) -> Self {
+        let mut extra = extra.into();
+        extra.truncate(u16::MAX as usize);
+        self.extra = Some(extra);
+        self
+    }
+
+    /// Set the original file name (FNAME), ISO 8859-1 by convention.
+    /// The name ends at the first NUL byte, if any.
+    pub fn name(mut self, name: impl Into<Vec<u8>>) -> Self {
+        self.name = Some(until_nul(name.into()));
+        self
+    }
+
+    /// Set the comment (FCOMMENT), ISO 8859-1 by convention.
+    /// The comment ends at the first NUL byte, if any.
+    pub fn comment(mut self, comment: impl Into<Vec<u8>>) -> Self {
+        self.comment = Some(until_nul(comment.into()));
+        self
+    }
+
+    /// Protect the header with a CRC16 (FHCRC)
+    pub fn header_crc(mut self, header_crc: bool) -> Self {
+        self.header_crc = header_crc;
+        self
+    }
+
+    /// If the content is flagged as probably text
+    pub fn is_text(&self) -> bool {
+        self.text
+    }
+
+    /// Modification time, `None` if there is no time stamp
+    pub fn get_mtime(&self) -> Option<SystemTime> {
+        match self.mtime {
+            0 => None,
+            secs => Some(UNIX_EPOCH + Duration::from_secs(secs as u64)),
+        }
+    }
+
+    /// Modification time in seconds since the Unix epoch, zero meaning no time stamp
+    pub fn get_mtime_unix(&self) -> u32 {
+        self.mtime
+    }
+
+    /// Extra flags byte (XFL)
+    pub fn get_xflags(&self) -> u8 {
+        self.xflags
+    }
+
+    /// Operating system byte
+    pub fn get_os(&self) -> u8 {
+        self.os
+    }
+
+    /// Extra field, if present
+    pub fn get_extra(&self) -> Option<&[u8]> {
+        self.extra.as_deref()
+    }
+
+    /// Original file name without its terminating NUL, if present
+    pub fn get_name(&self) -> Option<&[u8]> {
+        self.name.as_deref()
+    }
+
+    /// Comment without its terminating NUL, if present
+    pub fn get_comment(&self) -> Option<&[u8]> {
+        self.comment.as_deref()
+    }
+
+    /// If the header is protected by a CRC16
+    pub fn has_header_crc(&self) -> bool {
+        self.header_crc
+    }
+
+    /// Parse the gzip header at the start of `data`, returning it along with its length
+    /// in bytes; the compressed data of the member follows.
+    ///
+    /// Example
+    /// -------
+    /// ```
+    /// use isal::igzip::{GzipHeader, CompressOptions, CompressionLevel, Codec, compress};
+    ///
+    /// let opts = CompressOptions::new(CompressionLevel::Three, Codec::Gzip)
+    ///     .gzip_header(GzipHeader::new().name("report.csv"));
+    /// let compressed = compress(b"a,b,c".as_slice(), opts).unwrap();
+    ///
+    /// let (header, len) = GzipHeader::parse(&compressed).unwrap();
+    /// assert_eq!(header.get_name(), Some(b"report.csv".as_slice()));
+    /// assert_eq!(len, 10 + b"report.csv\0".len());
+    /// ```
+    pub fn parse(data: &[u8]) -> Result<(Self, usize)> {
+        match Self::parse_partial(data)? {
+            Some(parsed) => Ok(parsed),
+            None => Err(Error::DecompressionError(DecompCode::EndInput)),
+        }
+    }
+
+    /// Like `GzipHeader::parse`, but `None` if `data` ends before the header does
+    pub(crate) fn parse_partial(data: &[u8]) -> Result<Option<(Self, usize)>> {
+        let mut name = vec![0u8; PARSE_BUF_SIZE];
+        let mut comment = vec![0u8; PARSE_BUF_SIZE];
+        let mut extra = vec![0u8; PARSE_BUF_SIZE];
+        let mut zst = Box::new(InflateState::new());
+
+        loop {
+            let mut hdr: mem::MaybeUninit<isal::isal_gzip_header> = mem::MaybeUninit::uninit();
+            unsafe { isal::isal_gzip_header_init(hdr.as_mut_ptr()) };
+            let mut hdr = unsafe { hdr.assume_init() };
+            hdr.name = name.as_mut_ptr() as *mut _;
+            hdr.name_buf_len = name.len() as _;
+            hdr.comment = comment.as_mut_ptr() as *mut _;
+            hdr.comment_buf_len = comment.len() as _;
+            hdr.extra = extra.as_mut_ptr();
+            hdr.extra_buf_len = extra.len() as _;
+
+            zst.reset();
+            zst.0.next_in = data.as_ptr() as *mut _;
+            zst.0.avail_in = data.len() as _;
+
+            // On overflow, start over with a bigger buffer; fields can't be larger than `data`
+            let ret = unsafe { isal::isal_read_gzip_header(&mut zst.0, &mut hdr) };
+            match DecompCode::try_from(ret)? {
+                DecompCode::DecompOk => {
+                    let flags = data[3];
+                    let header = Self {
+                        text: hdr.text != 0,
+                        mtime: hdr.time,
+                        xflags: hdr.xflags as _,
+                        os: hdr.os as _,
+                        extra: (flags & FEXTRA != 0)
+                            .then(|| extra[..hdr.extra_len as usize].to_vec()),
+                        name: (flags & FNAME != 0).then(|| until_nul(name)),
+                        comment: (flags & FCOMMENT != 0).then(|| until_nul(comment)),
+                        header_crc: hdr.hcrc != 0,
+                    };
+                    let consumed = data.len() - zst.0.avail_in as usize;
+                    return Ok(Some((header, consumed)));
+                }
+                DecompCode::EndInput => return Ok(None),
+                DecompCode::NameOverflow => grow(&mut name),
+                DecompCode::CommentOverflow => grow(&mut comment),
+                DecompCode::ExtraOverflow => grow(&mut extra),
+                r => return Err(Error::DecompressionError(r)),
+            }
+        }
+    }
+
+    /// The header as it would be written to the stream, e.g. ahead of `Codec::GzipNoHdr` output
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut zstream = ZStream::new(CompressionLevel::Zero, ZStreamKind::Stateful);
+        self.write_to(&mut zstream)
+    }
+
+    /// Write the header with `isal_write_gzip_header`, via the stream's output buffer
+    pub(crate) fn write_to(&self, zstream: &mut ZStream) -> Vec<u8> {
+        let mut hdr: mem::MaybeUninit<isal::isal_gzip_header> = mem::MaybeUninit::uninit();
+        unsafe { isal::isal_gzip_header_init(hdr.as_mut_ptr()) };
+        let mut hdr = unsafe { hdr.assume_init() };
+
+        hdr.text = self.text as _;
+        hdr.time = self.mtime;
+        hdr.xflags = self.xflags as _;
+        hdr.os = self.os as _;
+        hdr.hcrc = self.header_crc as _;
+
+        // Kept alive until the header is written; name and comment as C strings
+        let mut extra = self.extra.clone();
+        let mut name = self.name.clone().map(with_nul);
+        let mut comment = self.comment.clone().map(with_nul);
+        if let Some(extra) = &mut extra {
+            hdr.extra = extra.as_mut_ptr();
+            hdr.extra_buf_len = extra.len() as _;
+            hdr.extra_len = extra.len() as _;
+        }
+        if let Some(name) = &mut name {
+            hdr.name = name.as_mut_ptr() as *mut _;
+            hdr.name_buf_len = name.len() as _;
+        }
+        if let Some(comment) = &mut comment {
+            hdr.comment = comment.as_mut_ptr() as *mut _;
+            hdr.comment_buf_len = comment.len() as _;
+        }
+
+        zstream.header_bytes(|stream| unsafe { isal::isal_write_gzip_header(stream, &mut hdr) })
+    }
+}
+
+/// A zlib stream header: window size, compression level hint and preset dictionary id.
+///
+/// Attach to compression with `CompressOptions::zlib_header`; without one (and without
+/// a dictionary or smaller window) isa-l's generic header is written. Decoders expose
+/// the header read with `zlib_header()`, or it can be read directly with `ZlibHeader::parse`.
+///
+/// Example
+/// -------
+/// ```
+/// use isal::igzip::{ZlibHeader, CompressOptions, CompressionLevel, Codec, compress, decompress};
+///
+/// let opts = CompressOptions::new(CompressionLevel::Three, Codec::Zlib)
+///     .zlib_header(ZlibHeader::new().level(2));
+/// let compressed = compress(b"foobar".as_slice(), opts).unwrap();
+/// assert_eq!(&compressed[..2], &[0x78, 0x9c]);
+///
+/// let (header, len) = ZlibHeader::parse(&compressed).unwrap();
+/// assert_eq!((header.get_window_bits(), header.get_level(), len), (15, 2, 2));
+/// assert_eq!(decompress(compressed.as_slice(), Codec::Zlib).unwrap(), b"foobar");
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ZlibHeader {
+    window_bits: u8,
+    level: u8,
+    dict_id: Option<u32>,
+}
+
+impl Default for ZlibHeader {
+    fn default() -> Self {
+        Self {
+            window_bits: MAX_WINDOW_BITS,
+            level: 0,
+            dict_id: None,
+        }
+    }
+}
+
+impl ZlibHeader {
+    /// Create a header for a 32KiB window, fastest level and no dictionary; the generic header.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the window size (CINFO) as `2^window_bits` bytes, clamped to `8..=15`.
+    pub fn window_bits(mut self, window_bits: u8) -> Self {
+        self.window_bits = window_bits.clamp(MIN_WINDOW_BITS, MAX_WINDOW_BITS);
+        self
+    }
+
+    /// Set the compression level hint (FLEVEL), from 0 (fastest) to 3 (best), clamped to 3.
+    pub fn level(mut self, level: u8) -> Self {
+        self.level = level.min(3);
+        self
+    }
+
+    /// Announce a preset dictionary (FDICT) by its Adler32 id
+    pub fn dict_id(mut self, dict_id: u32) -> Self {
+        self.dict_id = Some(dict_id);
+        self
+    }
+
+    /// Window size as log base 2
+    pub fn get_window_bits(&self) -> u8 {
+        self.window_bits
+    }
+
+    /// Compression level hint
+    pub fn get_level(&self) -> u8 {
+        self.level
+    }
+
+    /// Adler32 id of the preset dictionary, if one is needed
+    pub fn get_dict_id(&self) -> Option<u32> {
+        self.dict_id
+    }
+
+    /// Parse the zlib header at the start of `data`, returning it along with its length
+    /// in bytes, 2 or 6 if a dictionary id is present.
+    pub fn parse(data: &[u8]) -> Result<(Self, usize)> {
+        match Self::parse_partial(data)? {
+            Some(parsed) => Ok(parsed),
+            None => Err(Error::DecompressionError(DecompCode::EndInput)),
+        }
+    }
+
+    /// Like `ZlibHeader::parse`, but `None` if `data` ends before the header does
+    pub(crate) fn parse_partial(data: &[u8]) -> Result<Option<(Self, usize)>> {
+        if data.len() < 2 {
+            return Ok(None);
+        }
+        let (cmf, flg) = (data[0], data[1]);
+        if !u16::from_be_bytes([cmf, flg]).is_multiple_of(31) || cmf >> 4 > 7 {
+            return Err(Error::DecompressionError(DecompCode::InvalidWrapper));
+        }
+        if cmf & 0x0f != 8 {
+            return Err(Error::DecompressionError(DecompCode::UnsupportedMethod));
+        }
+
+        let mut header = Self {
+            window_bits: (cmf >> 4) + MIN_WINDOW_BITS,
+            level: flg >> 6,
+            dict_id: None,
+        };
+        if flg & FDICT == 0 {
+            return Ok(Some((header, 2)));
+        }
+        match data.get(2..6) {
+            Some(id) => {
+                header.dict_id = Some(u32::from_be_bytes(id.try_into().unwrap()));
+                Ok(Some((header, 6)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// The header as it would be written to the stream
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut zstream = ZStream::new(CompressionLevel::Zero, ZStreamKind::Stateful);
+        self.write_to(&mut zstream)
+    }
+
+    /// Write the header with `isal_write_zlib_header`, via the stream's output buffer
+    pub(crate) fn write_to(&self, zstream: &mut ZStream) -> Vec<u8> {
+        let mut hdr: mem::MaybeUninit<isal::isal_zlib_header> = mem::MaybeUninit::uninit();
+        unsafe { isal::isal_zlib_header_init(hdr.as_mut_ptr()) };
+        let mut hdr = unsafe { hdr.assume_init() };
+
+        hdr.info = (self.window_bits - MIN_WINDOW_BITS) as _;
+        hdr.level = self.level as _;
+        if let Some(dict_id) = self.dict_id {
+            hdr.dict_flag = 1;
+            hdr.dict_id = dict_id;
+        }
+        zstream.header_bytes(|stream| unsafe { isal::isal_write_zlib_header(stream, &mut hdr) })
+    }
+}
+
+fn grow(buf: &mut Vec<u8>) {
+    buf.resize(buf.len() * 2, 0);
+}
+
+fn until_nul(mut bytes: Vec<u8>) -> Vec<u8> {
+    if let Some(pos) = bytes.iter().position(|b| *b == 0) {
+        bytes.truncate(pos);
+    }
+    bytes
+}
+
+fn with_nul(mut bytes: Vec<u8>) -> Vec<u8> {
+    bytes.push(0);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::igzip::tests::{gen_large_data, same_same};
+    use std::io::{self, Cursor, Read, Write};
+
+    fn header() -> GzipHeader {
+        GzipHeader::new()
+            .name("data.json")
+            .comment("nightly export")
+            .mtime(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+            .os(3)
+            .xflags(4)
+            .extra(b"AB\x02\x00hi".to_vec())
+            .header_crc(true)
+    }
+
+    fn check_flate2(compressed: &[u8], data: &[u8]) {
+        let mut decoder = flate2::read::GzDecoder::new(compressed);
+        let mut decompressed = vec![];
+        io::copy(&mut decoder, &mut decompressed).unwrap();
+        assert!(same_same(data, &decompressed));
+
+        let hdr = decoder.header().unwrap();
+        assert_eq!(hdr.filename(), Some(b"data.json".as_slice()));
+        assert_eq!(hdr.comment(), Some(b"nightly export".as_slice()));
+        assert_eq!(hdr.mtime(), 1_700_000_000);
+        assert_eq!(hdr.operating_system(), 3);
+        assert_eq!(hdr.extra(), Some(b"AB\x02\x00hi".as_slice()));
+    }
+
+    #[test]
+    fn compress_into_header() -> Result<()> {
+        let data = gen_large_data();
+        let mut compressed = vec![0u8; data.len()];
+        let opts = CompressOptions::default().gzip_header(header());
+        let n = compress_into(&data, &mut compressed, opts)?;
+
+        // FHCRC, FEXTRA, FNAME and FCOMMENT flags
+        assert_eq!(compressed[3], 0x1e);
+        assert_eq!(compressed[8], 4);
+        check_flate2(&compressed[..n], &data);
+
+        let decompressed = decompress(&compressed[..n], Codec::Gzip)?;
+        assert!(same_same(&data, &decompressed));
+        Ok(())
+    }
+
+    #[test]
+    fn read_encoder_header() -> Result<()> {
+        let data = gen_large_data();
+        let opts = CompressOptions::default().gzip_header(header());
+        let compressed = compress(Cursor::new(&data), opts)?;
+        check_flate2(&compressed, &data);
+        Ok(())
+    }
+
+    #[test]
+    fn write_encoder_header() -> Result<()> {
+        let data = gen_large_data();
+        let mut compressed = vec![];
+        let opts = CompressOptions::default().gzip_header(header());
+        let mut encoder = write::Encoder::new(&mut compressed, opts);
+        io::copy(&mut Cursor::new(&data), &mut encoder)?;
+        encoder.finish()?;
+        check_flate2(&compressed, &data);
+        Ok(())
+    }
+
+    #[test]
+    fn reproducible_output() -> Result<()> {
+        let data = gen_large_data();
+        let opts = CompressOptions::default().gzip_header(GzipHeader::new().name("same"));
+        let a = compress(Cursor::new(&data), opts.clone())?;
+        let b = compress(Cursor::new(&data), opts)?;
+        assert_eq!(a, b);
+
+        // no time stamp, unknown OS
+        assert_eq!(&a[4..8], &[0, 0, 0, 0]);
+        assert_eq!(a[9], 255);
+        Ok(())
+    }
+
+    #[test]
+    fn name_ends_at_nul() {
+        assert_eq!(GzipHeader::new().name("a\0b"), GzipHeader::new().name("a"));
+    }
+
+    fn check_parsed(parsed: &GzipHeader) {
+        assert_eq!(parsed.get_name(), Some(b"data.json".as_slice()));
+        assert_eq!(parsed.get_comment(), Some(b"nightly export".as_slice()));
+        assert_eq!(
+            parsed.get_mtime(),
+            Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+        );
+        assert_eq!(parsed.get_os(), 3);
+        assert_eq!(parsed.get_xflags(), 4);
+        assert_eq!(parsed.get_extra(), Some(b"AB\x02\x00hi".as_slice()));
+        assert!(parsed.has_header_crc());
+        assert!(!parsed.is_text());
+    }
+
+    #[test]
+    fn parse_round_trip() -> Result<()> {
+        let opts = CompressOptions::default().gzip_header(header());
+        let compressed = compress(b"foobar".as_slice(), opts)?;
+
+        let (parsed, len) = GzipHeader::parse(&compressed)?;
+        assert_eq!(parsed, header());
+        check_parsed(&parsed);
+
+        // what follows is the deflate stream
+        let body = &compressed[len..compressed.len() - 8];
+        assert_eq!(decompress(body, Codec::Deflate)?, b"foobar");
+        Ok(())
+    }
+
+    #[test]
+    fn parse_generic_header() -> Result<()> {
+        let compressed = compress(b"foobar".as_slice(), CompressOptions::default())?;
+        let (parsed, len) = GzipHeader::parse(&compressed)?;
+        assert_eq!(len, 10);
+        assert_eq!(parsed.get_name(), None);
+        assert_eq!(parsed.get_comment(), None);
+        assert_eq!(parsed.get_extra(), None);
+        assert_eq!(parsed.get_mtime(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_grows_buffers() -> Result<()> {
+        let name = "n".repeat(PARSE_BUF_SIZE * 5);
+        let comment = "c".repeat(PARSE_BUF_SIZE * 3 + 1);
+        let extra = vec![7u8; PARSE_BUF_SIZE * 2];
+        let header = GzipHeader::new()
+            .name(name.clone())
+            .comment(comment.clone())
+            .extra(extra.clone());
+
+        let opts = CompressOptions::default().gzip_header(header.clone());
+        let compressed = compress(b"foobar".as_slice(), opts)?;
+
+        let (parsed, _) = GzipHeader::parse(&compressed)?;
+        assert_eq!(parsed, header);
+        assert_eq!(parsed.get_name(), Some(name.as_bytes()));
+        assert_eq!(parsed.get_comment(), Some(comment.as_bytes()));
+        assert_eq!(parsed.get_extra(), Some(extra.as_slice()));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_truncated() -> Result<()> {
+        let opts = CompressOptions::default().gzip_header(header());
+        let compressed = compress(b"foobar".as_slice(), opts)?;
+        match GzipHeader::parse(&compressed[..15]) {
+            Err(Error::DecompressionError(DecompCode::EndInput)) => (),
+            r => panic!("Expected EndInput, got {:?}", r),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn decoder_headers_per_member() -> Result<()> {
+        let mut compressed = vec![];
+        for name in ["first.txt", "second.txt"] {
+            let opts = CompressOptions::default().gzip_header(GzipHeader::new().name(name));
+            compressed.extend(compress(name.as_bytes(), opts)?);
+        }
+
+        // read::Decoder, one byte of input at a time
+        let mut decoder = read::Decoder::new(
+            io::BufReader::with_capacity(1, compressed.as_slice()),
+            Codec::Gzip,
+        );
+        assert!(decoder.header().is_none());
+        let mut buf = [0u8; 9];
+        decoder.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"first.txt");
+        assert_eq!(
+            decoder.header().unwrap().get_name(),
+            Some(b"first.txt".as_slice())
+        );
+        let mut buf = [0u8; 10];
+        decoder.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"second.txt");
+        assert_eq!(
+            decoder.header().unwrap().get_name(),
+            Some(b"second.txt".as_slice())
+        );
+
+        // write::Decoder
+        let mut decompressed = vec![];
+        let mut decoder = write::Decoder::new(&mut decompressed, Codec::Gzip);
+        decoder.write_all(&compressed)?;
+        assert_eq!(
+            decoder.header().unwrap().get_name(),
+            Some(b"second.txt".as_slice())
+        );
+        drop(decoder);
+        assert_eq!(decompressed, b"first.txtsecond.txt");
+        Ok(())
+    }
+
+    #[test]
+    fn flate2_header_parsed() -> Result<()> {
+        let mut encoder = flate2::GzBuilder::new()
+            .filename("their.txt")
+            .comment("from flate2")
+            .mtime(42)
+            .read(b"foobar".as_slice(), flate2::Compression::fast());
+        let mut compressed = vec![];
+        io::copy(&mut encoder, &mut compressed)?;
+
+        let mut decoder = read::Decoder::new(compressed.as_slice(), Codec::Gzip);
+        let mut decompressed = vec![];
+        io::copy(&mut decoder, &mut decompressed)?;
+        assert_eq!(decompressed, b"foobar");
+
+        let parsed = decoder.header().unwrap();
+        assert_eq!(parsed.get_name(), Some(b"their.txt".as_slice()));
+        assert_eq!(parsed.get_comment(), Some(b"from flate2".as_slice()));
+        assert_eq!(parsed.get_mtime_unix(), 42);
+        Ok(())
+    }
+
+    #[test]
+    fn gzip_header_to_bytes() -> Result<()> {
+        let bytes = header().to_bytes();
+        let (parsed, len) = GzipHeader::parse(&bytes)?;
+        assert_eq!(len, bytes.len());
+        check_parsed(&parsed);
+
+        // prefixed to headerless output makes a complete member
+        let mut compressed = bytes;
+        let opts = CompressOptions::default().codec(Codec::GzipNoHdr);
+        compressed.extend(compress(b"foobar".as_slice(), opts)?);
+        assert_eq!(decompress(compressed.as_slice(), Codec::Gzip)?, b"foobar");
+        Ok(())
+    }
+
+    #[test]
+    fn zlib_header_round_trip() -> Result<()> {
+        let header = ZlibHeader::new()
+            .window_bits(12)
+            .level(3)
+            .dict_id(0xdeadbeef);
+        let bytes = header.to_bytes();
+        assert_eq!(bytes.len(), 6);
+        assert_eq!(bytes[0], 0x48);
+        assert_eq!(&bytes[2..], &0xdeadbeef_u32.to_be_bytes());
+        assert_eq!(ZlibHeader::parse(&bytes)?, (header, 6));
+
+        let bytes = ZlibHeader::new().to_bytes();
+        assert_eq!(bytes, &[0x78, 0x01]);
+        assert_eq!(ZlibHeader::parse(&bytes)?, (ZlibHeader::new(), 2));
+        Ok(())
+    }
+
+    #[test]
+    fn zlib_header_parse_errors() {
+        match ZlibHeader::parse(&[0x78]) {
+            Err(Error::DecompressionError(DecompCode::EndInput)) => (),
+            r => panic!("Expected EndInput, got {:?}", r),
+        }
+        match ZlibHeader::parse(&[0x78, 0xbb, 0, 0]) {
+            Err(Error::DecompressionError(DecompCode::EndInput)) => (),
+            r => panic!("Expected EndInput, got {:?}", r),
+        }
+        match ZlibHeader::parse(&[0x78, 0x02]) {
+            Err(Error::DecompressionError(DecompCode::InvalidWrapper)) => (),
+            r => panic!("Expected InvalidWrapper, got {:?}", r),
+        }
+        match ZlibHeader::parse(&[0x79, 0xdb]) {
+            Err(Error::DecompressionError(DecompCode::UnsupportedMethod)) => (),
+            r => panic!("Expected UnsupportedMethod, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn zlib_header_from_decoder() -> Result<()> {
+        let opts = CompressOptions::new(CompressionLevel::Three, Codec::Zlib)
+            .zlib_header(ZlibHeader::new().level(2).window_bits(14));
+        let compressed = compress(b"foobar".as_slice(), opts)?;
+
+        let mut decoder = read::Decoder::new(compressed.as_slice(), Codec::Zlib);
+        let mut decompressed = vec![];
+        io::copy(&mut decoder, &mut decompressed)?;
+        assert_eq!(decompressed, b"foobar");
+        let header = decoder.zlib_header().unwrap();
+        assert_eq!((header.get_window_bits(), header.get_level()), (14, 2));
+        Ok(())
+    }
+
+    #[test]
+    fn zlib_dictionary_from_zlib() -> Result<()> {
+        // zlib.compressobj(zdict=b"hello hello hello ") output for b"hello hello hello world"
+        let compressed = [
+            0x78, 0xbb, 0x40, 0xcb, 0x06, 0x9d, 0xc3, 0x24, 0xcb, 0xf3, 0x8b, 0x72, 0x52, 0x00,
+            0x68, 0x7d, 0x08, 0xc5,
+        ];
+        let dictionary = b"hello hello hello ";
+        let data = b"hello hello hello world";
+
+        let (header, len) = ZlibHeader::parse(&compressed)?;
+        assert_eq!(header.get_dict_id(), Some(0x40cb069d));
+        assert_eq!(len, 6);
+
+        let opts = DecompressOptions::new(Codec::Zlib).dictionary(dictionary.to_vec());
+        assert_eq!(decompress(compressed.as_slice(), opts.clone())?, data);
+        let mut decompressed = vec![0u8; data.len()];
+        let n = decompress_into(&compressed, &mut decompressed, opts)?;
+        assert_eq!(&decompressed[..n], data);
+
+        // without the dictionary it's an error, not garbage
+        match decompress_into(&compressed, &mut decompressed, Codec::Zlib) {
+            Err(Error::DecompressionError(DecompCode::NeedDict)) => (),
+            r => panic!("Expected NeedDict, got {:?}", r),
+        }
+        Ok(())
+    }
+}
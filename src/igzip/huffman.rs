@@ -0,0 +1,182 @@
+//! Custom Huffman tables trained from sample data
+use crate::igzip::*;
+use std::fmt;
+
+/// Histogram of deflate symbols, used to train `HuffmanTables`.
+///
+/// Can be updated with several samples to get a histogram better representing
+/// the data set which will later be compressed.
+#[derive(Clone)]
+pub struct Histogram(Box<isal::isal_huff_histogram>);
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Histogram").finish_non_exhaustive()
+    }
+}
+
+impl Histogram {
+    /// Create a new, empty histogram
+    pub fn new() -> Self {
+        // all integer arrays, so zeroed is a valid (and the required) initial state
+        Self(Box::new(unsafe { mem::zeroed() }))
+    }
+
+    /// Update the histogram to include the symbols found in `data`
+    pub fn update(&mut self, data: &[u8]) {
+        for chunk in data.chunks(i32::MAX as usize) {
+            unsafe {
+                isal::isal_update_histogram(
+                    chunk.as_ptr() as *mut _,
+                    chunk.len() as _,
+                    self.0.as_mut(),
+                )
+            };
+        }
+    }
+}
+
+/// Huffman tables to use when compressing, created from a `Histogram`.
+///
+/// Attach to compression with `CompressOptions::hufftables`. ISA-L only uses custom
+/// tables for `CompressionLevel::Zero`; higher levels build their own tables per block.
+///
+/// Example
+/// -------
+/// ```
+/// use isal::igzip::{Histogram, HuffmanTables, CompressOptions, CompressionLevel, Codec, compress, decompress};
+///
+/// let sample = b"{\"id\": 1, \"temp\": 21.5, \"status\": \"ok\"}";
+/// let mut histogram = Histogram::new();
+/// histogram.update(sample);
+/// let tables = HuffmanTables::new(&histogram).unwrap();
+///
+/// let opts = CompressOptions::new(CompressionLevel::Zero, Codec::Gzip).hufftables(tables);
+/// let compressed = compress(sample.as_slice(), opts).unwrap();
+///
+/// let decompressed = decompress(compressed.as_slice(), Codec::Gzip).unwrap();
+/// assert_eq!(decompressed.as_slice(), sample);
+/// ```
+#[derive(Clone)]
+pub struct HuffmanTables(Box<isal::isal_hufftables>);
+
+impl fmt::Debug for HuffmanTables {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HuffmanTables").finish_non_exhaustive()
+    }
+}
+
+impl HuffmanTables {
+    /// Create tables from `histogram`, where every literal, repeat length and lookback
+    /// distance is assigned a code.
+    pub fn new(histogram: &Histogram) -> Result<Self> {
+        Self::create(histogram, isal::isal_create_hufftables)
+    }
+
+    /// Create tables from `histogram` like `HuffmanTables::new`, except literals
+    /// which were never seen in the histogram are not assigned a code.
+    ///
+    /// Only compress data whose every literal was counted in `histogram` with these
+    /// tables: isa-l doesn't check, and a literal without a code makes corrupt output.
+    pub fn subset(histogram: &Histogram) -> Result<Self> {
+        Self::create(histogram, isal::isal_create_hufftables_subset)
+    }
+
+    /// Convenience for training tables on a single sample
+    pub fn from_sample(sample: &[u8]) -> Result<Self> {
+        let mut histogram = Histogram::new();
+        histogram.update(sample);
+        Self::new(&histogram)
+    }
+
+    fn create(
+        histogram: &Histogram,
+        f: unsafe extern "C" fn(
+            *mut isal::isal_hufftables,
+            *mut isal::isal_huff_histogram,
+        ) -> std::os::raw::c_int,
+    ) -> Result<Self> {
+        // isa-l may use the histogram as scratch space, so give it a copy
+        let mut histogram = histogram.clone();
+        let mut tables: Box<isal::isal_hufftables> = Box::new(unsafe { mem::zeroed() });
+        let ret = unsafe { f(tables.as_mut(), histogram.0.as_mut()) };
+        if ret != 0 {
+            return Err(Error::Other((
+                Some(ret as isize),
+                "Failed to create huffman tables from histogram".to_string(),
+            )));
+        }
+        Ok(Self(tables))
+    }
+
+    #[inline]
+    pub(crate) fn as_ptr(&self) -> *mut isal::isal_hufftables {
+        self.0.as_ref() as *const _ as *mut _
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::igzip::tests::{gen_large_data, same_same};
+    use std::io::{self, Cursor};
+
+    fn telemetry() -> Vec<u8> {
+        (0..500)
+            .flat_map(|i| {
+                format!("{{\"id\": {}, \"temp\": 21.{}, \"ok\": true}}", i, i % 10).into_bytes()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn compress_into_custom_tables() -> Result<()> {
+        let data = telemetry();
+        let tables = HuffmanTables::from_sample(&data)?;
+
+        let mut compressed = vec![0u8; data.len()];
+        let opts = CompressOptions::new(CompressionLevel::Zero, Codec::Gzip).hufftables(tables);
+        let n = compress_into(&data, &mut compressed, opts)?;
+
+        let decompressed = decompress(&compressed[..n], Codec::Gzip)?;
+        assert!(same_same(&data, &decompressed));
+        Ok(())
+    }
+
+    #[test]
+    fn subset_tables_round_trip() -> Result<()> {
+        let data = telemetry();
+        let mut histogram = Histogram::new();
+        histogram.update(&data);
+        let tables = HuffmanTables::subset(&histogram)?;
+
+        let opts = CompressOptions::new(CompressionLevel::Zero, Codec::Deflate).hufftables(tables);
+        let compressed = compress(Cursor::new(&data), opts)?;
+
+        let decompressed = decompress(compressed.as_slice(), Codec::Deflate)?;
+        assert!(same_same(&data, &decompressed));
+        Ok(())
+    }
+
+    #[test]
+    fn write_encoder_custom_tables() -> Result<()> {
+        let data = gen_large_data();
+        let tables = HuffmanTables::from_sample(&data[..BUF_SIZE])?;
+
+        let mut compressed = vec![];
+        let opts = CompressOptions::new(CompressionLevel::Zero, Codec::Gzip).hufftables(tables);
+        let mut encoder = write::Encoder::new(&mut compressed, opts);
+        io::copy(&mut Cursor::new(&data), &mut encoder)?;
+        encoder.finish()?;
+
+        let decompressed = decompress(compressed.as_slice(), Codec::Gzip)?;
+        assert!(same_same(&data, &decompressed));
+        Ok(())
+    }
+}
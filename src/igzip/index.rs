@@ -0,0 +1,699 @@
+//! Random access into gzip files through an index of checkpoints, zran style
+//!
+//! Building the index decompresses the file once, recording every so often where a
+//! deflate block begins: its offset in bits into the file, its offset into the
+//! decompressed data and the 32KiB of data before it. `IndexedGzipReader` then serves
+//! any offset by starting to inflate at the checkpoint before it, with the recorded
+//! window as dictionary, instead of from the start of the file.
+//!
+//! Only the first gzip member is indexed, as is typical of logs written in one go.
+use crate::igzip::*;
+use std::collections::VecDeque;
+use std::io::{self, SeekFrom};
+
+/// Default distance in decompressed bytes between checkpoints
+pub const DEFAULT_SPAN: u64 = 1024 * 1024;
+
+// The most inflate can look back, which a checkpoint must hold
+const WINDOW_SIZE: usize = 32 * 1024;
+const CHUNK_SIZE: usize = 256 * 1024;
+
+// Serialized index: magic and version, then the decompressed length, the number of
+// checkpoints and for each its bit offset, offset and deflated window, all little endian
+const INDEX_MAGIC: [u8; 8] = *b"ISALGZI\x01";
+
+/// Point at which inflate can start over in the middle of a gzip file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    bit_offset: u64,
+    offset: u64,
+    window: Vec<u8>,
+}
+
+impl Checkpoint {
+    /// Offset in bits into the compressed file of the deflate block starting here
+    pub fn bit_offset(&self) -> u64 {
+        self.bit_offset
+    }
+
+    /// Offset into the decompressed data
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Up to 32KiB of decompressed data before `offset`, the dictionary to inflate with
+    pub fn window(&self) -> &[u8] {
+        &self.window
+    }
+}
+
+/// Builder for a `GzipIndex`
+///
+/// Example
+/// -------
+/// ```
+/// use std::io::{Cursor, Read, Seek, SeekFrom};
+/// use isal::igzip::{compress, index::{IndexBuilder, IndexedGzipReader}, CompressOptions};
+///
+/// let data: Vec<u8> = (0..100_000u32).flat_map(|i| format!("line {i}\n").into_bytes()).collect();
+/// let compressed = compress(data.as_slice(), CompressOptions::default()).unwrap();
+///
+/// let index = IndexBuilder::new().span(64 * 1024).build(compressed.as_slice()).unwrap();
+/// assert_eq!(index.uncompressed_len(), data.len() as u64);
+///
+/// let mut reader = IndexedGzipReader::new(Cursor::new(compressed), index);
+/// reader.seek(SeekFrom::Start(500_000)).unwrap();
+/// let mut buf = [0u8; 10];
+/// reader.read_exact(&mut buf).unwrap();
+/// assert_eq!(&buf, &data[500_000..500_010]);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct IndexBuilder {
+    span: u64,
+}
+
+impl Default for IndexBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IndexBuilder {
+    pub fn new() -> Self {
+        Self { span: DEFAULT_SPAN }
+    }
+
+    /// Least distance in decompressed bytes between checkpoints, default `DEFAULT_SPAN`.
+    ///
+    /// Smaller spans make seeks faster and the index bigger, at up to 32KiB a checkpoint.
+    pub fn span(mut self, span: u64) -> Self {
+        self.span = span;
+        self
+    }
+
+    /// Decompress the gzip member read from `reader`, verifying its checksum, and
+    /// index it.
+    pub fn build<R: io::Read>(&self, reader: R) -> io::Result<GzipIndex> {
+        Ok(self.build_at(reader, 0)?.0)
+    }
+
+    /// Like `IndexBuilder::build`, with `reader` at `base` in a file so checkpoints
+    /// are at offsets into it; also returns the length of the member, trailer included.
+    pub(crate) fn build_at<R: io::Read>(
+        &self,
+        mut reader: R,
+        base: u64,
+    ) -> io::Result<(GzipIndex, u64)> {
+        let mut in_buf = vec![0u8; CHUNK_SIZE];
+        let mut in_end = 0;
+        let header_len = loop {
+            if let Some((_, len)) = GzipHeader::parse_partial(&in_buf[..in_end])? {
+                break len;
+            }
+            if in_end == in_buf.len() {
+                in_buf.resize(in_buf.len() * 2, 0);
+            }
+            match read_retry(&mut reader, &mut in_buf[in_end..])? {
+                0 => return Err(end_input()),
+                n => in_end += n,
+            }
+        };
+
+        // isa-l inflates and checks the trailer, while the blocks are walked ahead of
+        // it to pick checkpoints, and it is stopped at each to take the window
+        let mut zst = Box::new(InflateState::new());
+        zst.0.crc_flag = isal::ISAL_GZIP_NO_HDR_VER;
+
+        let mut checkpoints = vec![Checkpoint {
+            bit_offset: (base + header_len as u64) * 8,
+            offset: 0,
+            window: vec![],
+        }];
+        // file offset of in_buf[0]
+        let mut in_base = base;
+        let mut in_pos = header_len;
+        // start in bits into in_buf of the next block to walk, and its offset into
+        // the decompressed data
+        let mut walk = header_len as u64 * 8;
+        let mut walk_out = 0u64;
+        let mut walk_done = false;
+        let mut unwalked = true;
+        let mut next_checkpoint = std::cmp::max(self.span, 1);
+        // bit offsets and offsets of checkpoints inflate has yet to reach
+        let mut pending = VecDeque::new();
+        // the output of the last inflate call ends the window
+        let mut out_buf = vec![0u8; WINDOW_SIZE + CHUNK_SIZE];
+        let mut out_len = 0usize;
+        let mut total_out = 0u64;
+
+        loop {
+            if in_pos == in_end {
+                // keep the block being walked, which runs past what was read
+                let keep = match walk_done {
+                    true => in_end,
+                    false => (walk / 8) as usize,
+                };
+                in_buf.copy_within(keep..in_end, 0);
+                in_base += keep as u64;
+                in_pos -= keep;
+                in_end -= keep;
+                walk -= keep as u64 * 8;
+                if in_end == in_buf.len() {
+                    in_buf.resize(in_buf.len() * 2, 0);
+                }
+                match read_retry(&mut reader, &mut in_buf[in_end..])? {
+                    0 => return Err(end_input()),
+                    n => in_end += n,
+                }
+                unwalked = true;
+            }
+            while unwalked && !walk_done {
+                let block = match blocks::walk_block(&in_buf[..in_end], walk) {
+                    Ok(block) => block,
+                    Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                    Err(err) => return Err(err),
+                };
+                walk = block.end;
+                walk_out += block.len;
+                walk_done = block.last;
+                if !block.last && walk_out >= next_checkpoint {
+                    pending.push_back((in_base * 8 + walk, walk_out));
+                    next_checkpoint = walk_out + std::cmp::max(self.span, 1);
+                }
+            }
+            unwalked = false;
+
+            while let Some(&(bit_offset, offset)) = pending.front() {
+                if offset > total_out {
+                    break;
+                }
+                // passed only if inflate and the walk disagree, and isa-l fails then
+                if offset == total_out {
+                    checkpoints.push(Checkpoint {
+                        bit_offset,
+                        offset,
+                        window: out_buf[out_len.saturating_sub(WINDOW_SIZE)..out_len].to_vec(),
+                    });
+                }
+                pending.pop_front();
+            }
+            if out_buf.len() - out_len < BUF_SIZE {
+                out_buf.copy_within(out_len - WINDOW_SIZE..out_len, 0);
+                out_len = WINDOW_SIZE;
+            }
+
+            // output stops at the next checkpoint
+            let mut avail_out = (out_buf.len() - out_len) as u64;
+            if let Some(&(_, offset)) = pending.front() {
+                avail_out = std::cmp::min(avail_out, offset - total_out);
+            }
+            let input = &in_buf[in_pos..in_end];
+            zst.0.next_in = input.as_ptr() as *mut _;
+            zst.0.avail_in = input.len() as _;
+            zst.0.next_out = out_buf[out_len..].as_mut_ptr();
+            zst.0.avail_out = avail_out as _;
+
+            zst.step_inflate()?;
+
+            in_pos = in_end - zst.0.avail_in as usize;
+            let produced = avail_out as usize - zst.0.avail_out as usize;
+            out_len += produced;
+            total_out += produced as u64;
+
+            if zst.block_state() == isal::isal_block_state_ISAL_BLOCK_FINISH {
+                break;
+            }
+        }
+
+        // isa-l hands back input read ahead of the trailer once finished
+        let index = GzipIndex {
+            checkpoints,
+            uncompressed_len: total_out,
+        };
+        Ok((index, in_base + in_pos as u64 - base))
+    }
+}
+
+fn end_input() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        Error::DecompressionError(DecompCode::EndInput),
+    )
+}
+
+fn invalid_index() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid gzip index")
+}
+
+fn read_retry<R: io::Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        match reader.read(buf) {
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            result => return result,
+        }
+    }
+}
+
+/// Checkpoints into a gzip file, made by `IndexBuilder`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GzipIndex {
+    checkpoints: Vec<Checkpoint>,
+    uncompressed_len: u64,
+}
+
+impl GzipIndex {
+    /// Checkpoints in increasing order, the first being the start of the deflate data
+    pub fn checkpoints(&self) -> &[Checkpoint] {
+        &self.checkpoints
+    }
+
+    /// Length of the decompressed data
+    pub fn uncompressed_len(&self) -> u64 {
+        self.uncompressed_len
+    }
+
+    /// Last checkpoint at or before `offset` into the decompressed data
+    pub fn checkpoint_before(&self, offset: u64) -> &Checkpoint {
+        let idx = self.checkpoints.partition_point(|cp| cp.offset <= offset);
+        &self.checkpoints[idx.saturating_sub(1)]
+    }
+
+    /// Save the index, windows deflated, to be loaded by `GzipIndex::read_from`.
+    pub fn write_to<W: io::Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&INDEX_MAGIC)?;
+        writer.write_all(&self.uncompressed_len.to_le_bytes())?;
+        writer.write_all(&(self.checkpoints.len() as u64).to_le_bytes())?;
+        for cp in &self.checkpoints {
+            let opts = CompressOptions::new(CompressionLevel::One, Codec::Deflate);
+            let window = compress_to_vec(&cp.window, opts)?;
+            writer.write_all(&cp.bit_offset.to_le_bytes())?;
+            writer.write_all(&cp.offset.to_le_bytes())?;
+            writer.write_all(&(window.len() as u32).to_le_bytes())?;
+            writer.write_all(&window)?;
+        }
+        Ok(())
+    }
+
+    /// Load an index saved by `GzipIndex::write_to`
+    pub fn read_from<R: io::Read>(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if magic != INDEX_MAGIC {
+            return Err(invalid_index());
+        }
+        let uncompressed_len = read_u64(&mut reader)?;
+        let n = read_u64(&mut reader)?;
+
+        let mut checkpoints: Vec<Checkpoint> = vec![];
+        for _ in 0..n {
+            let bit_offset = read_u64(&mut reader)?;
+            let offset = read_u64(&mut reader)?;
+            let mut len = [0u8; 4];
+            reader.read_exact(&mut len)?;
+            let len = u32::from_le_bytes(len) as usize;
+            if len > compress_bound(WINDOW_SIZE, Codec::Deflate) {
+                return Err(invalid_index());
+            }
+            let mut window = vec![0u8; len];
+            reader.read_exact(&mut window)?;
+            let window = decompress_to_vec(&window, Codec::Deflate)?;
+
+            let ordered = checkpoints
+                .last()
+                .is_none_or(|last| last.offset < offset && last.bit_offset < bit_offset);
+            if !ordered || offset > uncompressed_len || window.len() > WINDOW_SIZE {
+                return Err(invalid_index());
+            }
+            checkpoints.push(Checkpoint {
+                bit_offset,
+                offset,
+                window,
+            });
+        }
+        if checkpoints.first().is_none_or(|cp| cp.offset != 0) {
+            return Err(invalid_index());
+        }
+        Ok(Self {
+            checkpoints,
+            uncompressed_len,
+        })
+    }
+}
+
+fn read_u64<R: io::Read>(reader: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Decompression of an indexed gzip file, implementing `std::io::Seek` on the
+/// decompressed data.
+///
+/// Seeking ahead by less than the distance to the next checkpoint inflates through
+/// what's skipped, anything else starts over from the checkpoint before the target.
+/// The checksum of the whole file was verified when the index was built, and isn't
+/// checked again; `index` must be of the same file.
+///
+/// Example
+/// -------
+/// ```
+/// use std::io::{Cursor, Read, Seek, SeekFrom};
+/// use isal::igzip::{compress, index::{GzipIndex, IndexBuilder, IndexedGzipReader}, CompressOptions};
+///
+/// let data = b"Hello, World!".repeat(10_000);
+/// let compressed = compress(data.as_slice(), CompressOptions::default()).unwrap();
+///
+/// // Build once and save alongside the file
+/// let mut saved = vec![];
+/// IndexBuilder::new().build(compressed.as_slice()).unwrap().write_to(&mut saved).unwrap();
+///
+/// let index = GzipIndex::read_from(saved.as_slice()).unwrap();
+/// let mut reader = IndexedGzipReader::new(Cursor::new(compressed), index);
+/// reader.seek(SeekFrom::End(-13)).unwrap();
+/// let mut rest = String::new();
+/// reader.read_to_string(&mut rest).unwrap();
+/// assert_eq!(rest, "Hello, World!");
+/// ```
+pub struct IndexedGzipReader<R: io::Read + io::Seek> {
+    inner: R,
+    index: GzipIndex,
+    zst: Box<InflateState>,
+    in_buf: Vec<u8>,
+    in_pos: usize,
+    in_end: usize,
+    out_buf: Vec<u8>,
+    out_pos: usize,
+    out_end: usize,
+    // offset into the decompressed data of out_buf[0]
+    out_start: u64,
+    finished: bool,
+    // the inner reader isn't where inflate left off, start over before reading
+    restart: bool,
+}
+
+impl<R: io::Read + io::Seek> IndexedGzipReader<R> {
+    /// Create a new `IndexedGzipReader` over a gzip file and its index.
+    pub fn new(reader: R, index: GzipIndex) -> Self {
+        Self {
+            inner: reader,
+            index,
+            zst: Box::new(InflateState::new()),
+            in_buf: vec![0u8; BUF_SIZE],
+            in_pos: 0,
+            in_end: 0,
+            out_buf: vec![0u8; BUF_SIZE],
+            out_pos: 0,
+            out_end: 0,
+            out_start: 0,
+            finished: false,
+            restart: true,
+        }
+    }
+
+    /// Mutable reference to underlying reader, not advisable to modify during reading.
+    pub fn get_ref_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    // Reference to underlying reader
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// The index in use
+    pub fn index(&self) -> &GzipIndex {
+        &self.index
+    }
+
+    /// Give up the underlying reader
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    // Offset into the decompressed data of the next byte read
+    fn position(&self) -> u64 {
+        self.out_start + self.out_pos as u64
+    }
+
+    // Prepare to inflate from the checkpoint before `offset`
+    fn restart_at(&mut self, offset: u64) -> io::Result<()> {
+        let cp = self.index.checkpoint_before(offset);
+        self.zst.reset();
+        self.zst.0.crc_flag = isal::ISAL_DEFLATE;
+        if !cp.window.is_empty() {
+            self.zst.set_dict(&cp.window)?;
+        }
+
+        // Inflate takes its first bits from the bit buffer, the rest of the byte
+        // holding them
+        self.inner.seek(SeekFrom::Start(cp.bit_offset / 8))?;
+        let bits = (cp.bit_offset % 8) as u32;
+        if bits != 0 {
+            let mut byte = [0u8; 1];
+            self.inner.read_exact(&mut byte)?;
+            self.zst.0.read_in = (byte[0] >> bits) as u64;
+            self.zst.0.read_in_length = (8 - bits) as _;
+        }
+
+        self.in_pos = 0;
+        self.in_end = 0;
+        self.out_start = cp.offset;
+        self.out_pos = 0;
+        self.out_end = 0;
+        self.finished = false;
+        self.restart = false;
+        Ok(())
+    }
+
+    // Inflate the next output into `out_buf`, leaving it empty at the end of the data
+    fn fill(&mut self) -> io::Result<()> {
+        self.out_start += self.out_end as u64;
+        self.out_pos = 0;
+        self.out_end = 0;
+
+        while self.out_end == 0 && !self.finished {
+            if self.in_pos == self.in_end {
+                self.in_pos = 0;
+                self.in_end = read_retry(&mut self.inner, &mut self.in_buf)?;
+                if self.in_end == 0 {
+                    return Err(end_input());
+                }
+            }
+            let input = &self.in_buf[self.in_pos..self.in_end];
+            self.zst.0.next_in = input.as_ptr() as *mut _;
+            self.zst.0.avail_in = input.len() as _;
+            self.zst.0.next_out = self.out_buf.as_mut_ptr();
+            self.zst.0.avail_out = self.out_buf.len() as _;
+
+            self.zst.step_inflate()?;
+
+            self.in_pos = self.in_end - self.zst.0.avail_in as usize;
+            self.out_end = self.out_buf.len() - self.zst.0.avail_out as usize;
+            self.finished = self.zst.block_state() == isal::isal_block_state_ISAL_BLOCK_FINISH;
+        }
+        Ok(())
+    }
+
+    // Inflate and discard up to `offset`, which is at or after the current position
+    fn skip_to(&mut self, offset: u64) -> io::Result<()> {
+        loop {
+            let end = self.out_start + self.out_end as u64;
+            if offset <= end {
+                self.out_pos = (offset - self.out_start) as usize;
+                return Ok(());
+            }
+            if self.finished {
+                // Past the end, reads will give nothing
+                self.out_pos = self.out_end;
+                return Ok(());
+            }
+            self.out_pos = self.out_end;
+            self.fill()?;
+        }
+    }
+}
+
+impl<R: io::Read + io::Seek> io::Read for IndexedGzipReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position() >= self.index.uncompressed_len {
+            return Ok(0);
+        }
+        if self.restart {
+            let offset = self.position();
+            self.restart_at(offset)?;
+            self.skip_to(offset)?;
+        }
+        if self.out_pos == self.out_end {
+            self.fill()?;
+        }
+        let available = &self.out_buf[self.out_pos..self.out_end];
+        let n = std::cmp::min(buf.len(), available.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.out_pos += n;
+        Ok(n)
+    }
+}
+
+impl<R: io::Read + io::Seek> io::Seek for IndexedGzipReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.index.uncompressed_len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position().checked_add_signed(delta),
+        };
+        let target = target.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        let current = self.position();
+        let buffered = self.out_start..=self.out_start + self.out_end as u64;
+        if !self.restart && buffered.contains(&target) {
+            self.out_pos = (target - self.out_start) as usize;
+        } else if !self.restart
+            && target > current
+            && self.index.checkpoint_before(target).offset <= current
+        {
+            self.skip_to(target)?;
+        } else {
+            self.out_start = target;
+            self.out_pos = 0;
+            self.out_end = 0;
+            self.restart = true;
+        }
+        Ok(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::igzip::tests::{gen_log_data, same_same};
+    use std::io::{Cursor, Read, Seek, Write};
+
+    fn flate2_gzip(data: &[u8]) -> Result<Vec<u8>> {
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(data)?;
+        Ok(encoder.finish()?)
+    }
+
+    fn check_seeks(data: &[u8], compressed: Vec<u8>, index: GzipIndex) -> Result<()> {
+        let mut reader = IndexedGzipReader::new(Cursor::new(compressed), index);
+        let len = data.len() as u64;
+        let offsets = [
+            len / 2,
+            0,
+            len - 10,
+            17,
+            len / 3,
+            len / 3 + 100,
+            len / 3 + 300_000,
+        ];
+        for offset in offsets {
+            assert_eq!(reader.seek(SeekFrom::Start(offset))?, offset);
+            let end = std::cmp::min(offset as usize + 1000, data.len());
+            let mut buf = vec![0u8; end - offset as usize];
+            reader.read_exact(&mut buf)?;
+            assert_eq!(buf, &data[offset as usize..end]);
+        }
+
+        reader.seek(SeekFrom::End(-5))?;
+        reader.seek(SeekFrom::Current(-100_000))?;
+        let mut rest = vec![];
+        reader.read_to_end(&mut rest)?;
+        assert!(same_same(&rest, &data[data.len() - 100_005..]));
+
+        // Past the end reads nothing
+        reader.seek(SeekFrom::Start(len + 10))?;
+        assert_eq!(reader.read(&mut [0u8; 10])?, 0);
+        assert!(reader.seek(SeekFrom::Current(-(len as i64) - 11)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn seek_flate2() -> Result<()> {
+        let data = gen_log_data(0..200_000);
+        let compressed = flate2_gzip(&data)?;
+        let index = IndexBuilder::new()
+            .span(64 * 1024)
+            .build(compressed.as_slice())?;
+        assert_eq!(index.uncompressed_len(), data.len() as u64);
+        assert!(index.checkpoints().len() > 10);
+        for cp in &index.checkpoints()[1..] {
+            assert_eq!(
+                cp.window(),
+                &data[cp.offset() as usize - WINDOW_SIZE..cp.offset() as usize]
+            );
+        }
+        check_seeks(&data, compressed, index)
+    }
+
+    #[test]
+    fn seek_isal() -> Result<()> {
+        let data = gen_log_data(0..200_000);
+        let opts = CompressOptions::new(CompressionLevel::One, Codec::Gzip);
+        let compressed = compress(data.as_slice(), opts)?;
+        let index = IndexBuilder::new()
+            .span(64 * 1024)
+            .build(compressed.as_slice())?;
+        assert_eq!(index.uncompressed_len(), data.len() as u64);
+        check_seeks(&data, compressed, index)
+    }
+
+    #[test]
+    fn seek_stored() -> Result<()> {
+        // Stored blocks only, their headers far shorter than any dynamic one
+        let data = gen_log_data(0..200_000);
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::none());
+        encoder.write_all(&data)?;
+        let compressed = encoder.finish()?;
+        let index = IndexBuilder::new()
+            .span(64 * 1024)
+            .build(compressed.as_slice())?;
+        assert_eq!(index.uncompressed_len(), data.len() as u64);
+        assert!(index.checkpoints().len() > 10);
+        for cp in &index.checkpoints()[1..] {
+            assert_eq!(
+                cp.window(),
+                &data[cp.offset() as usize - WINDOW_SIZE..cp.offset() as usize]
+            );
+        }
+        check_seeks(&data, compressed, index)
+    }
+
+    #[test]
+    fn serialization() -> Result<()> {
+        let data = gen_log_data(0..200_000);
+        let compressed = flate2_gzip(&data)?;
+        let index = IndexBuilder::new()
+            .span(256 * 1024)
+            .build(compressed.as_slice())?;
+
+        let mut saved = vec![];
+        index.write_to(&mut saved)?;
+        let loaded = GzipIndex::read_from(saved.as_slice())?;
+        assert_eq!(loaded, index);
+        check_seeks(&data, compressed, loaded)?;
+
+        saved[0] = b'X';
+        assert!(GzipIndex::read_from(saved.as_slice()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn build_errors() -> Result<()> {
+        let data = gen_log_data(0..200_000);
+        let mut compressed = flate2_gzip(&data)?;
+
+        let truncated = &compressed[..compressed.len() / 2];
+        assert!(IndexBuilder::new().build(truncated).is_err());
+
+        let n = compressed.len();
+        compressed[n - 8] ^= 1;
+        assert!(IndexBuilder::new().build(compressed.as_slice()).is_err());
+        Ok(())
+    }
+}
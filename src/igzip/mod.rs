@@ -1,9 +1,34 @@
 //! IGZIP interface
+pub mod append;
+pub mod bgzf;
+mod blocks;
+pub mod bufread;
+mod dict;
+#[cfg(feature = "futures-io")]
+pub mod futures;
+mod header;
+mod huffman;
+pub mod index;
+pub mod parallel;
+mod poll;
 pub mod read;
+pub mod stitch;
+mod stream;
+#[cfg(feature = "tokio")]
+pub mod tokio;
 pub mod write;
 
+use std::fmt;
 use std::io;
 use std::mem;
+use std::sync::Arc;
+
+use dict::Dictionary;
+pub use dict::ProcessedDict;
+pub use header::{GzipHeader, ZlibHeader};
+pub use huffman::{Histogram, HuffmanTables};
+pub use stream::{adler32, adler32_combine, crc32, crc32_combine};
+pub use stream::{Compress, Decompress, Status};
 
 pub(crate) use crate::error::{Error, Result};
 use isal_sys::igzip_lib as isal;
@@ -13,39 +38,476 @@ pub const BUF_SIZE: usize = 16 * 1024;
 
 /// Flavor of De/Compression to use if using the `isal::igzip::Encoder/Decoder` directly
 /// and not the thin wrappers like `GzDecoder/GzEncoder` and similar.
+///
+/// The `*NoHdr` flavors leave the header to the caller. When compressing they write
+/// deflate data followed by the trailer. When decompressing, `GzipNoHdr` and `ZlibNoHdr`
+/// read only the deflate data, leaving any trailer unread, while `GzipNoHdrVer` and
+/// `ZlibNoHdrVer` also read and verify the trailer.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(u32)]
 pub enum Codec {
-    Gzip = isal::IGZIP_GZIP,
-    Deflate = isal::IGZIP_DEFLATE,
-    Zlib = isal::IGZIP_ZLIB,
+    Gzip = isal::ISAL_GZIP,
+    Deflate = isal::ISAL_DEFLATE,
+    Zlib = isal::ISAL_ZLIB,
+    /// Gzip without the header
+    GzipNoHdr = isal::ISAL_GZIP_NO_HDR,
+    /// Zlib without the header
+    ZlibNoHdr = isal::ISAL_ZLIB_NO_HDR,
+    /// Gzip without the header, verifying the CRC32 and size trailer when decompressing
+    GzipNoHdrVer = isal::ISAL_GZIP_NO_HDR_VER,
+    /// Zlib without the header, verifying the Adler32 trailer when decompressing
+    ZlibNoHdrVer = isal::ISAL_ZLIB_NO_HDR_VER,
+}
+
+impl Codec {
+    /// `gzip_flag` for compression; the variants themselves are the inflate `crc_flag`s
+    #[inline]
+    pub(crate) fn gzip_flag(self) -> u16 {
+        let flag = match self {
+            Self::Gzip => isal::IGZIP_GZIP,
+            Self::Deflate => isal::IGZIP_DEFLATE,
+            Self::Zlib => isal::IGZIP_ZLIB,
+            Self::GzipNoHdr | Self::GzipNoHdrVer => isal::IGZIP_GZIP_NO_HDR,
+            Self::ZlibNoHdr | Self::ZlibNoHdrVer => isal::IGZIP_ZLIB_NO_HDR,
+        };
+        flag as _
+    }
+}
+
+/// Size of the level buffer handed to ISA-L, trading memory for compression ratio.
+///
+/// Each `CompressionLevel` (other than `Zero`, which needs no buffer) has a set of
+/// suggested sizes; `Default` matches what ISA-L recommends for the level.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum LevelBufSize {
+    Min,
+    Small,
+    Medium,
+    Large,
+    ExtraLarge,
+    #[default]
+    Default,
+}
+
+impl LevelBufSize {
+    /// Number of bytes this tier allocates for `level`
+    pub fn size(&self, level: CompressionLevel) -> usize {
+        let size = match (level, self) {
+            (CompressionLevel::Zero, _) => isal::ISAL_DEF_LVL0_DEFAULT,
+            (CompressionLevel::One, Self::Min) => isal::ISAL_DEF_LVL1_MIN,
+            (CompressionLevel::One, Self::Small) => isal::ISAL_DEF_LVL1_SMALL,
+            (CompressionLevel::One, Self::Medium) => isal::ISAL_DEF_LVL1_MEDIUM,
+            (CompressionLevel::One, Self::Large) => isal::ISAL_DEF_LVL1_LARGE,
+            (CompressionLevel::One, Self::ExtraLarge) => isal::ISAL_DEF_LVL1_EXTRA_LARGE,
+            (CompressionLevel::One, Self::Default) => isal::ISAL_DEF_LVL1_DEFAULT,
+            (CompressionLevel::Two, Self::Min) => isal::ISAL_DEF_LVL2_MIN,
+            (CompressionLevel::Two, Self::Small) => isal::ISAL_DEF_LVL2_SMALL,
+            (CompressionLevel::Two, Self::Medium) => isal::ISAL_DEF_LVL2_MEDIUM,
+            (CompressionLevel::Two, Self::Large) => isal::ISAL_DEF_LVL2_LARGE,
+            (CompressionLevel::Two, Self::ExtraLarge) => isal::ISAL_DEF_LVL2_EXTRA_LARGE,
+            (CompressionLevel::Two, Self::Default) => isal::ISAL_DEF_LVL2_DEFAULT,
+            (CompressionLevel::Three, Self::Min) => isal::ISAL_DEF_LVL3_MIN,
+            (CompressionLevel::Three, Self::Small) => isal::ISAL_DEF_LVL3_SMALL,
+            (CompressionLevel::Three, Self::Medium) => isal::ISAL_DEF_LVL3_MEDIUM,
+            (CompressionLevel::Three, Self::Large) => isal::ISAL_DEF_LVL3_LARGE,
+            (CompressionLevel::Three, Self::ExtraLarge) => isal::ISAL_DEF_LVL3_EXTRA_LARGE,
+            (CompressionLevel::Three, Self::Default) => isal::ISAL_DEF_LVL3_DEFAULT,
+        };
+        size as usize
+    }
+}
+
+/// Options used to configure compression, accepted by `compress`, `compress_into`
+/// and the `read`/`write` Encoders.
+///
+/// Example
+/// -------
+/// ```
+/// use isal::igzip::{CompressOptions, CompressionLevel, LevelBufSize, Codec, compress, decompress};
+///
+/// let opts = CompressOptions::new(CompressionLevel::Two, Codec::Gzip)
+///     .level_buf_size(LevelBufSize::Small);
+/// let compressed = compress(b"foobar".as_slice(), opts).unwrap();
+///
+/// let decompressed = decompress(compressed.as_slice(), Codec::Gzip).unwrap();
+/// assert_eq!(decompressed.as_slice(), b"foobar");
+/// ```
+#[derive(Clone, Debug)]
+pub struct CompressOptions {
+    level: CompressionLevel,
+    level_buf_size: LevelBufSize,
+    codec: Codec,
+    flush: Option<FlushFlags>,
+    hufftables: Option<Arc<HuffmanTables>>,
+    dictionary: Option<Dictionary>,
+    window_bits: Option<u8>,
+    gzip_header: Option<GzipHeader>,
+    zlib_header: Option<ZlibHeader>,
+    stored_fallback: bool,
+}
+
+impl Default for CompressOptions {
+    fn default() -> Self {
+        Self::new(CompressionLevel::Three, Codec::Gzip)
+    }
+}
+
+impl CompressOptions {
+    /// Create options for `level` and `codec`, using the default level buffer size
+    /// and the default flush mode of whatever consumes the options.
+    pub fn new(level: CompressionLevel, codec: Codec) -> Self {
+        Self {
+            level,
+            level_buf_size: LevelBufSize::Default,
+            codec,
+            flush: None,
+            hufftables: None,
+            dictionary: None,
+            window_bits: None,
+            gzip_header: None,
+            zlib_header: None,
+            stored_fallback: false,
+        }
+    }
+
+    /// Set the compression level
+    pub fn level(mut self, level: CompressionLevel) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Set the size of the level buffer allocated for the stream
+    pub fn level_buf_size(mut self, level_buf_size: LevelBufSize) -> Self {
+        self.level_buf_size = level_buf_size;
+        self
+    }
+
+    /// Set the codec
+    pub fn codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Set the flush mode.
+    ///
+    /// Stateless compression (`compress_into`) only supports `NoFlush` and `FullFlush`.
+    pub fn flush(mut self, flush: FlushFlags) -> Self {
+        self.flush = Some(flush);
+        self
+    }
+
+    /// Use custom Huffman tables instead of the ISA-L defaults.
+    ///
+    /// Only takes effect with `CompressionLevel::Zero`, see `HuffmanTables`.
+    pub fn hufftables(mut self, hufftables: impl Into<Arc<HuffmanTables>>) -> Self {
+        self.hufftables = Some(hufftables.into());
+        self
+    }
+
+    /// Use a preset dictionary, only the last 32KiB of which will be used.
+    ///
+    /// For `Codec::Zlib` the header will announce the dictionary by its Adler32 id,
+    /// the decompressing side must then be given the same dictionary.
+    pub fn dictionary(mut self, dictionary: impl Into<Arc<[u8]>>) -> Self {
+        self.dictionary = Some(Dictionary::Raw(dictionary.into()));
+        self
+    }
+
+    /// Use a dictionary processed ahead of time, replacing any set with `CompressOptions::dictionary`.
+    pub fn processed_dict(mut self, dictionary: impl Into<Arc<ProcessedDict>>) -> Self {
+        self.dictionary = Some(Dictionary::Processed(dictionary.into()));
+        self
+    }
+
+    /// Limit the history window to `2^window_bits` bytes, equivalent to zlib's windowBits.
+    ///
+    /// Clamped to `8..=15`, the default being 15 (32KiB). For `Codec::Zlib` the window
+    /// size is recorded in the header; the decompressing side needs at least this window.
+    pub fn window_bits(mut self, window_bits: u8) -> Self {
+        self.window_bits = Some(window_bits.clamp(MIN_WINDOW_BITS, MAX_WINDOW_BITS));
+        self
+    }
+
+    /// Write `header` instead of the generic gzip header, only used with `Codec::Gzip`.
+    pub fn gzip_header(mut self, header: GzipHeader) -> Self {
+        self.gzip_header = Some(header);
+        self
+    }
+
+    /// Write `header` instead of the generic zlib header, only used with `Codec::Zlib`.
+    ///
+    /// Its window size is used unless `CompressOptions::window_bits` is set, and its
+    /// dictionary id is replaced by that of any dictionary set.
+    pub fn zlib_header(mut self, header: ZlibHeader) -> Self {
+        self.zlib_header = Some(header);
+        self
+    }
+
+    /// When the output of `compress_into` doesn't fit, write the input as stored
+    /// (uncompressed) blocks instead, which always fit within `compress_bound`.
+    pub fn stored_fallback(mut self, stored_fallback: bool) -> Self {
+        self.stored_fallback = stored_fallback;
+        self
+    }
+
+    /// Worst case output size for `len` bytes of input, as `compress_bound` but
+    /// accounting for a custom gzip header.
+    fn bound(&self, len: usize) -> usize {
+        let bound = compress_bound(len, self.codec);
+        match (self.codec, &self.gzip_header) {
+            (Codec::Gzip, Some(header)) => bound - GZIP_HEADER_LEN + header.to_bytes().len(),
+            _ => bound,
+        }
+    }
+}
+
+const MIN_WINDOW_BITS: u8 = 8;
+const GZIP_HEADER_LEN: usize = 10;
+const STORED_BLOCK_HDR_LEN: usize = 5;
+const STORED_BLOCK_MAX_LEN: usize = u16::MAX as usize;
+const MAX_WINDOW_BITS: u8 = isal::ISAL_DEF_MAX_HIST_BITS as _;
+
+type DictionaryLookup = Arc<dyn Fn(u32) -> Option<Arc<[u8]>> + Send + Sync>;
+type MismatchCallback = Arc<dyn Fn(&ChecksumMismatch) + Send + Sync>;
+
+/// What to do with the checksum (and gzip size) trailer when decompressing
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ChecksumPolicy {
+    /// Verify the trailer, failing with `DecompCode::IncorrectChecksum` on a mismatch
+    #[default]
+    Verify,
+    /// Don't compute checksums at all, the trailer is read but ignored.
+    ///
+    /// Only for data which is known to be intact, e.g. trusted internal hops.
+    Skip,
+    /// Compute and compare checksums, but report mismatches through
+    /// `DecompressOptions::on_checksum_mismatch` and the Decoders instead of failing
+    Report,
+}
+
+/// What to do with input following the last gzip member (or zlib/deflate stream).
+///
+/// Once a member ends, what follows is taken for the next member if it starts like
+/// one and `DecompressOptions::multi_member` is on. Headerless codecs give nothing to
+/// recognize, so for them only `multi_member(false)` leaves anything to this policy.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TrailingData {
+    /// Fail with `Error::TrailingData`, the default when decoding multiple members
+    Error,
+    /// Discard the rest of the input, e.g. the zero padding of tape images
+    Ignore,
+    /// Stop before it, the default when decoding a single member. The read Decoder
+    /// hands back what it buffered with `into_inner_with_remaining`; the write Decoder,
+    /// having no way to give input back, take and discard it as with `Ignore`.
+    Stop,
+}
+
+/// A trailer which didn't match the decompressed data, see `ChecksumPolicy::Report`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ChecksumMismatch {
+    /// CRC32 or Adler32 found in the trailer
+    pub expected: u32,
+    /// CRC32 or Adler32 of the decompressed data
+    pub computed: u32,
+    /// Gzip only, the size modulo 2^32 found in the trailer
+    pub expected_size: Option<u32>,
+    /// Gzip only, the decompressed size modulo 2^32
+    pub computed_size: Option<u32>,
+}
+
+/// Options used to configure decompression, accepted by `decompress`, `decompress_into`
+/// and the `read`/`write` Decoders.
+///
+/// A plain `Codec` converts into default options for that codec.
+///
+/// Example
+/// -------
+/// ```
+/// use isal::igzip::{CompressOptions, DecompressOptions, CompressionLevel, Codec, compress, decompress};
+///
+/// let dictionary = b"{\"id\": , \"status\": \"ok\"}".to_vec();
+/// let opts = CompressOptions::new(CompressionLevel::Three, Codec::Zlib).dictionary(dictionary.clone());
+/// let compressed = compress(b"{\"id\": 7, \"status\": \"ok\"}".as_slice(), opts).unwrap();
+///
+/// let opts = DecompressOptions::new(Codec::Zlib).dictionary(dictionary);
+/// let decompressed = decompress(compressed.as_slice(), opts).unwrap();
+/// assert_eq!(decompressed.as_slice(), b"{\"id\": 7, \"status\": \"ok\"}");
+/// ```
+#[derive(Clone)]
+pub struct DecompressOptions {
+    codec: Codec,
+    dictionary: Option<Arc<[u8]>>,
+    dictionary_lookup: Option<DictionaryLookup>,
+    window_bits: Option<u8>,
+    checksum_policy: ChecksumPolicy,
+    on_checksum_mismatch: Option<MismatchCallback>,
+    multi_member: bool,
+    trailing_data: Option<TrailingData>,
+}
+
+impl fmt::Debug for DecompressOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DecompressOptions")
+            .field("codec", &self.codec)
+            .field("dictionary", &self.dictionary)
+            .field("dictionary_lookup", &self.dictionary_lookup.is_some())
+            .field("window_bits", &self.window_bits)
+            .field("checksum_policy", &self.checksum_policy)
+            .field("on_checksum_mismatch", &self.on_checksum_mismatch.is_some())
+            .field("multi_member", &self.multi_member)
+            .field("trailing_data", &self.trailing_data)
+            .finish()
+    }
+}
+
+impl From<Codec> for DecompressOptions {
+    fn from(codec: Codec) -> Self {
+        Self::new(codec)
+    }
+}
+
+impl DecompressOptions {
+    /// Create options for decompressing `codec`
+    pub fn new(codec: Codec) -> Self {
+        Self {
+            codec,
+            dictionary: None,
+            dictionary_lookup: None,
+            window_bits: None,
+            checksum_policy: ChecksumPolicy::Verify,
+            on_checksum_mismatch: None,
+            multi_member: true,
+            trailing_data: None,
+        }
+    }
+
+    /// Set the codec
+    pub fn codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Use a preset dictionary.
+    ///
+    /// Deflate and gzip streams use it from the start, zlib streams only when their
+    /// header asks for a dictionary with a matching Adler32 id.
+    pub fn dictionary(mut self, dictionary: impl Into<Arc<[u8]>>) -> Self {
+        self.dictionary = Some(dictionary.into());
+        self
+    }
+
+    /// Look up the dictionary a zlib stream asks for by its Adler32 id, taking
+    /// precedence over `DecompressOptions::dictionary`.
+    pub fn dictionary_lookup<F>(mut self, lookup: F) -> Self
+    where
+        F: Fn(u32) -> Option<Arc<[u8]>> + Send + Sync + 'static,
+    {
+        self.dictionary_lookup = Some(Arc::new(lookup));
+        self
+    }
+
+    /// Limit the history window to `2^window_bits` bytes, equivalent to zlib's windowBits.
+    ///
+    /// Clamped to `8..=15`, the default being 15 (32KiB). Streams referring further
+    /// back than the window, or zlib streams whose header asks for a larger window,
+    /// are rejected.
+    pub fn window_bits(mut self, window_bits: u8) -> Self {
+        self.window_bits = Some(window_bits.clamp(MIN_WINDOW_BITS, MAX_WINDOW_BITS));
+        self
+    }
+
+    /// Set how trailers are checked, defaults to `ChecksumPolicy::Verify`
+    pub fn checksum_policy(mut self, policy: ChecksumPolicy) -> Self {
+        self.checksum_policy = policy;
+        self
+    }
+
+    /// Called for every mismatching trailer under `ChecksumPolicy::Report`
+    pub fn on_checksum_mismatch<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&ChecksumMismatch) + Send + Sync + 'static,
+    {
+        self.on_checksum_mismatch = Some(Arc::new(callback));
+        self
+    }
+
+    /// Keep decoding gzip members (or zlib/deflate streams) one after another until
+    /// the input ends, the default, or stop after the first one.
+    ///
+    /// Decoders report where that member ended with `member_end()`, input after it
+    /// is dealt with according to `DecompressOptions::trailing_data`.
+    pub fn multi_member(mut self, multi_member: bool) -> Self {
+        self.multi_member = multi_member;
+        self
+    }
+
+    /// Set what happens to input after the last member, defaults to `TrailingData::Error`,
+    /// or `TrailingData::Stop` with `multi_member(false)`.
+    pub fn trailing_data(mut self, policy: TrailingData) -> Self {
+        self.trailing_data = Some(policy);
+        self
+    }
+
+    #[inline]
+    fn has_dictionary(&self) -> bool {
+        self.dictionary.is_some() || self.dictionary_lookup.is_some()
+    }
+}
+
+/// Worst case size of compressing `len` bytes with `codec`, i.e. when stored as is.
+///
+/// Covers the generic gzip header and zlib headers with a dictionary id; a custom
+/// `GzipHeader` adds its own length beyond the generic 10 bytes.
+///
+/// Example
+/// -------
+/// ```
+/// use isal::igzip::{CompressOptions, CompressionLevel, Codec, compress_bound, compress_into, decompress};
+///
+/// let data = b"foobar";
+/// let mut compressed = vec![0u8; compress_bound(data.len(), Codec::Gzip)];
+/// let opts = CompressOptions::new(CompressionLevel::Three, Codec::Gzip).stored_fallback(true);
+/// let nbytes = compress_into(data, &mut compressed, opts).unwrap();
+///
+/// let decompressed = decompress(&compressed[..nbytes], Codec::Gzip).unwrap();
+/// assert_eq!(decompressed.as_slice(), data);
+/// ```
+pub fn compress_bound(len: usize, codec: Codec) -> usize {
+    // an empty input still takes one (empty) block
+    let n_blocks = std::cmp::max(1, len.div_ceil(STORED_BLOCK_MAX_LEN));
+    let (header_len, trailer_len) = match codec {
+        Codec::Gzip => (GZIP_HEADER_LEN, 8),
+        Codec::Zlib => (6, 4),
+        Codec::Deflate => (0, 0),
+        Codec::GzipNoHdr | Codec::GzipNoHdrVer => (0, 8),
+        Codec::ZlibNoHdr | Codec::ZlibNoHdrVer => (0, 4),
+    };
+    header_len + len + n_blocks * STORED_BLOCK_HDR_LEN + trailer_len
 }
 
 /// Compress `input` directly into `output`. This is the fastest possible compression available.
 ///
+/// Fails with `CompCode::StatelessOverflow` if `output` is too small, unless
+/// `CompressOptions::stored_fallback` is set and `output` is at least `compress_bound`.
+///
 /// Example
 /// -------
 /// ```
-/// use isal::igzip::{CompressionLevel, Codec, compress_into, decompress};
+/// use isal::igzip::{CompressOptions, CompressionLevel, Codec, compress_into, decompress};
 ///
 /// let mut compressed = vec![0u8; 100];
-/// let nbytes = compress_into(b"foobar", &mut compressed, CompressionLevel::Three, Codec::Gzip).unwrap();
+/// let opts = CompressOptions::new(CompressionLevel::Three, Codec::Gzip);
+/// let nbytes = compress_into(b"foobar", &mut compressed, opts).unwrap();
 ///
 /// let decompressed = decompress(&compressed[..nbytes], Codec::Gzip).unwrap();
 /// assert_eq!(decompressed.as_slice(), b"foobar");
 ///
 /// ```
 #[inline(always)]
-pub fn compress_into(
-    input: &[u8],
-    output: &mut [u8],
-    level: CompressionLevel,
-    codec: Codec,
-) -> Result<usize> {
-    let mut zstream = ZStream::new(level, ZStreamKind::Stateless);
+pub fn compress_into(input: &[u8], output: &mut [u8], options: CompressOptions) -> Result<usize> {
+    let mut zstream = ZStream::from_options(&options, ZStreamKind::Stateless);
 
-    zstream.stream.flush = FlushFlags::NoFlush as _;
-    zstream.stream.gzip_flag = codec as _;
+    zstream.stream.flush = options.flush.unwrap_or(FlushFlags::NoFlush) as _;
     zstream.stream.end_of_stream = 1;
 
     // read input into buffer
@@ -56,36 +518,153 @@ pub fn compress_into(
     zstream.stream.avail_out = output.len() as _;
     zstream.stream.next_out = output.as_mut_ptr();
 
-    zstream.deflate()?;
-    Ok(zstream.stream.total_out as _)
+    match zstream.deflate() {
+        Err(Error::CompressionError(CompCode::StatelessOverflow)) if options.stored_fallback => {
+            let header = match options.codec {
+                _ if !zstream.header.is_empty() => mem::take(&mut zstream.header),
+                Codec::Gzip => GzipHeader::default().write_to(&mut zstream),
+                Codec::Zlib => ZlibHeader::default().write_to(&mut zstream),
+                _ => vec![],
+            };
+            compress_stored(input, output, &header, options.codec)
+        }
+        Err(err) => Err(err),
+        Ok(()) => Ok(zstream.stream.total_out as _),
+    }
+}
+
+// Write `header`, `input` as stored deflate blocks and the trailer `codec` calls for
+fn compress_stored(input: &[u8], output: &mut [u8], header: &[u8], codec: Codec) -> Result<usize> {
+    let trailer = match codec {
+        Codec::Gzip | Codec::GzipNoHdr | Codec::GzipNoHdrVer => {
+            let mut trailer = crc32(input).to_le_bytes().to_vec();
+            trailer.extend_from_slice(&(input.len() as u32).to_le_bytes());
+            trailer
+        }
+        Codec::Zlib | Codec::ZlibNoHdr | Codec::ZlibNoHdrVer => {
+            adler32(input).to_be_bytes().to_vec()
+        }
+        Codec::Deflate => vec![],
+    };
+    let n_blocks = std::cmp::max(1, input.len().div_ceil(STORED_BLOCK_MAX_LEN));
+    let len = header.len() + input.len() + n_blocks * STORED_BLOCK_HDR_LEN + trailer.len();
+    if output.len() < len {
+        return Err(Error::CompressionError(CompCode::StatelessOverflow));
+    }
+
+    output[..header.len()].copy_from_slice(header);
+    let mut pos = header.len();
+    let mut blocks = input.chunks(STORED_BLOCK_MAX_LEN).peekable();
+    if blocks.peek().is_none() {
+        output[pos..pos + STORED_BLOCK_HDR_LEN].copy_from_slice(&[1, 0, 0, 0xff, 0xff]);
+        pos += STORED_BLOCK_HDR_LEN;
+    }
+    while let Some(block) = blocks.next() {
+        let is_final = blocks.peek().is_none() as u8;
+        let block_len = block.len() as u16;
+        output[pos] = is_final;
+        output[pos + 1..pos + 3].copy_from_slice(&block_len.to_le_bytes());
+        output[pos + 3..pos + 5].copy_from_slice(&(!block_len).to_le_bytes());
+        pos += STORED_BLOCK_HDR_LEN;
+        output[pos..pos + block.len()].copy_from_slice(block);
+        pos += block.len();
+    }
+    output[pos..pos + trailer.len()].copy_from_slice(&trailer);
+    Ok(pos + trailer.len())
+}
+
+/// Compress `input` into a new `Vec`, allocated once at `compress_bound` and falling back
+/// to stored blocks should the compressed output not fit.
+///
+/// Example
+/// -------
+/// ```
+/// use isal::igzip::{CompressOptions, CompressionLevel, Codec, compress_to_vec, decompress};
+///
+/// let opts = CompressOptions::new(CompressionLevel::Three, Codec::Zlib);
+/// let compressed = compress_to_vec(b"foobar", opts).unwrap();
+///
+/// let decompressed = decompress(compressed.as_slice(), Codec::Zlib).unwrap();
+/// assert_eq!(decompressed.as_slice(), b"foobar");
+/// ```
+pub fn compress_to_vec(input: &[u8], options: CompressOptions) -> Result<Vec<u8>> {
+    let mut output = vec![0u8; options.bound(input.len())];
+    let n = compress_into(input, &mut output, options.stored_fallback(true))?;
+    output.truncate(n);
+    Ok(output)
 }
 
 /// Compress `input`
 #[inline(always)]
-pub fn compress<R: std::io::Read>(
-    input: R,
-    level: CompressionLevel,
-    codec: Codec,
-) -> Result<Vec<u8>> {
+pub fn compress<R: std::io::Read>(input: R, options: CompressOptions) -> Result<Vec<u8>> {
     let mut out = vec![];
-    let mut encoder = read::Encoder::new(input, level, codec);
+    let mut encoder = read::Encoder::new(input, options);
     io::copy(&mut encoder, &mut out)?;
     Ok(out)
 }
 
 /// Decompress
 #[inline(always)]
-pub fn decompress<R: std::io::Read>(input: R, codec: Codec) -> Result<Vec<u8>> {
+pub fn decompress<R: std::io::Read>(
+    input: R,
+    options: impl Into<DecompressOptions>,
+) -> Result<Vec<u8>> {
     let mut out = vec![];
-    let mut decoder = read::Decoder::new(input, codec);
+    let mut decoder = read::Decoder::new(input, options);
     io::copy(&mut decoder, &mut out)?;
     Ok(out)
 }
 
 /// Decompress `input` into `output`, returning number of bytes written to output.
+///
+/// If `output` is too small, fails with `Error::OutputTooSmall` carrying the size needed.
 #[inline(always)]
-pub fn decompress_into(input: &[u8], output: &mut [u8], codec: Codec) -> Result<usize> {
+pub fn decompress_into(
+    input: &[u8],
+    output: &mut [u8],
+    options: impl Into<DecompressOptions>,
+) -> Result<usize> {
+    let options = options.into();
+    match decompress_into_inner(input, output, options.clone()) {
+        Err(Error::DecompressionError(DecompCode::OutOverflow)) => {
+            Err(Error::OutputTooSmall(decompressed_len(input, options)?))
+        }
+        result => result,
+    }
+}
+
+fn decompress_into_inner(
+    input: &[u8],
+    output: &mut [u8],
+    options: DecompressOptions,
+) -> Result<usize> {
+    let codec = options.codec;
+
+    // Headers with a dictionary id need a dictionary, which may be found by id
+    let zlib_header = match codec {
+        Codec::Zlib => Some(ZlibHeader::parse(input)?),
+        _ => None,
+    };
+    let needs_dict = matches!(zlib_header, Some((header, _)) if header.get_dict_id().is_some());
+
+    // Stateless inflate doesn't support dictionaries, nor reading the trailer ourselves,
+    // so run the stream in one go instead
+    if options.has_dictionary() || needs_dict || options.checksum_policy != ChecksumPolicy::Verify {
+        let mut decomp = Decompress::new(options);
+        let status = decomp.decompress(input, output)?;
+        if !status.stream_end {
+            let code = if status.produced == output.len() {
+                DecompCode::OutOverflow
+            } else {
+                DecompCode::EndInput
+            };
+            return Err(Error::DecompressionError(code));
+        }
+        return Ok(status.produced);
+    }
+
     let mut zst = InflateState::new();
+    zst.0.hist_bits = options.window_bits.unwrap_or(0) as _;
     zst.0.avail_in = input.len() as _;
     zst.0.next_in = input.as_ptr() as *mut _;
 
@@ -94,36 +673,128 @@ pub fn decompress_into(input: &[u8], output: &mut [u8], codec: Codec) -> Result<
     zst.0.avail_out = output.len() as _;
     zst.0.next_out = output.as_mut_ptr();
 
-    if codec == Codec::Zlib {
-        zst.0.crc_flag = 0; // zlib uses adler-32 checksum
-
-        let mut hdr: mem::MaybeUninit<isal::isal_zlib_header> = mem::MaybeUninit::uninit();
-        unsafe { isal::isal_zlib_header_init(hdr.as_mut_ptr()) };
-        let mut hdr = unsafe { hdr.assume_init() };
-        read_zlib_header(&mut zst.0, &mut hdr)?;
-        zst.0.next_in = input[2..].as_ptr() as *mut _; // skip header now that it's read
-        zst.0.avail_in -= 4; // adler-32 checksum
+    // Header read above, leave the body and trailer to isa-l
+    if let Some((header, len)) = zlib_header {
+        if header.get_window_bits() > options.window_bits.unwrap_or(MAX_WINDOW_BITS) {
+            return Err(Error::DecompressionError(DecompCode::InvalidWrapper));
+        }
+        zst.0.crc_flag = isal::ISAL_ZLIB_NO_HDR_VER;
+        zst.0.hist_bits = header.get_window_bits() as _;
+        zst.0.next_in = input[len..].as_ptr() as *mut _;
+        zst.0.avail_in = (input.len() - len) as _;
     }
 
     zst.inflate_stateless()?;
 
-    if codec == Codec::Zlib {
-        let decompressed = &output[..zst.0.total_out as _];
-        let c_adler32 =
-            unsafe { isal::isal_adler32(1, decompressed.as_ptr(), decompressed.len() as _) };
-        let bytes: [u8; 4] = (&input[input.len() - 4..]).try_into().unwrap();
-        let e_adler32 = u32::from_be_bytes(bytes);
-        if c_adler32 != e_adler32 {
-            // panic!("Computed adler: {}, expected: {}", c_adler32, e_adler32);
-            return Err(Error::DecompressionError(DecompCode::IncorrectChecksum));
+    Ok(zst.0.total_out as _)
+}
+
+// Decompressed size of the single stream in `input`, counted through a scratch buffer
+fn decompressed_len(input: &[u8], options: DecompressOptions) -> Result<usize> {
+    let mut decomp = Decompress::new(options);
+    let mut scratch = vec![0u8; BUF_SIZE];
+    let (mut consumed, mut len) = (0, 0);
+    while !decomp.is_finished() {
+        let status = decomp.decompress(&input[consumed..], &mut scratch)?;
+        consumed += status.consumed;
+        len += status.produced;
+        if !status.stream_end && consumed == input.len() && status.produced < scratch.len() {
+            return Err(Error::DecompressionError(DecompCode::EndInput));
         }
     }
+    Ok(len)
+}
 
-    Ok(zst.0.total_out as _)
+/// Decompressed size recorded in the ISIZE field of a gzip trailer at the end of `input`.
+///
+/// Only a hint: it's the size modulo 2^32 and covers only the last member.
+pub fn gzip_size_hint(input: &[u8]) -> Option<usize> {
+    // smallest member: header, an empty block and the trailer
+    if input.len() < GZIP_HEADER_LEN + 2 + 8 || input[..2] != [0x1f, 0x8b] {
+        return None;
+    }
+    let isize = input[input.len() - 4..].try_into().unwrap();
+    Some(u32::from_le_bytes(isize) as usize)
+}
+
+/// Decompress `input` into a new `Vec`, preallocated from the ISIZE trailer for `Codec::Gzip`.
+///
+/// Other codecs, or a trailer that undersells the data, start smaller and the
+/// output grows until all of `input` is decompressed.
+///
+/// Example
+/// -------
+/// ```
+/// use isal::igzip::{CompressOptions, CompressionLevel, Codec, compress_to_vec, decompress_to_vec};
+///
+/// let opts = CompressOptions::new(CompressionLevel::Three, Codec::Gzip);
+/// let compressed = compress_to_vec(b"foobar", opts).unwrap();
+///
+/// let decompressed = decompress_to_vec(&compressed, Codec::Gzip).unwrap();
+/// assert_eq!(decompressed.as_slice(), b"foobar");
+/// ```
+pub fn decompress_to_vec(input: &[u8], options: impl Into<DecompressOptions>) -> Result<Vec<u8>> {
+    let options = options.into();
+    let size_hint = match options.codec {
+        Codec::Gzip => gzip_size_hint(input),
+        _ => None,
+    };
+    // no better guess than a typical ratio
+    let size_hint = size_hint.unwrap_or(input.len().saturating_mul(4));
+    decompress_to_vec_with_hint(input, options, size_hint)
+}
+
+/// Decompress `input` into a new `Vec` preallocated to `size_hint` bytes, growing it
+/// should the hint be too small.
+///
+/// The hint is capped at the most `input` could decompress to, so one read from
+/// untrusted input, such as `gzip_size_hint`, can't force a huge allocation.
+pub fn decompress_to_vec_with_hint(
+    input: &[u8],
+    options: impl Into<DecompressOptions>,
+    size_hint: usize,
+) -> Result<Vec<u8>> {
+    let mut decomp = Decompress::new(options.into());
+    // deflate expands at most 1032:1
+    let size_hint = std::cmp::min(size_hint, input.len().saturating_mul(1032));
+    let mut output = vec![0u8; size_hint];
+    let (mut consumed, mut produced) = (0, 0);
+    loop {
+        // Previous stream/member done, begin the next one if there is more input
+        if decomp.is_finished() {
+            if consumed == input.len() {
+                break;
+            }
+            if !decomp.starts_member(input[consumed]) {
+                match decomp.trailing_data() {
+                    TrailingData::Error => return Err(Error::TrailingData(consumed as u64)),
+                    TrailingData::Ignore | TrailingData::Stop => break,
+                }
+            }
+            decomp.reset();
+        }
+        if produced == output.len() {
+            output.resize(std::cmp::max(output.len() * 2, BUF_SIZE), 0);
+        }
+
+        let status = decomp.decompress(&input[consumed..], &mut output[produced..])?;
+        consumed += status.consumed;
+        produced += status.produced;
+
+        if !status.stream_end && consumed == input.len() && produced < output.len() {
+            // Empty input is fine, a stream cut short is not
+            match decomp.total_in() {
+                0 => break,
+                _ => return Err(Error::DecompressionError(DecompCode::EndInput)),
+            }
+        }
+    }
+    output.truncate(produced);
+    Ok(output)
 }
 
 /// Flush Flags
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[repr(i8)]
 pub enum FlushFlags {
     NoFlush = isal::NO_FLUSH as _,
@@ -213,11 +884,12 @@ impl TryFrom<i32> for DecompCode {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[repr(u8)]
 pub enum CompressionLevel {
     Zero = 0,
     One = 1,
+    Two = 2,
     Three = 3,
 }
 
@@ -227,11 +899,12 @@ impl TryFrom<isize> for CompressionLevel {
         match value {
             0 => Ok(Self::Zero),
             1 => Ok(Self::One),
+            2 => Ok(Self::Two),
             3 => Ok(Self::Three),
             _ => Err(Self::Error::Other((
                 None,
                 format!(
-                    "Compression level {} not supported, must be one of [0, 1, 3]",
+                    "Compression level {} not supported, must be one of [0, 1, 2, 3]",
                     value
                 ),
             ))),
@@ -248,11 +921,28 @@ pub struct ZStream {
     stream: isal::isal_zstream,
     #[allow(dead_code)] // Pointer used by stream, kept here to release when dropped
     level_buf: Vec<u8>,
+    #[allow(dead_code)] // Pointer used by stream, kept here to release when dropped
+    hufftables: Option<Arc<HuffmanTables>>,
+    dictionary: Option<Dictionary>,
+    // gzip_flag to restore on reset, isa-l changes it once its own header is written
+    gzip_flag: u16,
+    // wrapper header written by us rather than isa-l, emitted ahead of compressed output
+    header: Vec<u8>,
+    header_pos: usize,
     kind: ZStreamKind,
 }
 
 impl ZStream {
     pub fn new(level: CompressionLevel, kind: ZStreamKind) -> Self {
+        Self::with_level_buf_size(level, LevelBufSize::Default, kind)
+    }
+
+    /// Create a new stream, allocating a level buffer of the given size tier
+    pub fn with_level_buf_size(
+        level: CompressionLevel,
+        level_buf_size: LevelBufSize,
+        kind: ZStreamKind,
+    ) -> Self {
         let mut zstream_uninit: mem::MaybeUninit<isal::isal_zstream> = mem::MaybeUninit::uninit();
         match kind {
             ZStreamKind::Stateful => unsafe {
@@ -263,12 +953,7 @@ impl ZStream {
             },
         }
         let mut zstream = unsafe { zstream_uninit.assume_init() };
-        let buf_size = match level {
-            CompressionLevel::Zero => isal::ISAL_DEF_LVL0_DEFAULT,
-            CompressionLevel::One => isal::ISAL_DEF_LVL1_DEFAULT,
-            CompressionLevel::Three => isal::ISAL_DEF_LVL3_DEFAULT,
-        };
-        let mut buf = vec![0u8; buf_size as usize];
+        let mut buf = vec![0u8; level_buf_size.size(level)];
 
         zstream.level = level as _;
         zstream.level_buf = buf.as_mut_ptr();
@@ -277,11 +962,184 @@ impl ZStream {
         Self {
             stream: zstream,
             level_buf: buf,
+            hufftables: None,
+            dictionary: None,
+            gzip_flag: isal::IGZIP_DEFLATE as _,
+            header: vec![],
+            header_pos: 0,
             kind,
         }
     }
+
+    /// Create a new stream configured from `options`; flush mode is left to the caller
+    pub(crate) fn from_options(options: &CompressOptions, kind: ZStreamKind) -> Self {
+        let mut zstream = Self::with_level_buf_size(options.level, options.level_buf_size, kind);
+        zstream.gzip_flag = options.codec.gzip_flag();
+
+        // zlib records the window and dictionary in its header
+        let mut zlib_header = options.zlib_header.unwrap_or_default();
+        if let Some(window_bits) = options.window_bits {
+            zlib_header = zlib_header.window_bits(window_bits);
+        }
+        if let Some(dictionary) = &options.dictionary {
+            zlib_header = zlib_header.dict_id(dictionary.id());
+        }
+        zstream.stream.hist_bits = match (options.codec, options.window_bits) {
+            (_, Some(window_bits)) => window_bits,
+            (Codec::Zlib, None) if options.zlib_header.is_some() => zlib_header.get_window_bits(),
+            _ => 0, // isa-l's default, the largest window
+        } as _;
+
+        // Can't fail on a freshly initialized stream
+        if let Some(hufftables) = &options.hufftables {
+            let ret = zstream.set_hufftables(hufftables.clone());
+            debug_assert!(ret.is_ok());
+        }
+        if let Some(dictionary) = &options.dictionary {
+            zstream.dictionary = Some(dictionary.clone());
+            let ret = zstream.apply_dictionary();
+            debug_assert!(ret.is_ok());
+        }
+
+        // isa-l's own zlib header is the generic one, otherwise write it ourselves
+        if options.codec == Codec::Zlib && zlib_header != ZlibHeader::default() {
+            zstream.header = zlib_header.write_to(&mut zstream);
+            zstream.gzip_flag = isal::IGZIP_ZLIB_NO_HDR as _;
+        }
+        if let (Codec::Gzip, Some(header)) = (options.codec, &options.gzip_header) {
+            zstream.header = header.write_to(&mut zstream);
+            zstream.gzip_flag = isal::IGZIP_GZIP_NO_HDR as _;
+        }
+        zstream.stream.gzip_flag = zstream.gzip_flag;
+        zstream
+    }
+
+    // Run one of isa-l's header writers against a scratch output buffer
+    fn header_bytes(&mut self, mut write: impl FnMut(&mut isal::isal_zstream) -> u32) -> Vec<u8> {
+        let (next_out, avail_out, total_out) = (
+            self.stream.next_out,
+            self.stream.avail_out,
+            self.stream.total_out,
+        );
+        let mut buf = vec![];
+        loop {
+            self.stream.next_out = buf.as_mut_ptr();
+            self.stream.avail_out = buf.len() as _;
+            // returns the size needed when the buffer is too small
+            match write(&mut self.stream) {
+                0 => break,
+                needed => buf.resize(needed as usize, 0),
+            }
+        }
+        buf.truncate((self.stream.total_out - total_out) as usize);
+        self.stream.next_out = next_out;
+        self.stream.avail_out = avail_out;
+        self.stream.total_out = total_out;
+        buf
+    }
+
+    /// Reset the stream to begin a new one, keeping the level, codec, tables and dictionary.
+    pub fn reset(&mut self) {
+        unsafe { isal::isal_deflate_reset(&mut self.stream) };
+        self.stream.gzip_flag = self.gzip_flag;
+        self.header_pos = 0;
+
+        // Can't fail on a freshly reset stream
+        let ret = self.apply_dictionary();
+        debug_assert!(ret.is_ok());
+    }
+
+    fn apply_dictionary(&mut self) -> Result<()> {
+        match self.dictionary.clone() {
+            Some(Dictionary::Raw(dictionary)) => self.set_dict(&dictionary),
+            Some(Dictionary::Processed(dictionary)) => self.set_processed_dict(&dictionary),
+            None => Ok(()),
+        }
+    }
+
+    /// Set a dictionary processed ahead of time, which is cheaper than `ZStream::set_dict`.
+    ///
+    /// If it was processed for another compression level, its history is set as a raw
+    /// dictionary instead. Must be called before compression begins or after a completed
+    /// sync/full flush.
+    pub fn set_processed_dict(&mut self, dictionary: &ProcessedDict) -> Result<()> {
+        if self.stream.level != dictionary.level() as u32 {
+            return self.set_dict(dictionary.history());
+        }
+        let ret = unsafe { isal::isal_deflate_reset_dict(&mut self.stream, dictionary.as_ptr()) };
+        match CompCode::try_from(ret)? {
+            CompCode::CompOk => Ok(()),
+            r => Err(Error::CompressionError(r)),
+        }
+    }
+
+    /// Set a preset dictionary, only the last 32KiB of which will be used.
+    ///
+    /// Must be called before compression begins or after a completed sync/full flush.
+    pub fn set_dict(&mut self, dictionary: &[u8]) -> Result<()> {
+        let ret = unsafe {
+            isal::isal_deflate_set_dict(
+                &mut self.stream,
+                dictionary.as_ptr() as *mut _,
+                dictionary.len() as _,
+            )
+        };
+        match CompCode::try_from(ret)? {
+            CompCode::CompOk => Ok(()),
+            r => Err(Error::CompressionError(r)),
+        }
+    }
+
+    /// Use custom Huffman tables for the rest of the stream.
+    ///
+    /// Must be called before compression begins or after a completed sync/full flush.
+    pub fn set_hufftables(&mut self, hufftables: Arc<HuffmanTables>) -> Result<()> {
+        let ret = unsafe {
+            isal::isal_deflate_set_hufftables(
+                &mut self.stream,
+                hufftables.as_ptr(),
+                isal::IGZIP_HUFFTABLE_CUSTOM as _,
+            )
+        };
+        match CompCode::try_from(ret)? {
+            CompCode::CompOk => {
+                self.hufftables = Some(hufftables);
+                Ok(())
+            }
+            r => Err(Error::CompressionError(r)),
+        }
+    }
+
     #[inline]
     pub fn deflate(&mut self) -> Result<()> {
+        // Any header we wrote ourselves goes out before isa-l's output
+        if self.header_pos < self.header.len() {
+            let n = std::cmp::min(
+                self.header.len() - self.header_pos,
+                self.stream.avail_out as usize,
+            );
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    self.header[self.header_pos..].as_ptr(),
+                    self.stream.next_out,
+                    n,
+                );
+                self.stream.next_out = self.stream.next_out.add(n);
+            }
+            self.stream.avail_out -= n as u32;
+            self.stream.total_out += n as u32;
+            self.header_pos += n;
+            if self.header_pos < self.header.len() {
+                // stateless has no second chance to write the rest
+                return match self.kind {
+                    ZStreamKind::Stateful => Ok(()),
+                    ZStreamKind::Stateless => {
+                        Err(Error::CompressionError(CompCode::StatelessOverflow))
+                    }
+                };
+            }
+        }
+
         let ret = match self.kind {
             ZStreamKind::Stateful => unsafe { isal::isal_deflate(&mut self.stream) },
             ZStreamKind::Stateless => unsafe { isal::isal_deflate_stateless(&mut self.stream) },
@@ -312,6 +1170,26 @@ impl InflateState {
         unsafe { isal::isal_inflate_reset(&mut self.0) }
     }
 
+    /// Set a preset dictionary, only the last 32KiB of which will be used.
+    ///
+    /// Must be called before decompression of the stream begins.
+    pub fn set_dict(&mut self, dictionary: &[u8]) -> Result<()> {
+        let ret = unsafe {
+            isal::isal_inflate_set_dict(
+                &mut self.0,
+                dictionary.as_ptr() as *mut _,
+                dictionary.len() as _,
+            )
+        };
+        match ret {
+            0 => Ok(()),
+            _ => Err(Error::Other((
+                Some(ret as isize),
+                "Failed to set inflate dictionary".to_string(),
+            ))),
+        }
+    }
+
     pub fn step_inflate(&mut self) -> Result<()> {
         let ret = unsafe { isal::isal_inflate(&mut self.0) };
         match DecompCode::try_from(ret)? {
@@ -363,7 +1241,7 @@ pub fn read_zlib_header(
 #[cfg(test)]
 pub mod tests {
 
-    use io::Cursor;
+    use io::{Cursor, Read, Write};
     use md5;
     use std::fs;
 
@@ -377,6 +1255,26 @@ pub mod tests {
             .collect()
     }
 
+    // Log like lines, compressing well but not so well that blocks get huge
+    pub fn gen_log_data(lines: std::ops::Range<u32>) -> Vec<u8> {
+        lines
+            .flat_map(|i| {
+                format!(
+                    "{i:08} GET /path/{} {} {}\n",
+                    i * 7919 % 1013,
+                    i % 97,
+                    i % 13
+                )
+                .into_bytes()
+            })
+            .collect()
+    }
+
+    // Preset dictionary for small JSON records
+    pub fn dictionary() -> Vec<u8> {
+        b"{\"id\": , \"temp\": 21., \"status\": \"ok\"}".repeat(4)
+    }
+
     pub fn same_same(a: &[u8], b: &[u8]) -> bool {
         md5::compute(a) == md5::compute(b)
     }
@@ -392,12 +1290,7 @@ pub mod tests {
     fn basic_compress_into() -> Result<()> {
         let data = get_data()?;
         let mut output = vec![0_u8; data.len()]; // assume compression isn't worse than input len.
-        let n_bytes = compress_into(
-            data.as_slice(),
-            &mut output,
-            CompressionLevel::Three,
-            Codec::Gzip,
-        )?;
+        let n_bytes = compress_into(data.as_slice(), &mut output, CompressOptions::default())?;
         println!(
             "n_bytes: {} - {:?}",
             n_bytes,
@@ -409,7 +1302,7 @@ pub mod tests {
     #[test]
     fn basic_compress() -> Result<()> {
         let data = get_data()?;
-        let output = compress(Cursor::new(data), CompressionLevel::Three, Codec::Gzip)?;
+        let output = compress(Cursor::new(data), CompressOptions::default())?;
         println!(
             "n_bytes: {:?}",
             &output[..std::cmp::min(output.len() - 1, 100)]
@@ -447,7 +1340,7 @@ pub mod tests {
     fn larger_decompress() -> Result<()> {
         /* Decompress data which is larger than BUF_SIZE */
         let data = get_data()?;
-        let compressed = compress(Cursor::new(&data), CompressionLevel::Three, Codec::Gzip)?;
+        let compressed = compress(Cursor::new(&data), CompressOptions::default())?;
         let decompressed = decompress(Cursor::new(compressed), Codec::Gzip)?;
         assert!(same_same(&data, &decompressed));
         Ok(())
@@ -470,7 +1363,7 @@ pub mod tests {
     #[test]
     fn basic_round_trip() -> Result<()> {
         let data = b"hello, world!";
-        let compressed = compress(Cursor::new(&data), CompressionLevel::Three, Codec::Gzip)?;
+        let compressed = compress(Cursor::new(&data), CompressOptions::default())?;
         let decompressed = decompress(Cursor::new(compressed), Codec::Gzip)?;
         assert_eq!(decompressed, data);
         Ok(())
@@ -480,15 +1373,14 @@ pub mod tests {
     fn basic_round_trip_into() -> Result<()> {
         let data = b"hello, world!".to_vec();
 
-        let compressed_len =
-            compress(Cursor::new(&data), CompressionLevel::Three, Codec::Gzip)?.len();
+        let compressed_len = compress(Cursor::new(&data), CompressOptions::default())?.len();
         let decompressed_len = data.len();
 
         let mut compressed = vec![0; compressed_len];
         let mut decompressed = vec![0; decompressed_len];
 
         // compress_into
-        let n_bytes = compress_into(&data, &mut compressed, CompressionLevel::Three, Codec::Gzip)?;
+        let n_bytes = compress_into(&data, &mut compressed, CompressOptions::default())?;
         assert_eq!(n_bytes, compressed_len);
 
         // decompress_into
@@ -504,15 +1396,14 @@ pub mod tests {
     fn large_round_trip_into() -> Result<()> {
         let data = gen_large_data();
 
-        let compressed_len =
-            compress(Cursor::new(&data), CompressionLevel::Three, Codec::Gzip)?.len();
+        let compressed_len = compress(Cursor::new(&data), CompressOptions::default())?.len();
         let decompressed_len = data.len();
 
         let mut compressed = vec![0; compressed_len];
         let mut decompressed = vec![0; decompressed_len];
 
         // compress_into
-        let n_bytes = compress_into(&data, &mut compressed, CompressionLevel::Three, Codec::Gzip)?;
+        let n_bytes = compress_into(&data, &mut compressed, CompressOptions::default())?;
         assert!(n_bytes < data.len());
 
         // decompress_into
@@ -524,18 +1415,350 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    fn level_two_round_trip_into() -> Result<()> {
+        let data = gen_large_data();
+        let mut compressed = vec![0; data.len()];
+        let opts = CompressOptions::new(CompressionLevel::Two, Codec::Gzip);
+        let n_bytes = compress_into(&data, &mut compressed, opts)?;
+
+        let decompressed = decompress(&compressed[..n_bytes], Codec::Gzip)?;
+        assert!(same_same(&data, &decompressed));
+        Ok(())
+    }
+
+    #[test]
+    fn level_buf_size_round_trip() -> Result<()> {
+        let data = gen_large_data();
+        let levels = [
+            CompressionLevel::Zero,
+            CompressionLevel::One,
+            CompressionLevel::Two,
+            CompressionLevel::Three,
+        ];
+        let sizes = [
+            LevelBufSize::Min,
+            LevelBufSize::Small,
+            LevelBufSize::Medium,
+            LevelBufSize::Large,
+            LevelBufSize::ExtraLarge,
+            LevelBufSize::Default,
+        ];
+        for level in levels {
+            for size in sizes {
+                let opts = CompressOptions::new(level, Codec::Gzip).level_buf_size(size);
+                let compressed = compress(Cursor::new(&data), opts)?;
+                let decompressed = decompress(Cursor::new(compressed), Codec::Gzip)?;
+                assert!(same_same(&data, &decompressed));
+            }
+        }
+        Ok(())
+    }
+
+    fn random_data(len: usize) -> Vec<u8> {
+        (0..len).map(|_| rand::random::<u8>()).collect()
+    }
+
+    #[test]
+    fn compress_bound_fits() -> Result<()> {
+        let codecs = [
+            Codec::Gzip,
+            Codec::Zlib,
+            Codec::Deflate,
+            Codec::GzipNoHdr,
+            Codec::ZlibNoHdr,
+        ];
+        for len in [0, 1, STORED_BLOCK_MAX_LEN, STORED_BLOCK_MAX_LEN * 3 + 7] {
+            let data = random_data(len);
+            for codec in codecs {
+                let bound = compress_bound(len, codec);
+                let mut compressed = vec![0u8; bound];
+                let opts =
+                    CompressOptions::new(CompressionLevel::Three, codec).stored_fallback(true);
+                let n = compress_into(&data, &mut compressed, opts)?;
+                assert!(n <= bound);
+
+                let verify = match codec {
+                    Codec::GzipNoHdr => Codec::GzipNoHdrVer,
+                    Codec::ZlibNoHdr => Codec::ZlibNoHdrVer,
+                    codec => codec,
+                };
+                let decompressed = decompress(&compressed[..n], verify)?;
+                assert!(same_same(&data, &decompressed));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn stored_fallback_needs_bound() -> Result<()> {
+        let data = random_data(1000);
+        let mut compressed = vec![0u8; data.len()];
+        let opts = CompressOptions::new(CompressionLevel::One, Codec::Gzip).stored_fallback(true);
+        match compress_into(&data, &mut compressed, opts) {
+            Err(Error::CompressionError(CompCode::StatelessOverflow)) => (),
+            r => panic!("Expected StatelessOverflow, got {:?}", r),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn stored_blocks_flate2_compat() -> Result<()> {
+        let data = random_data(STORED_BLOCK_MAX_LEN * 2 + 1);
+        for codec in [Codec::Gzip, Codec::Zlib, Codec::Deflate] {
+            let header = match codec {
+                Codec::Gzip => GzipHeader::new().to_bytes(),
+                Codec::Zlib => ZlibHeader::new().to_bytes(),
+                _ => vec![],
+            };
+            let mut compressed = vec![0u8; compress_bound(data.len(), codec)];
+            let n = compress_stored(&data, &mut compressed, &header, codec)?;
+            let compressed = &compressed[..n];
+
+            let mut decompressed = vec![];
+            match codec {
+                Codec::Gzip => {
+                    flate2::read::GzDecoder::new(compressed).read_to_end(&mut decompressed)
+                }
+                Codec::Zlib => {
+                    flate2::read::ZlibDecoder::new(compressed).read_to_end(&mut decompressed)
+                }
+                _ => flate2::read::DeflateDecoder::new(compressed).read_to_end(&mut decompressed),
+            }?;
+            assert!(same_same(&data, &decompressed));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn compress_to_vec_round_trip() -> Result<()> {
+        let data = gen_large_data();
+        let header = GzipHeader::new().name("data.txt").comment("a comment");
+        let opts = CompressOptions::new(CompressionLevel::Three, Codec::Gzip).gzip_header(header);
+        let compressed = compress_to_vec(&data, opts)?;
+        assert!(compressed.len() < data.len());
+        assert!(same_same(
+            &data,
+            &decompress(compressed.as_slice(), Codec::Gzip)?
+        ));
+
+        // incompressible, with a header longer than the generic one
+        let data = random_data(1000);
+        let header = GzipHeader::new().name("x".repeat(100));
+        let opts =
+            CompressOptions::new(CompressionLevel::Three, Codec::Gzip).gzip_header(header.clone());
+        let compressed = compress_to_vec(&data, opts)?;
+        let (parsed, _) = GzipHeader::parse(&compressed)?;
+        assert_eq!(parsed, header);
+        assert!(same_same(
+            &data,
+            &decompress(compressed.as_slice(), Codec::Gzip)?
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn decompress_into_reports_required_size() -> Result<()> {
+        let data = gen_large_data();
+        for codec in [Codec::Gzip, Codec::Zlib, Codec::Deflate] {
+            let opts = CompressOptions::new(CompressionLevel::Three, codec);
+            let compressed = compress_to_vec(&data, opts)?;
+
+            let mut decompressed = vec![0u8; data.len() / 2];
+            match decompress_into(&compressed, &mut decompressed, codec) {
+                Err(Error::OutputTooSmall(required)) => assert_eq!(required, data.len()),
+                r => panic!("Expected OutputTooSmall, got {:?}", r),
+            }
+
+            // likewise when decompressing statefully
+            let opts = DecompressOptions::new(codec).checksum_policy(ChecksumPolicy::Skip);
+            match decompress_into(&compressed, &mut decompressed, opts) {
+                Err(Error::OutputTooSmall(required)) => assert_eq!(required, data.len()),
+                r => panic!("Expected OutputTooSmall, got {:?}", r),
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn decompress_to_vec_grows() -> Result<()> {
+        let data = gen_large_data();
+        for codec in [Codec::Gzip, Codec::Zlib, Codec::Deflate] {
+            let opts = CompressOptions::new(CompressionLevel::Three, codec);
+            let compressed = compress_to_vec(&data, opts)?;
+
+            let decompressed = decompress_to_vec(&compressed, codec)?;
+            assert!(same_same(&data, &decompressed));
+
+            for size_hint in [0, 1, data.len() - 1, data.len() * 2] {
+                let decompressed = decompress_to_vec_with_hint(&compressed, codec, size_hint)?;
+                assert!(same_same(&data, &decompressed));
+            }
+        }
+        assert!(decompress_to_vec(&[], Codec::Gzip)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn decompress_to_vec_gzip_hint() -> Result<()> {
+        let data = gen_large_data();
+        let opts = CompressOptions::new(CompressionLevel::Three, Codec::Gzip);
+        let mut compressed = compress_to_vec(&data, opts.clone())?;
+        assert_eq!(gzip_size_hint(&compressed), Some(data.len()));
+        assert_eq!(
+            decompress_to_vec(&compressed, Codec::Gzip)?.capacity(),
+            data.len()
+        );
+
+        // the hint only covers the last member, the rest is grown into
+        compressed.extend(compress_to_vec(b"foobar", opts)?);
+        assert_eq!(gzip_size_hint(&compressed), Some(6));
+        let decompressed = decompress_to_vec(&compressed, Codec::Gzip)?;
+        assert!(same_same(&decompressed[..data.len()], &data));
+        assert_eq!(&decompressed[data.len()..], b"foobar");
+
+        // cut short
+        let compressed = &compressed[..compressed.len() - 20];
+        match decompress_to_vec(compressed, Codec::Gzip) {
+            Err(Error::DecompressionError(DecompCode::EndInput)) => (),
+            r => panic!("Expected EndInput, got {:?}", r),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn decompress_to_vec_forged_hint() -> Result<()> {
+        let mut compressed = compress_to_vec(b"foobar", CompressOptions::default())?;
+        let len = compressed.len();
+        compressed[len - 4..].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(gzip_size_hint(&compressed), Some(u32::MAX as usize));
+
+        // allocates no more than the input could hold
+        let opts = DecompressOptions::new(Codec::Gzip).checksum_policy(ChecksumPolicy::Skip);
+        let decompressed = decompress_to_vec(&compressed, opts)?;
+        assert_eq!(decompressed, b"foobar");
+        assert!(decompressed.capacity() <= len * 1032);
+        assert!(decompress_to_vec(&compressed, Codec::Gzip).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn decompress_single_member() -> Result<()> {
+        let mut compressed = compress_to_vec(b"foo", CompressOptions::default())?;
+        compressed.extend(compress_to_vec(b"bar", CompressOptions::default())?);
+
+        let opts = DecompressOptions::new(Codec::Gzip).multi_member(false);
+        assert_eq!(decompress(compressed.as_slice(), opts.clone())?, b"foo");
+        assert_eq!(decompress_to_vec(&compressed, opts)?, b"foo");
+        assert_eq!(decompress_to_vec(&compressed, Codec::Gzip)?, b"foobar");
+        Ok(())
+    }
+
+    #[test]
+    fn decompress_to_vec_trailing_data() -> Result<()> {
+        let mut compressed = compress_to_vec(b"foo", CompressOptions::default())?;
+        let len = compressed.len();
+        compressed.extend([0u8; 100]);
+
+        match decompress_to_vec(&compressed, Codec::Gzip) {
+            Err(Error::TrailingData(offset)) => assert_eq!(offset, len as u64),
+            r => panic!("Expected TrailingData, got {:?}", r),
+        }
+        for policy in [TrailingData::Ignore, TrailingData::Stop] {
+            let opts = DecompressOptions::new(Codec::Gzip).trailing_data(policy);
+            assert_eq!(decompress_to_vec(&compressed, opts)?, b"foo");
+        }
+
+        // a single member followed by anything is fine, unless asked otherwise
+        let opts = DecompressOptions::new(Codec::Gzip).multi_member(false);
+        assert_eq!(decompress_to_vec(&compressed, opts.clone())?, b"foo");
+        let opts = opts.trailing_data(TrailingData::Error);
+        assert!(decompress_to_vec(&compressed, opts).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn compress_decompress_streams() -> Result<()> {
+        let data = gen_large_data();
+        for codec in [Codec::Gzip, Codec::Zlib, Codec::Deflate] {
+            let mut compress = Compress::new(CompressOptions::new(CompressionLevel::One, codec));
+            let mut compressed = vec![];
+            let mut buf = [0u8; 100];
+
+            // uneven input, a sync flush half way and small output
+            for (i, chunk) in data.chunks(777).enumerate() {
+                let flush = match i == data.len() / 777 / 2 {
+                    true => FlushFlags::SyncFlush,
+                    false => FlushFlags::NoFlush,
+                };
+                let mut input = chunk;
+                loop {
+                    let status = compress.compress(input, &mut buf, flush)?;
+                    input = &input[status.consumed..];
+                    compressed.extend_from_slice(&buf[..status.produced]);
+                    if input.is_empty() && status.produced < buf.len() {
+                        break;
+                    }
+                }
+            }
+            loop {
+                let status = compress.finish(&[], &mut buf)?;
+                compressed.extend_from_slice(&buf[..status.produced]);
+                if status.stream_end {
+                    break;
+                }
+            }
+            assert!(compress.is_finished());
+            assert_eq!(compress.total_in(), data.len());
+            assert_eq!(compress.total_out(), compressed.len());
+
+            let mut decompress = Decompress::new(codec);
+            let mut decompressed = vec![];
+            for chunk in compressed.chunks(33) {
+                let mut input = chunk;
+                loop {
+                    let status = decompress.decompress(input, &mut buf)?;
+                    input = &input[status.consumed..];
+                    decompressed.extend_from_slice(&buf[..status.produced]);
+                    if input.is_empty() && status.produced < buf.len() {
+                        break;
+                    }
+                }
+            }
+            assert!(decompress.is_finished());
+            assert_eq!(decompress.total_in(), compressed.len());
+            assert_eq!(decompress.total_out(), data.len());
+            assert!(same_same(&data, &decompressed));
+
+            // and again once reset
+            compress.reset();
+            let mut out = vec![0u8; compress_bound(6, codec) + 100];
+            let status = compress.finish(b"foobar", &mut out)?;
+            assert!(status.stream_end);
+            assert_eq!(
+                decompress_to_vec(&out[..status.produced], codec)?,
+                b"foobar"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn compression_level_try_from() {
+        assert_eq!(
+            CompressionLevel::try_from(2).unwrap(),
+            CompressionLevel::Two
+        );
+        assert!(CompressionLevel::try_from(4).is_err());
+    }
+
     #[test]
     fn flate2_zlib_compat_compress_into() {
         let data = b"foobar";
 
         let mut compressed = vec![0u8; 100];
-        let n = compress_into(
-            data.as_slice(),
-            &mut compressed,
-            CompressionLevel::One,
-            Codec::Zlib,
-        )
-        .unwrap();
+        let opts = CompressOptions::new(CompressionLevel::One, Codec::Zlib);
+        let n = compress_into(data.as_slice(), &mut compressed, opts).unwrap();
 
         let mut decompressed = vec![];
         let mut decoder = flate2::read::ZlibDecoder::new(&compressed[..n]);
@@ -547,7 +1770,8 @@ pub mod tests {
     fn flate2_zlib_compat_compress() {
         let data = b"foobar";
 
-        let compressed = compress(data.as_slice(), CompressionLevel::One, Codec::Zlib).unwrap();
+        let opts = CompressOptions::new(CompressionLevel::One, Codec::Zlib);
+        let compressed = compress(data.as_slice(), opts).unwrap();
 
         let mut decompressed = vec![];
         let mut decoder = flate2::read::ZlibDecoder::new(compressed.as_slice());
@@ -581,4 +1805,369 @@ pub mod tests {
         assert_eq!(n, data.len());
         assert_eq!(data, decompressed.as_slice());
     }
+
+    #[test]
+    fn dictionary_round_trip() -> Result<()> {
+        let data = b"{\"id\": 42, \"temp\": 21.5, \"status\": \"ok\"}";
+        for codec in [Codec::Deflate, Codec::Gzip, Codec::Zlib] {
+            let opts =
+                CompressOptions::new(CompressionLevel::Three, codec).dictionary(dictionary());
+            let compressed = compress(data.as_slice(), opts.clone())?;
+
+            // using the dictionary should make a difference
+            let plain = compress(
+                data.as_slice(),
+                CompressOptions::new(CompressionLevel::Three, codec),
+            )?;
+            assert!(compressed.len() < plain.len());
+
+            let opts = DecompressOptions::new(codec).dictionary(dictionary());
+            let decompressed = decompress(compressed.as_slice(), opts.clone())?;
+            assert_eq!(data, decompressed.as_slice());
+
+            let mut decompressed = vec![0u8; data.len()];
+            let n = decompress_into(&compressed, &mut decompressed, opts)?;
+            assert_eq!(data, &decompressed[..n]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn dictionary_round_trip_into() -> Result<()> {
+        let data = gen_large_data();
+        let opts =
+            CompressOptions::new(CompressionLevel::One, Codec::Gzip).dictionary(dictionary());
+        let mut compressed = vec![0u8; data.len()];
+        let n = compress_into(&data, &mut compressed, opts)?;
+
+        let mut decompressed = vec![0u8; data.len()];
+        let opts = DecompressOptions::new(Codec::Gzip).dictionary(dictionary());
+        let n = decompress_into(&compressed[..n], &mut decompressed, opts)?;
+        assert!(same_same(&data, &decompressed[..n]));
+        Ok(())
+    }
+
+    #[test]
+    fn zlib_dictionary_lookup() -> Result<()> {
+        let data = b"{\"id\": 7, \"temp\": 21.1, \"status\": \"ok\"}";
+        let opts =
+            CompressOptions::new(CompressionLevel::Three, Codec::Zlib).dictionary(dictionary());
+        let compressed = compress(data.as_slice(), opts)?;
+
+        // FDICT set and the id is the adler32 of the dictionary
+        assert_ne!(compressed[1] & 0x20, 0);
+        let dict_id = u32::from_be_bytes(compressed[2..6].try_into().unwrap());
+        assert_eq!(dict_id, adler32(&dictionary()));
+
+        let opts = DecompressOptions::new(Codec::Zlib)
+            .dictionary_lookup(move |id| (id == dict_id).then(|| Arc::from(dictionary())));
+        let decompressed = decompress(compressed.as_slice(), opts)?;
+        assert_eq!(data, decompressed.as_slice());
+        Ok(())
+    }
+
+    #[test]
+    fn zlib_missing_dictionary() -> Result<()> {
+        let data = b"{\"id\": 7, \"temp\": 21.1, \"status\": \"ok\"}";
+        let opts =
+            CompressOptions::new(CompressionLevel::Three, Codec::Zlib).dictionary(dictionary());
+        let compressed = compress(data.as_slice(), opts)?;
+
+        let mut decompressed = vec![0u8; data.len()];
+        let wrong = DecompressOptions::new(Codec::Zlib).dictionary(b"not it".to_vec());
+        match decompress_into(&compressed, &mut decompressed, wrong) {
+            Err(Error::DecompressionError(DecompCode::NeedDict)) => (),
+            r => panic!("Expected NeedDict, got {:?}", r),
+        }
+        match decompress(compressed.as_slice(), Codec::Zlib) {
+            Err(err) => assert!(err.to_string().contains("NeedDict")),
+            r => panic!("Expected NeedDict, got {:?}", r),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn write_decoder_dictionary() -> Result<()> {
+        let data = gen_large_data();
+        let opts =
+            CompressOptions::new(CompressionLevel::Three, Codec::Zlib).dictionary(dictionary());
+        let compressed = compress(data.as_slice(), opts)?;
+
+        let mut decompressed = vec![];
+        let opts = DecompressOptions::new(Codec::Zlib).dictionary(dictionary());
+        let mut decoder = write::Decoder::new(&mut decompressed, opts);
+        for chunk in compressed.chunks(7) {
+            decoder.write_all(chunk)?;
+        }
+        decoder.flush()?;
+        assert!(same_same(&data, &decompressed));
+        Ok(())
+    }
+
+    // Random block repeated, so matches are only found a whole block back
+    fn far_matches(block_len: usize) -> Vec<u8> {
+        let mut x = 0x2545_f491_u32;
+        let block: Vec<u8> = (0..block_len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as u8
+            })
+            .collect();
+        block.repeat(8)
+    }
+
+    #[test]
+    fn window_bits_round_trip() -> Result<()> {
+        let data = far_matches(200);
+        for codec in [Codec::Deflate, Codec::Gzip, Codec::Zlib] {
+            let opts = CompressOptions::new(CompressionLevel::Three, codec).window_bits(9);
+            let compressed = compress(data.as_slice(), opts)?;
+            assert!(compressed.len() < data.len() / 2);
+
+            let opts = DecompressOptions::new(codec).window_bits(9);
+            let decompressed = decompress(compressed.as_slice(), opts.clone())?;
+            assert_eq!(data, decompressed);
+
+            let mut decompressed = vec![0u8; data.len()];
+            let n = decompress_into(&compressed, &mut decompressed, opts)?;
+            assert_eq!(data, &decompressed[..n]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn window_bits_zlib_header() -> Result<()> {
+        let opts = CompressOptions::new(CompressionLevel::Three, Codec::Zlib);
+        let compressed = compress(b"foobar".as_slice(), opts.clone())?;
+        assert_eq!(compressed[0], 0x78);
+
+        let compressed = compress(b"foobar".as_slice(), opts.window_bits(10))?;
+        assert_eq!(compressed[0], 0x28);
+        assert_eq!(u16::from_be_bytes([compressed[0], compressed[1]]) % 31, 0);
+
+        // their decoder is happy with it
+        let mut decompressed = vec![];
+        let mut decoder = flate2::read::ZlibDecoder::new(compressed.as_slice());
+        io::copy(&mut decoder, &mut decompressed)?;
+        assert_eq!(decompressed, b"foobar");
+        Ok(())
+    }
+
+    #[test]
+    fn window_bits_rejects_larger_window() -> Result<()> {
+        let data = far_matches(4096);
+
+        // zlib header announcing a 32KiB window
+        let compressed = compress(
+            data.as_slice(),
+            CompressOptions::new(CompressionLevel::Three, Codec::Zlib),
+        )?;
+        let opts = DecompressOptions::new(Codec::Zlib).window_bits(9);
+        match decompress_into(&compressed, &mut vec![0u8; data.len()], opts.clone()) {
+            Err(Error::DecompressionError(DecompCode::InvalidWrapper)) => (),
+            r => panic!("Expected InvalidWrapper, got {:?}", r),
+        }
+        assert!(decompress(compressed.as_slice(), opts).is_err());
+
+        // raw deflate referring 4KiB back
+        let compressed = compress(
+            data.as_slice(),
+            CompressOptions::new(CompressionLevel::Three, Codec::Deflate),
+        )?;
+        let opts = DecompressOptions::new(Codec::Deflate).window_bits(9);
+        match decompress_into(&compressed, &mut vec![0u8; data.len()], opts.clone()) {
+            Err(Error::DecompressionError(DecompCode::InvalidLoopBack)) => (),
+            r => panic!("Expected InvalidLoopBack, got {:?}", r),
+        }
+        assert!(decompress(compressed.as_slice(), opts).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn no_hdr_codecs_round_trip() -> Result<()> {
+        let data = gen_large_data();
+        for (codec, wrapped, hdr_len, verify) in [
+            (Codec::GzipNoHdr, Codec::Gzip, 10, Codec::GzipNoHdrVer),
+            (Codec::ZlibNoHdr, Codec::Zlib, 2, Codec::ZlibNoHdrVer),
+        ] {
+            let opts = CompressOptions::new(CompressionLevel::Three, codec);
+            let compressed = compress(Cursor::new(&data), opts)?;
+
+            // the same stream, just without the header
+            let opts = CompressOptions::new(CompressionLevel::Three, wrapped);
+            let full = compress(Cursor::new(&data), opts)?;
+            assert_eq!(&full[hdr_len..], compressed.as_slice());
+
+            // *Ver also reads the trailer
+            let decompressed = decompress(compressed.as_slice(), verify)?;
+            assert!(same_same(&data, &decompressed));
+
+            let mut decompressed = vec![0u8; data.len()];
+            let n = decompress_into(&compressed, &mut decompressed, verify)?;
+            assert!(same_same(&data, &decompressed[..n]));
+
+            let mut decompressed = vec![];
+            let mut decoder = write::Decoder::new(&mut decompressed, verify);
+            decoder.write_all(&compressed)?;
+            drop(decoder);
+            assert!(same_same(&data, &decompressed));
+
+            // otherwise the trailer is left alone
+            let trailer_len = if codec == Codec::GzipNoHdr { 8 } else { 4 };
+            let body = &compressed[..compressed.len() - trailer_len];
+            let decompressed = decompress(body, codec)?;
+            assert!(same_same(&data, &decompressed));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn no_hdr_ver_bad_trailer() -> Result<()> {
+        for codec in [Codec::GzipNoHdr, Codec::ZlibNoHdr] {
+            let opts = CompressOptions::new(CompressionLevel::Three, codec);
+            let mut compressed = compress(b"foobar".as_slice(), opts)?;
+            let verify = match codec {
+                Codec::GzipNoHdr => Codec::GzipNoHdrVer,
+                _ => Codec::ZlibNoHdrVer,
+            };
+
+            // corrupt the checksum
+            let i = match codec {
+                Codec::GzipNoHdr => compressed.len() - 8,
+                _ => compressed.len() - 1,
+            };
+            compressed[i] ^= 0xff;
+
+            let mut decompressed = vec![0u8; 6];
+            match decompress_into(&compressed, &mut decompressed, verify) {
+                Err(Error::DecompressionError(DecompCode::IncorrectChecksum)) => (),
+                r => panic!("Expected IncorrectChecksum, got {:?}", r),
+            }
+            assert!(decompress(compressed.as_slice(), verify).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn no_hdr_with_own_header() -> Result<()> {
+        // our own gzip header, followed by what isa-l writes
+        let data = b"Hello, World!";
+        let mut compressed = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 3];
+        let opts = CompressOptions::new(CompressionLevel::Three, Codec::GzipNoHdr);
+        compressed.extend(compress(data.as_slice(), opts)?);
+
+        let mut decoder = flate2::read::GzDecoder::new(compressed.as_slice());
+        let mut decompressed = vec![];
+        io::copy(&mut decoder, &mut decompressed)?;
+        assert_eq!(decompressed, data);
+        assert_eq!(decompress(compressed.as_slice(), Codec::Gzip)?, data);
+        Ok(())
+    }
+
+    // compressed `data` with its checksum corrupted
+    fn bad_checksum(data: &[u8], codec: Codec) -> Result<Vec<u8>> {
+        let opts = CompressOptions::new(CompressionLevel::Three, codec);
+        let mut compressed = compress(data, opts)?;
+        let i = match codec {
+            Codec::Gzip => compressed.len() - 8,
+            _ => compressed.len() - 1,
+        };
+        compressed[i] ^= 0xff;
+        Ok(compressed)
+    }
+
+    #[test]
+    fn checksum_policy_skip() -> Result<()> {
+        let data = gen_large_data();
+        for codec in [Codec::Gzip, Codec::Zlib] {
+            let compressed = bad_checksum(&data, codec)?;
+            assert!(decompress(compressed.as_slice(), codec).is_err());
+
+            let opts = DecompressOptions::new(codec).checksum_policy(ChecksumPolicy::Skip);
+            let decompressed = decompress(compressed.as_slice(), opts.clone())?;
+            assert!(same_same(&data, &decompressed));
+
+            let mut decompressed = vec![0u8; data.len()];
+            let n = decompress_into(&compressed, &mut decompressed, opts)?;
+            assert_eq!(n, data.len());
+            assert!(same_same(&data, &decompressed));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn checksum_policy_skip_multi_member() -> Result<()> {
+        let mut compressed = bad_checksum(b"foo", Codec::Gzip)?;
+        compressed.extend(bad_checksum(b"bar", Codec::Gzip)?);
+
+        let opts = DecompressOptions::new(Codec::Gzip).checksum_policy(ChecksumPolicy::Skip);
+        assert_eq!(decompress(compressed.as_slice(), opts)?, b"foobar");
+        Ok(())
+    }
+
+    #[test]
+    fn checksum_policy_report() -> Result<()> {
+        let data = b"Hello, World!";
+        for codec in [Codec::Gzip, Codec::Zlib] {
+            let reported = Arc::new(std::sync::Mutex::new(vec![]));
+            let opts = DecompressOptions::new(codec)
+                .checksum_policy(ChecksumPolicy::Report)
+                .on_checksum_mismatch({
+                    let reported = reported.clone();
+                    move |mismatch| reported.lock().unwrap().push(*mismatch)
+                });
+
+            // an intact stream reports nothing
+            let compressed = compress(
+                data.as_slice(),
+                CompressOptions::new(CompressionLevel::Three, codec),
+            )?;
+            assert_eq!(decompress(compressed.as_slice(), opts.clone())?, data);
+            assert!(reported.lock().unwrap().is_empty());
+
+            let compressed = bad_checksum(data, codec)?;
+            let mut decoder = read::Decoder::new(compressed.as_slice(), opts.clone());
+            let mut decompressed = vec![];
+            io::copy(&mut decoder, &mut decompressed)?;
+            assert_eq!(decompressed, data);
+            assert_eq!(
+                decoder.checksum_mismatches(),
+                reported.lock().unwrap().as_slice()
+            );
+
+            let mismatch = decoder.checksum_mismatches()[0];
+            assert_eq!(mismatch.expected ^ mismatch.computed, 0xff);
+            match codec {
+                Codec::Gzip => {
+                    assert_eq!(mismatch.expected_size, Some(data.len() as u32));
+                    assert_eq!(mismatch.computed_size, Some(data.len() as u32));
+                }
+                _ => assert_eq!(mismatch.expected_size, None),
+            }
+
+            let mut decompressed = vec![0u8; data.len()];
+            decompress_into(&compressed, &mut decompressed, opts)?;
+            assert_eq!(decompressed, data);
+            assert_eq!(reported.lock().unwrap().len(), 2);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn checksum_policy_write_decoder() -> Result<()> {
+        let compressed = bad_checksum(b"Hello, World!", Codec::Zlib)?;
+        let opts = DecompressOptions::new(Codec::Zlib).checksum_policy(ChecksumPolicy::Report);
+        let mut decompressed = vec![];
+        let mut decoder = write::Decoder::new(&mut decompressed, opts);
+        // one byte at a time, so the trailer is split across writes
+        for byte in compressed.iter() {
+            decoder.write_all(&[*byte])?;
+        }
+        assert_eq!(decoder.checksum_mismatches().len(), 1);
+        drop(decoder);
+        assert_eq!(decompressed, b"Hello, World!");
+        Ok(())
+    }
 }
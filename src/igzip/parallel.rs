@@ -0,0 +1,798 @@
+//! Gzip compression and decompression spread over several threads
+//!
+//! For compression, input is split into blocks, each compressed on its own thread as
+//! raw deflate with the 32KiB before it as dictionary and ending in a full flush, so
+//! the compressed blocks can be concatenated into a single standard gzip member. Their
+//! CRC32s are combined for the trailer.
+//!
+//! Decompression is parallel across members, each of which is inflated on its own thread.
+use crate::igzip::*;
+use std::collections::VecDeque;
+use std::io::{self, Read};
+use std::mem;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// Default size of the blocks compressed by each thread, same as pigz
+pub const PARALLEL_BLOCK_SIZE: usize = 128 * 1024;
+
+// History a block is compressed with, the deflate window
+const DICT_SIZE: usize = 32 * 1024;
+
+type Task = Box<dyn FnOnce() + Send>;
+
+// Threads running the tasks handed to them, until dropped
+struct Pool {
+    tasks: Option<mpsc::Sender<Task>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Pool {
+    fn new(threads: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<Task>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..threads)
+            .map(|_| {
+                let receiver = receiver.clone();
+                thread::spawn(move || loop {
+                    // the lock is only held while waiting for the next task
+                    let task = match receiver.lock() {
+                        Ok(receiver) => receiver.recv(),
+                        Err(_) => break,
+                    };
+                    match task {
+                        Ok(task) => task(),
+                        Err(_) => break,
+                    }
+                })
+            })
+            .collect();
+        Self {
+            tasks: Some(sender),
+            workers,
+        }
+    }
+
+    // Run `f` on one of the threads, its result arriving on the returned receiver
+    fn spawn<T: Send + 'static>(
+        &self,
+        f: impl FnOnce() -> T + Send + 'static,
+    ) -> mpsc::Receiver<T> {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let task: Task = Box::new(move || {
+            let _ = sender.send(f());
+        });
+        // The workers only exit once `tasks` is dropped, so this can't fail
+        let _ = self.tasks.as_ref().unwrap().send(task);
+        receiver
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        // Closing the channel lets the workers exit
+        self.tasks.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+// A compressed block, with the CRC32 and length of its input
+struct Block {
+    compressed: Vec<u8>,
+    crc: u32,
+    len: usize,
+}
+
+/// Parallel gzip compression for output streams implementing `std::io::Write`, in
+/// the manner of pigz.
+///
+/// Blocks are compressed by a pool of threads started on the first write, while
+/// the output is written in order on the calling thread. The result is a single
+/// gzip member readable by any gzip decoder.
+///
+/// `flush` ends the current block early with a full flush and waits for all blocks
+/// so far to be written. The member is ended with `try_finish` or `finish`, or when
+/// the `ParallelGzipEncoder` is dropped; writing after `try_finish` begins a new member.
+///
+/// Example
+/// -------
+/// ```
+/// use std::io::Write;
+/// use isal::igzip::{parallel::ParallelGzipEncoder, CompressionLevel, decompress, Codec};
+///
+/// let data = b"Hello, World!".repeat(100_000);
+///
+/// let mut encoder = ParallelGzipEncoder::new(vec![], CompressionLevel::Three).threads(4);
+/// encoder.write_all(&data).unwrap();
+/// let compressed = encoder.finish().unwrap();
+///
+/// let decompressed = decompress(compressed.as_slice(), Codec::Gzip).unwrap();
+/// assert_eq!(decompressed, data);
+/// ```
+pub struct ParallelGzipEncoder<W: io::Write> {
+    // only taken by `finish`
+    inner: Option<W>,
+    level: CompressionLevel,
+    header: GzipHeader,
+    threads: usize,
+    block_size: usize,
+    buf: Vec<u8>,
+    // last 32KiB of input handed out so far
+    dict: Vec<u8>,
+    // started on the first block
+    pool: Option<Pool>,
+    // results of blocks handed out, in order
+    pending: VecDeque<mpsc::Receiver<Result<Block>>>,
+    header_written: bool,
+    crc: u32,
+    len: usize,
+    total_in: usize,
+    total_out: usize,
+    finished: bool,
+}
+
+impl<W: io::Write> ParallelGzipEncoder<W> {
+    /// Create a new `ParallelGzipEncoder`, with as many threads as there are cores
+    /// and `PARALLEL_BLOCK_SIZE` blocks.
+    pub fn new(writer: W, level: CompressionLevel) -> Self {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        Self {
+            inner: Some(writer),
+            level,
+            header: GzipHeader::default(),
+            threads,
+            block_size: PARALLEL_BLOCK_SIZE,
+            buf: vec![],
+            dict: vec![],
+            pool: None,
+            pending: VecDeque::new(),
+            header_written: false,
+            crc: 0,
+            len: 0,
+            total_in: 0,
+            total_out: 0,
+            finished: false,
+        }
+    }
+
+    /// Number of threads compressing blocks, at least one; fixed once writing begins.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Size of the blocks each thread compresses, larger blocks compress slightly
+    /// better at the cost of memory; fixed once writing begins.
+    pub fn block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size.max(1);
+        self
+    }
+
+    /// Set the gzip header written at the start of the member.
+    pub fn gzip_header(mut self, header: GzipHeader) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Mutable reference to underlying writer, not advisable to modify during writing.
+    pub fn get_ref_mut(&mut self) -> &mut W {
+        self.inner.as_mut().unwrap()
+    }
+
+    // Reference to underlying writer
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    /// End the member, writing the trailer, without giving up the inner writer.
+    ///
+    /// Does nothing if already finished, unless more data was written since, which
+    /// begins a new member to be finished.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.dispatch(true)?;
+        while !self.pending.is_empty() {
+            self.write_block()?;
+        }
+        let mut trailer = self.crc.to_le_bytes().to_vec();
+        trailer.extend_from_slice(&(self.len as u32).to_le_bytes());
+        self.write_out(&trailer)?;
+        self.finished = true;
+        Ok(())
+    }
+
+    /// End the member and return the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.inner.take().unwrap())
+    }
+
+    /// total bytes written to the writer, inclusive of all members
+    pub fn total_out(&self) -> usize {
+        self.total_out
+    }
+
+    /// total bytes processed, inclusive of all members
+    pub fn total_in(&self) -> usize {
+        self.total_in
+    }
+
+    fn write_out(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.inner.as_mut().unwrap().write_all(buf)?;
+        self.total_out += buf.len();
+        Ok(())
+    }
+
+    // Hand the buffered input to the pool as the next block
+    fn dispatch(&mut self, last: bool) -> io::Result<()> {
+        if !self.header_written {
+            let header = self.header.to_bytes();
+            self.write_out(&header)?;
+            self.header_written = true;
+        }
+
+        // This block follows the last 32KiB before it, which the next one follows in turn
+        let data = mem::take(&mut self.buf);
+        let dict = self.dict.clone();
+        self.dict
+            .extend_from_slice(&data[data.len().saturating_sub(DICT_SIZE)..]);
+        let excess = self.dict.len().saturating_sub(DICT_SIZE);
+        self.dict.drain(..excess);
+
+        let (threads, level) = (self.threads, self.level);
+        let pool = self.pool.get_or_insert_with(|| Pool::new(threads));
+        let receiver = pool.spawn(move || compress_block(&data, &dict, last, level));
+        self.pending.push_back(receiver);
+
+        // Keep the pool busy without holding too many blocks in memory
+        while self.pending.len() > self.threads * 2 {
+            self.write_block()?;
+        }
+        Ok(())
+    }
+
+    // Wait for the oldest block and write it out
+    fn write_block(&mut self) -> io::Result<()> {
+        let receiver = self.pending.pop_front().unwrap();
+        let block = receiver
+            .recv()
+            .map_err(|_| io::Error::other("compression thread panicked"))??;
+        self.crc = crc32_combine(self.crc, block.crc, block.len as u64);
+        self.write_out(&block.compressed)
+    }
+}
+
+// Compress a block as raw deflate following `dict`, ending it on a byte boundary
+// with a full flush, or with the final deflate block if `last`.
+fn compress_block(data: &[u8], dict: &[u8], last: bool, level: CompressionLevel) -> Result<Block> {
+    let mut options = CompressOptions::new(level, Codec::Deflate);
+    if !dict.is_empty() {
+        options = options.dictionary(dict);
+    }
+    let mut compress = Compress::new(options);
+
+    let mut compressed = vec![0u8; compress_bound(data.len(), Codec::Deflate) + 64];
+    let (mut pos, mut len) = (0, 0);
+    loop {
+        if len == compressed.len() {
+            compressed.resize(compressed.len() * 2, 0);
+        }
+        let status = match last {
+            true => compress.finish(&data[pos..], &mut compressed[len..])?,
+            false => {
+                compress.compress(&data[pos..], &mut compressed[len..], FlushFlags::FullFlush)?
+            }
+        };
+        pos += status.consumed;
+        len += status.produced;
+
+        // isa-l is done flushing once it leaves room in the output buffer
+        let done = match last {
+            true => status.stream_end,
+            false => pos == data.len() && len < compressed.len(),
+        };
+        if done {
+            break;
+        }
+    }
+    compressed.truncate(len);
+    Ok(Block {
+        compressed,
+        crc: crc32(data),
+        len: data.len(),
+    })
+}
+
+impl<W: io::Write> io::Write for ParallelGzipEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.finished {
+            // Prep for the next member
+            self.header_written = false;
+            self.dict.clear();
+            self.crc = 0;
+            self.len = 0;
+            self.finished = false;
+        }
+        let n = std::cmp::min(buf.len(), self.block_size - self.buf.len());
+        self.buf.extend_from_slice(&buf[..n]);
+        self.len += n;
+        self.total_in += n;
+        if self.buf.len() == self.block_size {
+            self.dispatch(false)?;
+        }
+        Ok(n)
+    }
+
+    /// Compress and write out all data given so far, ending on a byte boundary,
+    /// and flush the inner writer. The member is not ended, see `ParallelGzipEncoder::finish`.
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.finished && !self.buf.is_empty() {
+            self.dispatch(false)?;
+        }
+        while !self.pending.is_empty() {
+            self.write_block()?;
+        }
+        self.get_ref_mut().flush()
+    }
+}
+
+impl<W: io::Write> Drop for ParallelGzipEncoder<W> {
+    fn drop(&mut self) {
+        // Errors can't be reported from here, call `finish` to see them
+        if self.inner.is_some() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+// Input read from the inner reader at a time, while looking for member boundaries
+const READ_CHUNK: u64 = 1 << 20;
+
+// Smallest possible gzip member: header, empty final block and trailer
+const MIN_MEMBER_LEN: usize = GZIP_HEADER_LEN + 2 + 8;
+
+// A member handed out to the pool, starting at `start` in the input
+struct Member {
+    start: u64,
+    output: mpsc::Receiver<Result<Option<Vec<u8>>>>,
+}
+
+/// Parallel decompression of gzip files made of many members, such as concatenated
+/// logs or BGZF, for input streams implementing `std::io::Read`.
+///
+/// Member boundaries are found ahead of decompression, exactly for BGZF blocks and
+/// otherwise by the next gzip header signature; each member is then inflated in one
+/// go on a pool of threads, its CRC32 and ISIZE verified, and the output read in order.
+/// A signature turning up inside compressed data costs time, not correctness: the
+/// member is then decompressed in sequence to find where it really ends.
+///
+/// Each member is held in memory whole, compressed and decompressed, while inflated.
+/// A file of a single member gets no faster, see `ParallelGzipEncoder` for making
+/// files which do: calling `try_finish` every so often ends a member, and writing on
+/// begins the next.
+///
+/// Example
+/// -------
+/// ```
+/// use std::io::{Read, Write};
+/// use isal::igzip::{parallel::ParallelMultiGzDecoder, CompressOptions, compress};
+///
+/// let mut compressed = vec![];
+/// for line in ["first\n", "second\n", "third\n"] {
+///     compressed.extend(compress(line.as_bytes(), CompressOptions::default()).unwrap());
+/// }
+///
+/// let mut decoder = ParallelMultiGzDecoder::new(compressed.as_slice()).threads(2);
+/// let mut decompressed = String::new();
+/// decoder.read_to_string(&mut decompressed).unwrap();
+/// assert_eq!(decompressed, "first\nsecond\nthird\n");
+/// ```
+pub struct ParallelMultiGzDecoder<R: io::Read> {
+    inner: R,
+    threads: usize,
+    // input from offset `base` on, kept from the start of the oldest member in flight
+    input: Vec<u8>,
+    base: u64,
+    eof: bool,
+    // start of the next member to hand out, and how far signatures were looked for
+    next: u64,
+    searched: u64,
+    // started on the first member
+    pool: Option<Pool>,
+    // members handed out, in order
+    pending: VecDeque<Member>,
+    out_buf: Vec<u8>,
+    out_pos: usize,
+}
+
+impl<R: io::Read> ParallelMultiGzDecoder<R> {
+    /// Create a new `ParallelMultiGzDecoder`, with as many threads as there are cores.
+    pub fn new(reader: R) -> Self {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        Self {
+            inner: reader,
+            threads,
+            input: vec![],
+            base: 0,
+            eof: false,
+            next: 0,
+            searched: 0,
+            pool: None,
+            pending: VecDeque::new(),
+            out_buf: vec![],
+            out_pos: 0,
+        }
+    }
+
+    /// Number of threads inflating members, at least one; fixed once reading begins.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Mutable reference to underlying reader, not advisable to modify during reading.
+    pub fn get_ref_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    // Reference to underlying reader
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    // Index into `input` of offset `pos`
+    fn idx(&self, pos: u64) -> usize {
+        (pos - self.base) as usize
+    }
+
+    // Read more input, returning false at the end of it
+    fn read_more(&mut self) -> io::Result<bool> {
+        let n = (&mut self.inner)
+            .take(READ_CHUNK)
+            .read_to_end(&mut self.input)?;
+        self.eof = n == 0;
+        Ok(!self.eof)
+    }
+
+    // Hand out members until enough are in flight or the input is exhausted
+    fn fill(&mut self) -> io::Result<()> {
+        // Drop input no longer needed, once that is worth moving the rest for
+        let keep = self
+            .pending
+            .front()
+            .map_or(self.next, |member| member.start);
+        let unused = self.idx(keep);
+        if unused > self.input.len() / 2 {
+            self.input.drain(..unused);
+            self.base = keep;
+            // nothing before `keep` is left to search
+            self.searched = self.searched.max(keep);
+        }
+
+        while self.pending.len() < self.threads * 2 {
+            let Some(end) = self.member_end()? else {
+                break;
+            };
+            let data = self.input[self.idx(self.next)..self.idx(end)].to_vec();
+            let threads = self.threads;
+            let pool = self.pool.get_or_insert_with(|| Pool::new(threads));
+            let output = pool.spawn(move || inflate_member(&data));
+            self.pending.push_back(Member {
+                start: self.next,
+                output,
+            });
+            self.next = end;
+        }
+        Ok(())
+    }
+
+    // Where the member starting at `next` likely ends, reading more input as needed;
+    // `None` at the end of the input.
+    fn member_end(&mut self) -> io::Result<Option<u64>> {
+        loop {
+            let start = self.idx(self.next);
+            let available = &self.input[start..];
+            if available.len() >= 2 && available[..2] != [0x1f, 0x8b] {
+                return Err(Error::TrailingData(self.next).into());
+            }
+            if let Some(len) = bgzf::block_size(available) {
+                if len <= available.len() {
+                    return Ok(Some(self.next + len as u64));
+                }
+            } else {
+                let searched = self.idx(self.searched.max(self.base));
+                let from = std::cmp::max(searched, start + MIN_MEMBER_LEN);
+                if let Some(pos) = find_member(&self.input, from) {
+                    return Ok(Some(self.base + pos as u64));
+                }
+                // signatures may straddle the end of what was read so far
+                self.searched = self.base + self.input.len().saturating_sub(3) as u64;
+            }
+            let available = available.len();
+            if !self.read_more()? {
+                return match available {
+                    0 => Ok(None),
+                    1 => Err(Error::TrailingData(self.next).into()),
+                    _ => Ok(Some(self.base + self.input.len() as u64)),
+                };
+            }
+        }
+    }
+
+    // Decompress the member starting at `start` in sequence, for when its end was
+    // guessed wrong, returning its output and where it really ends.
+    fn inflate_from(&mut self, start: u64) -> io::Result<(Vec<u8>, u64)> {
+        let opts = DecompressOptions::new(Codec::Gzip).multi_member(false);
+        let mut decomp = Decompress::new(opts);
+        let mut output = vec![];
+        let mut buf = vec![0u8; BUF_SIZE];
+        let mut pos = self.idx(start);
+        loop {
+            let status = decomp.decompress(&self.input[pos..], &mut buf)?;
+            pos += status.consumed;
+            output.extend_from_slice(&buf[..status.produced]);
+            if status.stream_end {
+                return Ok((output, self.base + pos as u64));
+            }
+            if pos == self.input.len() && status.produced < buf.len() && !self.read_more()? {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    Error::DecompressionError(DecompCode::EndInput),
+                ));
+            }
+        }
+    }
+}
+
+impl<R: io::Read> io::Read for ParallelMultiGzDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.out_pos < self.out_buf.len() {
+                let n = std::cmp::min(buf.len(), self.out_buf.len() - self.out_pos);
+                buf[..n].copy_from_slice(&self.out_buf[self.out_pos..self.out_pos + n]);
+                self.out_pos += n;
+                return Ok(n);
+            }
+
+            self.fill()?;
+            let Some(member) = self.pending.pop_front() else {
+                return Ok(0);
+            };
+            let output = member
+                .output
+                .recv()
+                .map_err(|_| io::Error::other("decompression thread panicked"))??;
+            self.out_buf = match output {
+                Some(output) => output,
+                None => {
+                    // Not where the member ends after all, so neither are those after it
+                    self.pending.clear();
+                    let (output, end) = self.inflate_from(member.start)?;
+                    self.next = end;
+                    self.searched = end;
+                    output
+                }
+            };
+            self.out_pos = 0;
+        }
+    }
+}
+
+// Offset of the next likely gzip header in `input` at or after `from`
+fn find_member(input: &[u8], from: usize) -> Option<usize> {
+    // ID1, ID2, deflate method and no reserved flags
+    input
+        .get(from..)?
+        .windows(4)
+        .position(|w| w[..3] == [0x1f, 0x8b, 8] && w[3] & 0xe0 == 0)
+        .map(|pos| from + pos)
+}
+
+// Inflate `data` as a single gzip member in one go, verifying its trailer; `None` if
+// the member doesn't end exactly where `data` does.
+fn inflate_member(data: &[u8]) -> Result<Option<Vec<u8>>> {
+    let header_len = match GzipHeader::parse_partial(data)? {
+        Some((_, len)) => len,
+        None => return Ok(None),
+    };
+    if data.len() < header_len + 8 {
+        return Ok(None);
+    }
+    let trailer = &data[data.len() - 8..];
+    let crc = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+    let size = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+
+    // ISIZE is the size modulo 2^32, and could be anything if the end was guessed
+    // wrong; deflate expands at most 1032:1
+    let body = &data[header_len..];
+    let max_len = body.len().saturating_mul(1032);
+    let mut len = std::cmp::min(size as usize, max_len);
+    loop {
+        let mut output = vec![0u8; len];
+        let mut zst = Box::new(InflateState::new());
+        // isa-l computes the CRC32, leaving the trailer to us
+        zst.0.crc_flag = isal::ISAL_GZIP_NO_HDR;
+        zst.0.next_in = body.as_ptr() as *mut _;
+        zst.0.avail_in = body.len() as _;
+        zst.0.next_out = output.as_mut_ptr();
+        zst.0.avail_out = output.len() as _;
+
+        match zst.inflate_stateless() {
+            Ok(()) => (),
+            Err(Error::DecompressionError(DecompCode::OutOverflow)) if len < max_len => {
+                len = std::cmp::min(std::cmp::max(len * 2, BUF_SIZE), max_len);
+                continue;
+            }
+            Err(Error::DecompressionError(DecompCode::EndInput)) => return Ok(None),
+            Err(err) => return Err(err),
+        }
+        if zst.0.avail_in != 8 {
+            return Ok(None);
+        }
+        if zst.0.crc != crc || zst.0.total_out != size {
+            return Err(Error::DecompressionError(DecompCode::IncorrectChecksum));
+        }
+        output.truncate(len - zst.0.avail_out as usize);
+        return Ok(Some(output));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::igzip::tests::{gen_large_data, same_same};
+    use std::io::{Read, Write};
+
+    #[test]
+    fn crc32_combine_matches() {
+        let data = gen_large_data();
+        for split in [0, 1, 1000, DICT_SIZE + 1, data.len()] {
+            let (a, b) = data.split_at(split);
+            let combined = crc32_combine(crc32(a), crc32(b), b.len() as u64);
+            assert_eq!(combined, crc32(&data));
+        }
+    }
+
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let data = gen_large_data();
+        // blocks smaller than, equal to and larger than the dictionary
+        for block_size in [1000, DICT_SIZE, PARALLEL_BLOCK_SIZE] {
+            let mut encoder = ParallelGzipEncoder::new(vec![], CompressionLevel::Three)
+                .threads(3)
+                .block_size(block_size);
+            for chunk in data.chunks(7777) {
+                encoder.write_all(chunk)?;
+            }
+            let compressed = encoder.finish()?;
+
+            let mut decompressed = vec![];
+            read::Decoder::new(compressed.as_slice(), Codec::Gzip)
+                .read_to_end(&mut decompressed)?;
+            assert!(same_same(&data, &decompressed));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn single_member() -> Result<()> {
+        let data = gen_large_data();
+        let mut encoder = ParallelGzipEncoder::new(vec![], CompressionLevel::One).threads(4);
+        encoder.write_all(&data)?;
+        encoder.flush()?;
+        encoder.write_all(&data[..1000])?;
+        let compressed = encoder.finish()?;
+
+        // flate2's GzDecoder stops after one member, so this checks there is only one
+        let mut decompressed = vec![];
+        flate2::read::GzDecoder::new(compressed.as_slice()).read_to_end(&mut decompressed)?;
+        assert_eq!(decompressed.len(), data.len() + 1000);
+        assert!(same_same(&data, &decompressed[..data.len()]));
+
+        let opts = DecompressOptions::new(Codec::Gzip).multi_member(false);
+        let mut decoder = read::Decoder::new(compressed.as_slice(), opts);
+        decoder.read_to_end(&mut vec![])?;
+        assert_eq!(decoder.member_end(), Some(compressed.len() as u64));
+        Ok(())
+    }
+
+    #[test]
+    fn empty() -> Result<()> {
+        let compressed = ParallelGzipEncoder::new(vec![], CompressionLevel::Three).finish()?;
+        assert!(decompress(compressed.as_slice(), Codec::Gzip)?.is_empty());
+        Ok(())
+    }
+
+    // Many members of `data`, one per chunk
+    fn multi_member(data: &[u8], chunk_size: usize) -> Result<Vec<u8>> {
+        let mut compressed = vec![];
+        for chunk in data.chunks(chunk_size) {
+            compressed.extend(compress(chunk, CompressOptions::default())?);
+        }
+        Ok(compressed)
+    }
+
+    #[test]
+    fn decoder_roundtrip() -> Result<()> {
+        let data = gen_large_data();
+        for chunk_size in [1000, 100_000, data.len()] {
+            let compressed = multi_member(&data, chunk_size)?;
+            let mut decompressed = vec![];
+            ParallelMultiGzDecoder::new(compressed.as_slice())
+                .threads(3)
+                .read_to_end(&mut decompressed)?;
+            assert!(same_same(&data, &decompressed));
+        }
+
+        let mut decompressed = vec![];
+        ParallelMultiGzDecoder::new([].as_slice()).read_to_end(&mut decompressed)?;
+        assert!(decompressed.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn decoder_bgzf() -> Result<()> {
+        let data = gen_large_data();
+        let mut writer = bgzf::Writer::new(vec![], CompressionLevel::Three);
+        writer.write_all(&data)?;
+        let compressed = writer.finish()?;
+        let mut decompressed = vec![];
+        ParallelMultiGzDecoder::new(compressed.as_slice()).read_to_end(&mut decompressed)?;
+        assert!(same_same(&data, &decompressed));
+        Ok(())
+    }
+
+    #[test]
+    fn decoder_signature_in_data() -> Result<()> {
+        // Stored blocks keep a gzip header signature in the data as is
+        let mut data = gen_large_data();
+        data[50_000..50_010]
+            .copy_from_slice(&compress(b"".as_slice(), CompressOptions::default())?[..10]);
+        let mut compressed = vec![];
+        for chunk in data.chunks(100_000) {
+            let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::none());
+            encoder.write_all(chunk)?;
+            compressed.extend(encoder.finish()?);
+        }
+        let mut decompressed = vec![];
+        ParallelMultiGzDecoder::new(compressed.as_slice())
+            .threads(2)
+            .read_to_end(&mut decompressed)?;
+        assert!(same_same(&data, &decompressed));
+        Ok(())
+    }
+
+    #[test]
+    fn decoder_errors() -> Result<()> {
+        let data = gen_large_data();
+        let compressed = multi_member(&data, 100_000)?;
+
+        // CRC32 of the first member
+        let mut corrupt = compressed.clone();
+        let first_end = find_member(&compressed, MIN_MEMBER_LEN).unwrap();
+        corrupt[first_end - 8] ^= 1;
+        let result = ParallelMultiGzDecoder::new(corrupt.as_slice()).read_to_end(&mut vec![]);
+        assert!(result.is_err());
+
+        // ISIZE of the last member
+        let mut corrupt = compressed.clone();
+        let last = corrupt.len() - 1;
+        corrupt[last] ^= 1;
+        let result = ParallelMultiGzDecoder::new(corrupt.as_slice()).read_to_end(&mut vec![]);
+        assert!(result.is_err());
+
+        let mut trailing = compressed.clone();
+        trailing.extend(b"trailing");
+        let result = ParallelMultiGzDecoder::new(trailing.as_slice()).read_to_end(&mut vec![]);
+        assert!(result.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,404 @@
+//! Runtime independent halves of the Encoders and Decoders over buffered and async
+//! readers and async writers; `bufread`, `tokio` and `futures` only move bytes between
+//! these and the inner reader/writer.
+//!
+//! Nothing here waits on I/O: output not yet taken by the inner writer stays in
+//! `out_buf` between calls, so a `Poll::Pending` part way through never loses any.
+//! The writer halves are handed the inner writer's `poll_write` as a closure, so the
+//! async writers only tie them to their runtime's `AsyncWrite`.
+use crate::igzip::*;
+use std::io;
+#[cfg(any(feature = "tokio", feature = "futures-io"))]
+use std::task::{ready, Poll};
+
+/// Compression for a buffered or async reader, input comes from the reader's buffer
+/// and output goes straight into the caller's, so nothing is held between reads.
+pub(crate) struct ReadEncoder {
+    compress: Compress,
+    flush: FlushFlags,
+}
+
+impl ReadEncoder {
+    pub fn new(options: CompressOptions) -> Self {
+        Self {
+            flush: options.flush.unwrap_or(FlushFlags::NoFlush),
+            compress: Compress::new(options),
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.compress.is_finished()
+    }
+
+    /// Compress from `input`, empty once the reader is done, into `output`; returns
+    /// the bytes consumed and, once there is one, the result of the read.
+    pub fn step(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, Option<usize>)> {
+        if self.compress.is_finished() {
+            return Ok((0, Some(0)));
+        }
+        let status = match input.is_empty() {
+            true => self.compress.finish(input, output)?,
+            false => self.compress.compress(input, output, self.flush)?,
+        };
+        Ok((
+            status.consumed,
+            (status.produced > 0).then_some(status.produced),
+        ))
+    }
+}
+
+/// Decompression for a buffered or async reader, see `ReadEncoder`
+pub(crate) struct ReadDecoder {
+    decomp: Decompress,
+    // trailing data seen under `TrailingData::Ignore`, discard the rest of the reader
+    ignore_rest: bool,
+}
+
+impl ReadDecoder {
+    pub fn new(options: impl Into<DecompressOptions>) -> Self {
+        Self {
+            decomp: Decompress::new(options),
+            ignore_rest: false,
+        }
+    }
+
+    pub fn decomp(&self) -> &Decompress {
+        &self.decomp
+    }
+
+    /// Decompress from `input`, empty once the reader is done, into `output`; returns
+    /// the bytes consumed and, once there is one, the result of the read.
+    pub fn step(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, Option<usize>)> {
+        if self.ignore_rest {
+            return Ok((input.len(), input.is_empty().then_some(0)));
+        }
+
+        // Previous stream/member done, begin the next one if there is more input
+        if self.decomp.is_finished() {
+            match input.first() {
+                None => return Ok((0, Some(0))),
+                Some(byte) if !self.decomp.starts_member(*byte) => {
+                    return match self.decomp.trailing_data() {
+                        TrailingData::Error => {
+                            let offset = self.decomp.member_end().unwrap_or_default();
+                            Err(Error::TrailingData(offset).into())
+                        }
+                        TrailingData::Ignore => {
+                            self.ignore_rest = true;
+                            Ok((input.len(), None))
+                        }
+                        TrailingData::Stop => Ok((0, Some(0))),
+                    };
+                }
+                Some(_) => self.decomp.reset(),
+            }
+        }
+
+        let status = self.decomp.decompress(input, output)?;
+        if status.produced > 0 {
+            return Ok((status.consumed, Some(status.produced)));
+        }
+        if !status.stream_end && input.is_empty() {
+            // Empty input is fine, a stream cut short is not
+            return match self.decomp.total_in() {
+                0 => Ok((0, Some(0))),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    Error::DecompressionError(DecompCode::EndInput),
+                )),
+            };
+        }
+        Ok((status.consumed, None))
+    }
+}
+
+// Write some of `pending` through `write`, an inner writer's `poll_write`
+#[cfg(any(feature = "tokio", feature = "futures-io"))]
+fn poll_write_pending(
+    write: &mut impl FnMut(&[u8]) -> Poll<io::Result<usize>>,
+    pending: &[u8],
+) -> Poll<io::Result<usize>> {
+    let n = ready!(write(pending))?;
+    if n == 0 {
+        return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+    }
+    Poll::Ready(Ok(n))
+}
+
+/// Compression for an async writer, holding compressed output until the writer takes it.
+#[cfg(any(feature = "tokio", feature = "futures-io"))]
+pub(crate) struct WriteEncoder {
+    compress: Compress,
+    flush: FlushFlags,
+    out_buf: Vec<u8>,
+    out_pos: usize,
+    out_end: usize,
+    // a flush was begun and hasn't reached the inner writer yet
+    flushing: bool,
+    // the last flush call filled `out_buf`, so isa-l may have more to give
+    flush_more: bool,
+}
+
+#[cfg(any(feature = "tokio", feature = "futures-io"))]
+impl WriteEncoder {
+    pub fn new(options: CompressOptions) -> Self {
+        Self {
+            flush: options.flush.unwrap_or(FlushFlags::NoFlush),
+            compress: Compress::new(options),
+            out_buf: vec![0u8; BUF_SIZE],
+            out_pos: 0,
+            out_end: 0,
+            flushing: false,
+            flush_more: false,
+        }
+    }
+
+    /// `poll_write` of the async Encoders, writing output through `write`. Writing
+    /// after `poll_finish` begins a new stream (gzip member).
+    pub fn poll_write(
+        &mut self,
+        buf: &[u8],
+        mut write: impl FnMut(&[u8]) -> Poll<io::Result<usize>>,
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        loop {
+            ready!(self.poll_drain(&mut write))?;
+            let n = self.compress_from(buf)?;
+            if n > 0 {
+                // Pass on what we can now, the rest waits for the next poll
+                if let Poll::Ready(Err(err)) = self.poll_drain(&mut write) {
+                    return Poll::Ready(Err(err));
+                }
+                return Poll::Ready(Ok(n));
+            }
+        }
+    }
+
+    /// Sync flush what was given so far through `write`; the caller then flushes
+    /// the inner writer and calls `flush_done`.
+    pub fn poll_flush(
+        &mut self,
+        mut write: impl FnMut(&[u8]) -> Poll<io::Result<usize>>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            ready!(self.poll_drain(&mut write))?;
+            if self.flush_step()? {
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+
+    /// The flush reached the inner writer, the next one starts over
+    pub fn flush_done(&mut self) {
+        self.flushing = false;
+    }
+
+    /// End the stream through `write`, trailer included; the caller then shuts
+    /// down the inner writer.
+    pub fn poll_finish(
+        &mut self,
+        mut write: impl FnMut(&[u8]) -> Poll<io::Result<usize>>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            ready!(self.poll_drain(&mut write))?;
+            if self.finish_step()? {
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+
+    // Output yet to be taken by the inner writer
+    fn pending(&self) -> &[u8] {
+        &self.out_buf[self.out_pos..self.out_end]
+    }
+
+    // Write out all of `pending`
+    fn poll_drain(
+        &mut self,
+        write: &mut impl FnMut(&[u8]) -> Poll<io::Result<usize>>,
+    ) -> Poll<io::Result<()>> {
+        while !self.pending().is_empty() {
+            let n = ready!(poll_write_pending(write, self.pending()))?;
+            self.out_pos += n;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    // Compress what fits of `input`, returning the bytes consumed; only call with
+    // nothing `pending`.
+    fn compress_from(&mut self, input: &[u8]) -> io::Result<usize> {
+        debug_assert!(self.pending().is_empty());
+        if self.compress.is_finished() {
+            self.compress.reset();
+        }
+        self.flushing = false;
+        let status = self
+            .compress
+            .compress(input, &mut self.out_buf, self.flush)?;
+        self.out_pos = 0;
+        self.out_end = status.produced;
+        Ok(status.consumed)
+    }
+
+    // Sync flush what was given so far, returning true once there is no more output
+    // to come from it; only call with nothing `pending`.
+    fn flush_step(&mut self) -> io::Result<bool> {
+        debug_assert!(self.pending().is_empty());
+        if !self.flushing {
+            self.flushing = true;
+            self.flush_more = !self.compress.is_finished();
+        }
+        if !self.flush_more {
+            return Ok(true);
+        }
+        let status = self
+            .compress
+            .compress(&[], &mut self.out_buf, FlushFlags::SyncFlush)?;
+        self.out_pos = 0;
+        self.out_end = status.produced;
+        // isa-l is done flushing once it leaves room in the output buffer
+        self.flush_more = status.produced == self.out_buf.len();
+        Ok(false)
+    }
+
+    // End the stream, returning true once the trailer has been produced; only
+    // call with nothing `pending`.
+    fn finish_step(&mut self) -> io::Result<bool> {
+        debug_assert!(self.pending().is_empty());
+        if self.compress.is_finished() {
+            return Ok(true);
+        }
+        let status = self.compress.finish(&[], &mut self.out_buf)?;
+        self.out_pos = 0;
+        self.out_end = status.produced;
+        Ok(false)
+    }
+}
+
+/// Decompression for an async writer, holding decompressed output until the writer takes it.
+#[cfg(any(feature = "tokio", feature = "futures-io"))]
+pub(crate) struct WriteDecoder {
+    decomp: Decompress,
+    out_buf: Vec<u8>,
+    out_pos: usize,
+    out_end: usize,
+    // the last call filled `out_buf`, so isa-l may have more to give
+    more: bool,
+    // trailing data seen under `TrailingData::Ignore` or `Stop`, discard all further input
+    ignore_rest: bool,
+}
+
+#[cfg(any(feature = "tokio", feature = "futures-io"))]
+impl WriteDecoder {
+    pub fn new(options: impl Into<DecompressOptions>) -> Self {
+        Self {
+            decomp: Decompress::new(options),
+            out_buf: vec![0u8; BUF_SIZE],
+            out_pos: 0,
+            out_end: 0,
+            more: false,
+            ignore_rest: false,
+        }
+    }
+
+    pub fn decomp(&self) -> &Decompress {
+        &self.decomp
+    }
+
+    /// `poll_write` of the async Decoders, writing output through `write`.
+    ///
+    /// Input can't be handed back, so under `TrailingData::Stop` what follows the
+    /// member is taken and discarded as with `TrailingData::Ignore`.
+    pub fn poll_write(
+        &mut self,
+        buf: &[u8],
+        mut write: impl FnMut(&[u8]) -> Poll<io::Result<usize>>,
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        loop {
+            ready!(self.poll_drain(&mut write))?;
+            if let Some(n) = self.decompress_from(buf)? {
+                // Pass on what we can now, the rest waits for the next poll
+                if let Poll::Ready(Err(err)) = self.poll_drain(&mut write) {
+                    return Poll::Ready(Err(err));
+                }
+                return Poll::Ready(Ok(n));
+            }
+        }
+    }
+
+    /// Write out all output of the input given so far through `write`, for flushing
+    /// or shutting down the inner writer after.
+    pub fn poll_drain(
+        &mut self,
+        mut write: impl FnMut(&[u8]) -> Poll<io::Result<usize>>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            while !self.pending().is_empty() {
+                let n = ready!(poll_write_pending(&mut write, self.pending()))?;
+                self.out_pos += n;
+            }
+            if self.flush_step()? {
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+
+    // Output yet to be taken by the inner writer
+    fn pending(&self) -> &[u8] {
+        &self.out_buf[self.out_pos..self.out_end]
+    }
+
+    // Decompress from non-empty `input`, only call with nothing `pending`. Returns
+    // the bytes consumed, or `None` if only output was produced.
+    fn decompress_from(&mut self, input: &[u8]) -> io::Result<Option<usize>> {
+        debug_assert!(self.pending().is_empty());
+        if self.ignore_rest {
+            return Ok(Some(input.len()));
+        }
+
+        // Previous stream/member done, begin the next one
+        if self.decomp.is_finished() {
+            if !self.decomp.starts_member(input[0]) {
+                return match self.decomp.trailing_data() {
+                    TrailingData::Error => {
+                        let offset = self.decomp.member_end().unwrap_or_default();
+                        Err(Error::TrailingData(offset).into())
+                    }
+                    TrailingData::Ignore | TrailingData::Stop => {
+                        self.ignore_rest = true;
+                        Ok(Some(input.len()))
+                    }
+                };
+            }
+            self.decomp.reset();
+        }
+
+        let status = self.decomp.decompress(input, &mut self.out_buf)?;
+        self.out_pos = 0;
+        self.out_end = status.produced;
+        self.more = status.produced == self.out_buf.len();
+        match status.consumed {
+            0 if status.produced > 0 => Ok(None),
+            n => Ok(Some(n)),
+        }
+    }
+
+    // Take what isa-l still holds of the input given so far, returning true once
+    // there is nothing left; only call with nothing `pending`.
+    fn flush_step(&mut self) -> io::Result<bool> {
+        debug_assert!(self.pending().is_empty());
+        if !self.more {
+            return Ok(true);
+        }
+        let status = self.decomp.decompress(&[], &mut self.out_buf)?;
+        self.out_pos = 0;
+        self.out_end = status.produced;
+        self.more = status.produced == self.out_buf.len();
+        Ok(false)
+    }
+}
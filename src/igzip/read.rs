@@ -13,10 +13,11 @@ use std::io;
 /// -------
 /// ```
 /// use std::{io, io::Read};
-/// use isal::igzip::{read::Encoder, CompressionLevel, decompress, Codec};
+/// use isal::igzip::{read::Encoder, CompressOptions, CompressionLevel, decompress, Codec};
 /// let data = b"Hello, World!".to_vec();
 ///
-/// let mut encoder = Encoder::new(data.as_slice(), CompressionLevel::Three, Codec::Gzip);
+/// let opts = CompressOptions::new(CompressionLevel::Three, Codec::Gzip);
+/// let mut encoder = Encoder::new(data.as_slice(), opts);
 /// let mut compressed = vec![];
 ///
 /// // Number of compressed bytes written to `output`
@@ -30,22 +31,31 @@ pub struct Encoder<R: io::Read> {
     inner: R,
     stream: ZStream,
     in_buf: [u8; BUF_SIZE],
+    in_pos: usize,
+    in_end: usize,
+    eof: bool,
 }
 
 impl<R: io::Read> Encoder<R> {
     /// Create a new `Encoder` which implements the `std::io::Read` trait.
-    pub fn new(reader: R, level: CompressionLevel, codec: Codec) -> Encoder<R> {
+    ///
+    /// Unless set in `options`, the flush mode defaults to `FlushFlags::NoFlush`.
+    /// With `FlushFlags::SyncFlush` or `FlushFlags::FullFlush`, every chunk read from
+    /// the inner reader ends on a byte boundary, at the cost of compression ratio.
+    pub fn new(reader: R, options: CompressOptions) -> Encoder<R> {
         let in_buf = [0_u8; BUF_SIZE];
 
-        let mut zstream = ZStream::new(level, ZStreamKind::Stateful);
+        let mut zstream = ZStream::from_options(&options, ZStreamKind::Stateful);
         zstream.stream.end_of_stream = 0;
-        zstream.stream.flush = FlushFlags::SyncFlush as _;
-        zstream.stream.gzip_flag = codec as _;
+        zstream.stream.flush = options.flush.unwrap_or(FlushFlags::NoFlush) as _;
 
         Self {
             inner: reader,
             stream: zstream,
             in_buf,
+            in_pos: 0,
+            in_end: 0,
+            eof: false,
         }
     }
 
@@ -63,26 +73,36 @@ impl<R: io::Read> Encoder<R> {
 impl<R: io::Read> io::Read for Encoder<R> {
     #[inline]
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        // Check if there is data left in out_buf, otherwise refill; if end state, return 0
-        if self.stream.stream.internal_state.state != isal::isal_zstate_state_ZSTATE_END
-            && (self.stream.stream.avail_in == 0
-                || self.stream.stream.internal_state.state
-                    != isal::isal_zstate_state_ZSTATE_TMP_FLUSH_ICF_BUFFER)
-        {
-            // Read out next buf len worth to compress; filling intermediate out_buf
-            self.stream.stream.avail_in = self.inner.read(&mut self.in_buf)? as _;
-            self.stream.stream.next_in = self.in_buf.as_mut_ptr();
-            self.stream.stream.end_of_stream =
-                (self.stream.stream.avail_in < self.in_buf.len() as _) as _;
+        if buf.is_empty() {
+            return Ok(0);
         }
+        loop {
+            if self.stream.stream.internal_state.state == isal::isal_zstate_state_ZSTATE_END {
+                return Ok(0);
+            }
+
+            // Only refill once isa-l has taken all of the previous input
+            if self.in_pos == self.in_end && !self.eof {
+                self.in_end = self.inner.read(&mut self.in_buf)?;
+                self.in_pos = 0;
+                self.eof = self.in_end == 0;
+                self.stream.stream.end_of_stream = self.eof as _;
+            }
 
-        self.stream.stream.avail_out = buf.len() as _;
-        self.stream.stream.next_out = buf.as_mut_ptr();
+            // in_buf moves with self, so point isa-l at it afresh every call
+            self.stream.stream.next_in = self.in_buf[self.in_pos..].as_mut_ptr();
+            self.stream.stream.avail_in = (self.in_end - self.in_pos) as _;
+            self.stream.stream.avail_out = buf.len() as _;
+            self.stream.stream.next_out = buf.as_mut_ptr();
 
-        self.stream.deflate()?;
+            self.stream.deflate()?;
 
-        let nbytes = buf.len() - self.stream.stream.avail_out as usize;
-        Ok(nbytes)
+            self.in_pos = self.in_end - self.stream.stream.avail_in as usize;
+            let nbytes = buf.len() - self.stream.stream.avail_out as usize;
+            if nbytes > 0 {
+                return Ok(nbytes);
+            }
+        }
     }
 }
 
@@ -97,10 +117,11 @@ impl<R: io::Read> io::Read for Encoder<R> {
 /// -------
 /// ```
 /// use std::{io, io::Read};
-/// use isal::igzip::{read::Decoder, CompressionLevel, compress, Codec};
+/// use isal::igzip::{read::Decoder, CompressOptions, CompressionLevel, compress, Codec};
 /// let data = b"Hello, World!".to_vec();
 ///
-/// let compressed = compress(data.as_slice(), CompressionLevel::Three, Codec::Gzip).unwrap();
+/// let opts = CompressOptions::new(CompressionLevel::Three, Codec::Gzip);
+/// let compressed = compress(data.as_slice(), opts).unwrap();
 /// let mut decoder = Decoder::new(compressed.as_slice(), Codec::Gzip);
 /// let mut decompressed = vec![];
 ///
@@ -111,27 +132,23 @@ impl<R: io::Read> io::Read for Encoder<R> {
 /// ```
 pub struct Decoder<R: io::Read> {
     inner: R,
-    zst: InflateState,
+    decomp: Decompress,
     in_buf: [u8; BUF_SIZE],
-    out_buf: Vec<u8>,
-    dsts: usize,
-    dste: usize,
-    codec: Codec,
+    in_pos: usize,
+    in_end: usize,
+    eof: bool,
 }
 
 impl<R: io::Read> Decoder<R> {
-    pub fn new(reader: R, codec: Codec) -> Decoder<R> {
-        let mut zst = InflateState::new();
-        zst.0.crc_flag = codec as _;
-
+    /// Create a new `Decoder`, accepting either a `Codec` or `DecompressOptions`.
+    pub fn new(reader: R, options: impl Into<DecompressOptions>) -> Decoder<R> {
         Self {
             inner: reader,
-            zst,
+            decomp: Decompress::new(options.into()),
             in_buf: [0u8; BUF_SIZE],
-            out_buf: Vec::with_capacity(BUF_SIZE),
-            dste: 0,
-            dsts: 0,
-            codec,
+            in_pos: 0,
+            in_end: 0,
+            eof: false,
         }
     }
 
@@ -145,123 +162,98 @@ impl<R: io::Read> Decoder<R> {
         &self.inner
     }
 
-    // Read data from intermediate output buffer holding compressed output.
-    // It's unknown if the output from igzip will fit into buffer passed during read
-    // so we hold it here and empty as read calls pass.
-    // thanks to: https://github.com/BurntSushi/rust-snappy/blob/f9eb8d49c713adc48732fb95682a201a7b74d39a/src/read.rs#L327
-    #[inline(always)]
-    fn read_from_out_buf(&mut self, buf: &mut [u8]) -> usize {
-        let available_bytes = self.dste - self.dsts;
-        let count = std::cmp::min(available_bytes, buf.len());
-        buf[..count].copy_from_slice(&self.out_buf[self.dsts..self.dsts + count]);
-        self.dsts += count;
-        count
+    /// Header of the current gzip member, `None` until it has been read or
+    /// if the codec isn't `Codec::Gzip`
+    pub fn header(&self) -> Option<&GzipHeader> {
+        self.decomp.header()
+    }
+
+    /// Header of the current zlib stream, `None` until it has been read or
+    /// if the codec isn't `Codec::Zlib`
+    pub fn zlib_header(&self) -> Option<&ZlibHeader> {
+        self.decomp.zlib_header()
+    }
+
+    /// Trailers which didn't match the data under `ChecksumPolicy::Report`, across all members
+    pub fn checksum_mismatches(&self) -> &[ChecksumMismatch] {
+        self.decomp.checksum_mismatches()
+    }
+
+    /// Offset in the input just past the last gzip member (or zlib/deflate stream)
+    /// to end, `None` until one has.
+    ///
+    /// With `DecompressOptions::multi_member(false)` this is where the only member
+    /// ended; input past it may already have been read from the inner reader.
+    pub fn member_end(&self) -> Option<u64> {
+        self.decomp.member_end()
+    }
+
+    /// Give up the inner reader, along with input read from it but not decompressed;
+    /// after `TrailingData::Stop` that's the start of the trailing data.
+    pub fn into_inner_with_remaining(self) -> (R, Vec<u8>) {
+        let remaining = self.in_buf[self.in_pos..self.in_end].to_vec();
+        (self.inner, remaining)
+    }
+
+    // Deal with input following the last member, always ending the read
+    fn trailing_data(&mut self) -> io::Result<usize> {
+        match self.decomp.trailing_data() {
+            TrailingData::Error => {
+                let offset = self.decomp.member_end().unwrap_or_default();
+                Err(Error::TrailingData(offset).into())
+            }
+            TrailingData::Ignore => {
+                self.in_pos = self.in_end;
+                io::copy(&mut self.inner, &mut io::sink())?;
+                self.eof = true;
+                Ok(0)
+            }
+            TrailingData::Stop => Ok(0),
+        }
     }
 }
 
 impl<R: io::Read> io::Read for Decoder<R> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        // Check if there is data left in out_buf, otherwise refill; if end state, return 0
-        let count = self.read_from_out_buf(buf);
-        if count > 0 {
-            Ok(count)
-        } else {
-            // Read out next buf len worth to compress; filling intermediate out_buf
-            debug_assert_eq!(self.zst.0.avail_in, 0);
-            self.zst.0.avail_in = self.inner.read(&mut self.in_buf)? as _;
-            self.zst.0.next_in = self.in_buf.as_mut_ptr();
-            let avail_in_original = self.zst.0.avail_in;
-
-            let mut n_bytes = 0;
-            while self.zst.0.avail_in != 0 {
-                if self.zst.block_state() == isal::isal_block_state_ISAL_BLOCK_NEW_HDR {
-                    // Read gzip header
-                    if self.codec == Codec::Gzip {
-                        // Read this member's gzip header
-                        let mut gz_hdr: mem::MaybeUninit<isal::isal_gzip_header> =
-                            mem::MaybeUninit::uninit();
-                        unsafe { isal::isal_gzip_header_init(gz_hdr.as_mut_ptr()) };
-                        let mut gz_hdr = unsafe { gz_hdr.assume_init() };
-                        read_gzip_header(&mut self.zst.0, &mut gz_hdr)?;
-
-                    // Read zlib header
-                    } else if self.codec == Codec::Zlib {
-                        self.zst.0.crc_flag = 0; // zlib uses adler-32 checksum
-
-                        let mut hdr: mem::MaybeUninit<isal::isal_zlib_header> =
-                            mem::MaybeUninit::uninit();
-                        unsafe { isal::isal_zlib_header_init(hdr.as_mut_ptr()) };
-                        let mut hdr = unsafe { hdr.assume_init() };
-                        read_zlib_header(&mut self.zst.0, &mut hdr)?;
-                        self.zst.0.next_in = self.in_buf[2..].as_ptr() as *mut _; // skip header now that it's read
-                        self.zst.0.avail_in -= 4; // skip adler-32
-                    }
-                }
-                // TODO: I'm pretty sure we can remove out_buf
-                // decompress member
-                loop {
-                    self.out_buf.resize(n_bytes + BUF_SIZE, 0);
-
-                    self.zst.0.next_out = self.out_buf[n_bytes..n_bytes + BUF_SIZE].as_mut_ptr();
-                    self.zst.0.avail_out = BUF_SIZE as _;
-
-                    self.zst.step_inflate()?;
-
-                    n_bytes += BUF_SIZE - self.zst.0.avail_out as usize;
-
-                    let state = self.zst.block_state();
-                    match self.codec {
-                        Codec::Deflate | Codec::Zlib => {
-                            if state == isal::isal_block_state_ISAL_BLOCK_FINISH {
-                                break;
-
-                            // refill avail in, still actively decoding but reached end of input
-                            } else if state == isal::isal_block_state_ISAL_BLOCK_CODED
-                                && self.zst.0.avail_in == 0
-                            {
-                                self.zst.0.avail_in = self.inner.read(&mut self.in_buf)? as _;
-                                self.zst.0.next_in = self.in_buf.as_mut_ptr();
-                            }
-                        }
-                        Codec::Gzip => {
-                            if state == isal::isal_block_state_ISAL_BLOCK_CODED
-                                || state == isal::isal_block_state_ISAL_BLOCK_TYPE0
-                                || state == isal::isal_block_state_ISAL_BLOCK_HDR
-                                || state == isal::isal_block_state_ISAL_BLOCK_FINISH
-                            {
-                                break;
-                            }
-                        }
-                    }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if self.in_pos == self.in_end && !self.eof {
+                self.in_end = self.inner.read(&mut self.in_buf)?;
+                self.in_pos = 0;
+                self.eof = self.in_end == 0;
+            }
+
+            // Previous stream/member done, begin the next one if there is more input
+            if self.decomp.is_finished() {
+                if self.in_pos == self.in_end {
+                    return Ok(0);
                 }
-                if self.zst.0.block_state == isal::isal_block_state_ISAL_BLOCK_FINISH {
-                    self.zst.reset();
+                if !self.decomp.starts_member(self.in_buf[self.in_pos]) {
+                    return self.trailing_data();
                 }
+                self.decomp.reset();
             }
 
-            // Check adler
-            if self.codec == Codec::Zlib && avail_in_original > 4 {
-                let decompressed = &self.out_buf[..n_bytes];
-                let c_adler32 = unsafe {
-                    isal::isal_adler32(1, decompressed.as_ptr(), decompressed.len() as _)
+            let status = self
+                .decomp
+                .decompress(&self.in_buf[self.in_pos..self.in_end], buf)?;
+            self.in_pos += status.consumed;
+
+            if status.produced > 0 {
+                return Ok(status.produced);
+            }
+            if !status.stream_end && self.eof && self.in_pos == self.in_end {
+                // Empty input is fine, a stream cut short is not
+                return match self.decomp.total_in() {
+                    0 => Ok(0),
+                    _ => Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        Error::DecompressionError(DecompCode::EndInput),
+                    )),
                 };
-                let bytes: [u8; 4] = (&self.in_buf
-                    [avail_in_original as usize - 4..avail_in_original as usize])
-                    .try_into()
-                    .unwrap();
-                let e_adler32 = u32::from_be_bytes(bytes);
-                if c_adler32 != e_adler32 {
-                    return Err(std::io::Error::new(
-                        std::io::ErrorKind::InvalidData,
-                        crate::error::Error::DecompressionError(DecompCode::IncorrectChecksum),
-                    ));
-                }
             }
-            self.out_buf.truncate(n_bytes);
-            self.dste = n_bytes;
-            self.dsts = 0;
-
-            Ok(self.read_from_out_buf(buf))
         }
     }
 }
@@ -275,7 +267,7 @@ pub struct DeflateEncoder<R: io::Read> {
 impl<R: io::Read> DeflateEncoder<R> {
     pub fn new(reader: R, level: CompressionLevel) -> Self {
         Self {
-            inner: Encoder::new(reader, level, Codec::Deflate),
+            inner: Encoder::new(reader, CompressOptions::new(level, Codec::Deflate)),
         }
     }
 }
@@ -314,7 +306,7 @@ pub struct ZlibEncoder<R: io::Read> {
 impl<R: io::Read> ZlibEncoder<R> {
     pub fn new(reader: R, level: CompressionLevel) -> Self {
         Self {
-            inner: Encoder::new(reader, level, Codec::Zlib),
+            inner: Encoder::new(reader, CompressOptions::new(level, Codec::Zlib)),
         }
     }
 }
@@ -353,7 +345,7 @@ pub struct GzipEncoder<R: io::Read> {
 impl<R: io::Read> GzipEncoder<R> {
     pub fn new(reader: R, level: CompressionLevel) -> Self {
         Self {
-            inner: Encoder::new(reader, level, Codec::Gzip),
+            inner: Encoder::new(reader, CompressOptions::new(level, Codec::Gzip)),
         }
     }
 }
@@ -391,6 +383,63 @@ mod tests {
     use crate::igzip::tests::{gen_large_data, same_same};
     use std::io::{self, Cursor};
 
+    #[test]
+    fn single_member() -> Result<()> {
+        let first = compress(b"foo".as_slice(), CompressOptions::default())?;
+        let mut compressed = first.clone();
+        compressed.extend(compress(b"bar".as_slice(), CompressOptions::default())?);
+        compressed.extend(b"not gzip");
+
+        let opts = DecompressOptions::new(Codec::Gzip).multi_member(false);
+        let mut decoder = Decoder::new(compressed.as_slice(), opts);
+        assert_eq!(decoder.member_end(), None);
+        let mut decompressed = vec![];
+        io::copy(&mut decoder, &mut decompressed)?;
+        assert_eq!(decompressed, b"foo");
+        assert_eq!(decoder.member_end(), Some(first.len() as u64));
+
+        // otherwise the garbage is decoded too
+        let mut decoder = Decoder::new(compressed.as_slice(), Codec::Gzip);
+        assert!(io::copy(&mut decoder, &mut vec![]).is_err());
+        assert_eq!(decoder.member_end(), Some(compressed.len() as u64 - 8));
+        Ok(())
+    }
+
+    #[test]
+    fn trailing_data_policies() -> Result<()> {
+        let data = gen_large_data();
+        let compressed = compress(data.as_slice(), CompressOptions::default())?;
+        let mut padded = compressed.clone();
+        padded.resize(compressed.len() + 3 * BUF_SIZE, 0);
+
+        let mut decoder = Decoder::new(padded.as_slice(), Codec::Gzip);
+        match io::copy(&mut decoder, &mut vec![]) {
+            Err(err) => assert!(err.to_string().contains("Trailing data")),
+            r => panic!("Expected trailing data error, got {:?}", r),
+        }
+
+        let opts = DecompressOptions::new(Codec::Gzip).trailing_data(TrailingData::Ignore);
+        let mut decoder = Decoder::new(padded.as_slice(), opts);
+        let mut decompressed = vec![];
+        io::copy(&mut decoder, &mut decompressed)?;
+        assert!(same_same(&data, &decompressed));
+        let (rest, remaining) = decoder.into_inner_with_remaining();
+        assert!(rest.is_empty() && remaining.is_empty());
+
+        // metadata appended to the stream
+        let mut appended = compressed.clone();
+        appended.extend(b"{\"tags\": []}");
+        let opts = DecompressOptions::new(Codec::Gzip).trailing_data(TrailingData::Stop);
+        let mut decoder = Decoder::new(appended.as_slice(), opts);
+        let mut decompressed = vec![];
+        io::copy(&mut decoder, &mut decompressed)?;
+        assert!(same_same(&data, &decompressed));
+        assert_eq!(decoder.member_end(), Some(compressed.len() as u64));
+        let (rest, remaining) = decoder.into_inner_with_remaining();
+        assert_eq!([remaining.as_slice(), rest].concat(), b"{\"tags\": []}");
+        Ok(())
+    }
+
     #[test]
     fn roundtrip_small() {
         roundtrip(b"foobar")
@@ -400,7 +449,7 @@ mod tests {
         roundtrip(&gen_large_data())
     }
     fn roundtrip(input: &[u8]) {
-        let mut encoder = Encoder::new(Cursor::new(&input), CompressionLevel::Three, Codec::Gzip);
+        let mut encoder = Encoder::new(Cursor::new(&input), CompressOptions::default());
         let mut output = vec![];
 
         let n = io::copy(&mut encoder, &mut output).unwrap();
@@ -423,7 +472,7 @@ mod tests {
         basic_compress(&gen_large_data())
     }
     fn basic_compress(input: &[u8]) -> Result<()> {
-        let mut encoder = Encoder::new(Cursor::new(input), CompressionLevel::Three, Codec::Gzip);
+        let mut encoder = Encoder::new(Cursor::new(input), CompressOptions::default());
         let mut output = vec![];
 
         let n = io::copy(&mut encoder, &mut output)? as usize;
@@ -444,7 +493,7 @@ mod tests {
             }
         }
 
-        let mut encoder = Encoder::new(Cursor::new(&input), CompressionLevel::Three, Codec::Gzip);
+        let mut encoder = Encoder::new(Cursor::new(&input), CompressOptions::default());
         let mut output = vec![];
 
         let n = io::copy(&mut encoder, &mut output)? as usize;
@@ -454,6 +503,73 @@ mod tests {
         Ok(())
     }
 
+    // Hands out at most `chunk` bytes per read, like a pipe or socket would
+    struct Chunked<'a> {
+        data: &'a [u8],
+        chunk: usize,
+    }
+
+    impl io::Read for Chunked<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.chunk.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    fn compress_chunked(data: &[u8], chunk: usize, opts: CompressOptions) -> Result<Vec<u8>> {
+        let mut encoder = Encoder::new(Chunked { data, chunk }, opts);
+        let mut output = vec![];
+        io::copy(&mut encoder, &mut output)?;
+        Ok(output)
+    }
+
+    #[test]
+    fn short_reads_compress() -> Result<()> {
+        // short reads aren't the end of the input
+        let input = gen_large_data();
+        let output = compress_chunked(&input, 1000, CompressOptions::default())?;
+        let decompressed = decompress(output.as_slice(), Codec::Gzip)?;
+        assert!(same_same(&input, &decompressed));
+        Ok(())
+    }
+
+    #[test]
+    fn no_flush_by_default() -> Result<()> {
+        let input = b"Hello, World! ".repeat(1000);
+        let default = compress_chunked(&input, 100, CompressOptions::default())?;
+        let synced = compress_chunked(
+            &input,
+            100,
+            CompressOptions::default().flush(FlushFlags::SyncFlush),
+        )?;
+
+        // an empty stored block after every chunk
+        assert!(synced.len() > default.len() + 4 * (input.len() / 100));
+        for compressed in [default, synced] {
+            assert_eq!(decompress(compressed.as_slice(), Codec::Gzip)?, input);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn small_output_buffer_compress() -> Result<()> {
+        let input = gen_large_data();
+        let mut encoder = Encoder::new(input.as_slice(), CompressOptions::default());
+        let mut output = vec![];
+        let mut buf = [0u8; 7];
+        loop {
+            match io::Read::read(&mut encoder, &mut buf)? {
+                0 => break,
+                n => output.extend_from_slice(&buf[..n]),
+            }
+        }
+        let decompressed = decompress(output.as_slice(), Codec::Gzip)?;
+        assert!(same_same(&input, &decompressed));
+        Ok(())
+    }
+
     #[test]
     fn basic_decompress_small() -> Result<()> {
         basic_decompress(b"foobar")
@@ -463,7 +579,7 @@ mod tests {
         basic_decompress(&gen_large_data())
     }
     fn basic_decompress(input: &[u8]) -> Result<()> {
-        let compressed = compress(Cursor::new(input), CompressionLevel::Three, Codec::Gzip)?;
+        let compressed = compress(Cursor::new(input), CompressOptions::default())?;
 
         let mut decoder = Decoder::new(compressed.as_slice(), Codec::Gzip);
         let mut decompressed = vec![];
@@ -485,7 +601,7 @@ mod tests {
             }
         }
 
-        let compressed = compress(input.as_slice(), CompressionLevel::Three, Codec::Gzip)?;
+        let compressed = compress(input.as_slice(), CompressOptions::default())?;
 
         let mut decoder = Decoder::new(compressed.as_slice(), Codec::Gzip);
         let mut decompressed = vec![];
@@ -507,7 +623,7 @@ mod tests {
     }
     fn flate2_gzip_compat_encoder_out(data: &[u8]) {
         // our encoder
-        let mut encoder = Encoder::new(data, CompressionLevel::Three, Codec::Gzip);
+        let mut encoder = Encoder::new(data, CompressOptions::default());
         let mut compressed = vec![];
         io::copy(&mut encoder, &mut compressed).unwrap();
 
@@ -0,0 +1,461 @@
+//! Joining independently compressed chunks into one gzip or zlib stream
+//!
+//! Deflate data that ends with a full (or sync) flush is byte aligned and isn't the
+//! last of its stream, so chunks compressed apart, say on different machines, can be
+//! written one after the other as they are. Chunks that were finished instead have
+//! the BFINAL bit of their last block cleared, and are brought to a byte boundary with
+//! an empty stored block. `Stitcher` adds the header, a final empty block and the
+//! trailer, combining the checksums and lengths of the chunks, without recompressing
+//! anything.
+use crate::igzip::*;
+use std::borrow::Cow;
+
+// Empty stored block written by a full or sync flush, from its length on
+const SYNC_MARKER: [u8; 4] = [0, 0, 0xff, 0xff];
+
+// Empty last block ending a stream: fixed Huffman with just the end of block code
+const FIXED_LAST_BLOCK: [u8; 2] = [0x03, 0x00];
+
+/// Deflate data of one piece of the input, with the length and checksums of what it
+/// decompresses to, ready to be joined by `Stitcher`.
+///
+/// Example
+/// -------
+/// ```
+/// use isal::igzip::{stitch::{stitch, Chunk}, decompress, Codec, CompressionLevel};
+///
+/// // Compressed anywhere, in any order
+/// let first = Chunk::compress(b"Hello, ", CompressionLevel::Three).unwrap();
+/// let second = Chunk::compress(b"World!", CompressionLevel::Three).unwrap();
+///
+/// let compressed = stitch(&[first, second], Codec::Gzip).unwrap();
+/// let decompressed = decompress(compressed.as_slice(), Codec::Gzip).unwrap();
+/// assert_eq!(decompressed, b"Hello, World!");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    deflate: Vec<u8>,
+    len: u64,
+    crc32: u32,
+    adler32: u32,
+}
+
+impl Chunk {
+    /// Compress `data` as a chunk, ending with a full flush
+    pub fn compress(data: &[u8], level: CompressionLevel) -> Result<Self> {
+        let mut compress = Compress::new(CompressOptions::new(level, Codec::Deflate));
+        let mut deflate = Vec::with_capacity(compress_bound(data.len(), Codec::Deflate));
+        let mut buf = vec![0u8; BUF_SIZE];
+        let mut input = data;
+        loop {
+            let status = compress.compress(input, &mut buf, FlushFlags::FullFlush)?;
+            deflate.extend_from_slice(&buf[..status.produced]);
+            input = &input[status.consumed..];
+            // isa-l is done flushing once it leaves room in the output buffer
+            if input.is_empty() && status.produced < buf.len() {
+                break;
+            }
+        }
+        Ok(Self::from_parts(deflate, data))
+    }
+
+    /// Chunk of deflate data compressed some other way, along with what it decompresses to.
+    ///
+    /// Any complete deflate data will do, ending with a full or sync flush or finished
+    /// as a stream of its own.
+    pub fn from_parts(deflate: Vec<u8>, data: &[u8]) -> Self {
+        Self::from_raw_parts(deflate, data.len() as u64, crc32(data), adler32(data))
+    }
+
+    /// Like `Chunk::from_parts`, but with the length and checksums of the data worked
+    /// out beforehand, such as by whoever sent the chunk.
+    pub fn from_raw_parts(deflate: Vec<u8>, len: u64, crc32: u32, adler32: u32) -> Self {
+        Self {
+            deflate,
+            len,
+            crc32,
+            adler32,
+        }
+    }
+
+    /// The deflate data
+    pub fn deflate(&self) -> &[u8] {
+        &self.deflate
+    }
+
+    /// Length of the decompressed data
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// If there is no decompressed data
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// CRC32 of the decompressed data
+    pub fn crc32(&self) -> u32 {
+        self.crc32
+    }
+
+    /// Adler32 of the decompressed data
+    pub fn adler32(&self) -> u32 {
+        self.adler32
+    }
+
+    // The deflate data as it goes in the middle of a stream: not final and ending on
+    // a byte boundary
+    fn body(&self) -> io::Result<Cow<'_, [u8]>> {
+        let data = self.deflate.as_slice();
+        let blocks = Blocks::walk(data)?;
+        let (pos, bits) = ((blocks.end / 8) as usize, blocks.end % 8);
+        let mut body = match (bits, blocks.final_block) {
+            (0, None) => return Ok(Cow::Borrowed(&data[..pos])),
+            _ => data[..pos].to_vec(),
+        };
+        if bits != 0 {
+            body.extend_from_slice(&bridge(data[pos], bits as u32));
+        }
+        if let Some(header) = blocks.final_block {
+            body[(header / 8) as usize] &= !(1 << (header % 8));
+        }
+        Ok(Cow::Owned(body))
+    }
+}
+
+/// Writes `Chunk`s one after the other into a single stream of the given `Codec`.
+///
+/// Gzip and zlib get their header before the first chunk, and the trailer when the
+/// `Stitcher` is finished, computed from the chunks' checksums; the `*NoHdr` codecs
+/// get only the trailer, and `Codec::Deflate` neither.
+///
+/// Example
+/// -------
+/// ```
+/// use isal::igzip::{stitch::{Chunk, Stitcher}, decompress, Codec, CompressionLevel};
+///
+/// let mut stitcher = Stitcher::new(vec![], Codec::Zlib);
+/// for part in [b"Hello, ".as_slice(), b"World!"] {
+///     stitcher.push(&Chunk::compress(part, CompressionLevel::Three).unwrap()).unwrap();
+/// }
+/// let compressed = stitcher.finish().unwrap();
+///
+/// let decompressed = decompress(compressed.as_slice(), Codec::Zlib).unwrap();
+/// assert_eq!(decompressed, b"Hello, World!");
+/// ```
+pub struct Stitcher<W: io::Write> {
+    // only taken by `finish`
+    inner: Option<W>,
+    codec: Codec,
+    gzip_header: GzipHeader,
+    header_done: bool,
+    finished: bool,
+    len: u64,
+    crc32: u32,
+    adler32: u32,
+}
+
+impl<W: io::Write> Stitcher<W> {
+    /// Create a new `Stitcher` writing a stream of `codec`.
+    pub fn new(writer: W, codec: Codec) -> Self {
+        Self {
+            inner: Some(writer),
+            codec,
+            gzip_header: GzipHeader::default(),
+            header_done: false,
+            finished: false,
+            len: 0,
+            crc32: 0,
+            adler32: 1,
+        }
+    }
+
+    /// Header to write with `Codec::Gzip`, default `GzipHeader::default()`.
+    pub fn gzip_header(mut self, header: GzipHeader) -> Self {
+        self.gzip_header = header;
+        self
+    }
+
+    /// Mutable reference to underlying writer, not advisable to modify during writing.
+    pub fn get_ref_mut(&mut self) -> &mut W {
+        self.inner.as_mut().unwrap()
+    }
+
+    // Reference to underlying writer
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    /// Length of the data written so far, decompressed
+    pub fn total_in(&self) -> u64 {
+        self.len
+    }
+
+    /// Write `chunk` after those before it, failing with `io::ErrorKind::InvalidData`
+    /// if it isn't whole deflate data.
+    pub fn push(&mut self, chunk: &Chunk) -> io::Result<()> {
+        if self.finished {
+            return Err(io::Error::other(
+                "chunk pushed after the stream was finished",
+            ));
+        }
+        let body = chunk.body()?;
+        self.write_header()?;
+        self.get_ref_mut().write_all(&body)?;
+        self.crc32 = crc32_combine(self.crc32, chunk.crc32, chunk.len);
+        self.adler32 = adler32_combine(self.adler32, chunk.adler32, chunk.len);
+        self.len += chunk.len;
+        Ok(())
+    }
+
+    /// End the stream with an empty last block and the trailer, without giving up
+    /// the inner writer. Does nothing if already finished.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.write_header()?;
+        let mut trailer = FIXED_LAST_BLOCK.to_vec();
+        match self.codec {
+            Codec::Gzip | Codec::GzipNoHdr | Codec::GzipNoHdrVer => {
+                trailer.extend_from_slice(&self.crc32.to_le_bytes());
+                // ISIZE is the length modulo 2^32
+                trailer.extend_from_slice(&(self.len as u32).to_le_bytes());
+            }
+            Codec::Zlib | Codec::ZlibNoHdr | Codec::ZlibNoHdrVer => {
+                trailer.extend_from_slice(&self.adler32.to_be_bytes());
+            }
+            Codec::Deflate => (),
+        }
+        self.get_ref_mut().write_all(&trailer)?;
+        self.finished = true;
+        Ok(())
+    }
+
+    /// End the stream and return the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.inner.take().unwrap())
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if self.header_done {
+            return Ok(());
+        }
+        let header = match self.codec {
+            Codec::Gzip => self.gzip_header.to_bytes(),
+            Codec::Zlib => ZlibHeader::default().to_bytes(),
+            _ => vec![],
+        };
+        self.get_ref_mut().write_all(&header)?;
+        self.header_done = true;
+        Ok(())
+    }
+}
+
+/// Join `chunks` into a single stream of `codec`, see `Stitcher`.
+pub fn stitch(chunks: &[Chunk], codec: Codec) -> Result<Vec<u8>> {
+    let len = chunks
+        .iter()
+        .map(|chunk| chunk.deflate.len())
+        .sum::<usize>();
+    let mut stitcher = Stitcher::new(Vec::with_capacity(len + 32), codec);
+    for chunk in chunks {
+        stitcher.push(chunk)?;
+    }
+    Ok(stitcher.finish()?)
+}
+
+// The first `bits` of `byte`, 1 to 7, ending deflate data in the middle of a byte,
+// followed by an empty stored block to reach the next byte boundary: all zero bits
+// but for its length
+pub(crate) fn bridge(byte: u8, bits: u32) -> Vec<u8> {
+    let mut bridge = vec![byte & ((1 << bits) - 1)];
+    // its three header bits don't all fit in this byte
+    if bits > 5 {
+        bridge.push(0);
+    }
+    bridge.extend_from_slice(&SYNC_MARKER);
+    bridge
+}
+
+// Where a deflate stream ends, in bits, and where its last block begins if that
+// block has BFINAL set
+struct Blocks {
+    end: u64,
+    final_block: Option<u64>,
+}
+
+// The shortest block there is, fixed Huffman with just the end of block code
+const MIN_BLOCK_BITS: u64 = 3 + 7;
+
+impl Blocks {
+    fn walk(data: &[u8]) -> io::Result<Self> {
+        let mut pos = 0;
+        // what's left after a non-final block is padding if too short for another
+        while data.len() as u64 * 8 - pos >= MIN_BLOCK_BITS {
+            let block = blocks::walk_block(data, pos).map_err(|err| match err.kind() {
+                io::ErrorKind::UnexpectedEof => invalid_deflate("deflate chunk is cut short"),
+                _ => err,
+            })?;
+            if block.last {
+                if data.len() as u64 * 8 - block.end >= 8 {
+                    return Err(invalid_deflate("data after the end of the deflate chunk"));
+                }
+                return Ok(Self {
+                    end: block.end,
+                    final_block: Some(pos),
+                });
+            }
+            pos = block.end;
+        }
+        Ok(Self {
+            end: pos,
+            final_block: None,
+        })
+    }
+}
+
+fn invalid_deflate(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::igzip::tests::{gen_large_data, same_same};
+    use std::io::{Read, Write};
+
+    fn chunks(data: &[u8]) -> Result<Vec<Chunk>> {
+        data.chunks(100_000)
+            .map(|part| Chunk::compress(part, CompressionLevel::Three))
+            .collect()
+    }
+
+    #[test]
+    fn adler32_combine_matches() {
+        let data = gen_large_data();
+        for split in [0, 1, 1000, 65521 * 3, data.len()] {
+            let (a, b) = data.split_at(split);
+            let combined = adler32_combine(adler32(a), adler32(b), b.len() as u64);
+            assert_eq!(combined, adler32(&data));
+        }
+    }
+
+    #[test]
+    fn stitch_gzip() -> Result<()> {
+        let data = gen_large_data();
+        let compressed = stitch(&chunks(&data)?, Codec::Gzip)?;
+        assert!(same_same(
+            &data,
+            &decompress(compressed.as_slice(), Codec::Gzip)?
+        ));
+
+        // A single member any decoder reads
+        let mut decompressed = vec![];
+        flate2::read::GzDecoder::new(compressed.as_slice()).read_to_end(&mut decompressed)?;
+        assert!(same_same(&data, &decompressed));
+        Ok(())
+    }
+
+    #[test]
+    fn stitch_zlib_and_deflate() -> Result<()> {
+        let data = gen_large_data();
+        let chunks = chunks(&data)?;
+        for codec in [Codec::Zlib, Codec::Deflate] {
+            let compressed = stitch(&chunks, codec)?;
+            assert!(same_same(&data, &decompress(compressed.as_slice(), codec)?));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn finished_chunks() -> Result<()> {
+        // Finished after the flush, as zlib does with Z_FINISH following Z_FULL_FLUSH
+        let data = gen_large_data();
+        let lasts = [FIXED_LAST_BLOCK.as_slice(), &[0x01, 0, 0, 0xff, 0xff]];
+        let mut chunks = vec![];
+        for (part, last) in data.chunks(1_000_000).zip(lasts.iter().cycle()) {
+            let mut deflate = Chunk::compress(part, CompressionLevel::Three)?.deflate;
+            deflate.extend_from_slice(last);
+            // Each is a complete deflate stream on its own
+            assert_eq!(decompress(deflate.as_slice(), Codec::Deflate)?, part);
+            chunks.push(Chunk::from_parts(deflate, part));
+        }
+        let compressed = stitch(&chunks, Codec::Gzip)?;
+        assert!(same_same(
+            &data,
+            &decompress(compressed.as_slice(), Codec::Gzip)?
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn empty() -> Result<()> {
+        let compressed = stitch(&[], Codec::Gzip)?;
+        assert!(decompress(compressed.as_slice(), Codec::Gzip)?.is_empty());
+
+        let chunk = Chunk::compress(b"", CompressionLevel::Three)?;
+        let compressed = stitch(&[chunk.clone(), chunk], Codec::Zlib)?;
+        assert!(decompress(compressed.as_slice(), Codec::Zlib)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn finished_streams() -> Result<()> {
+        // Whole deflate streams, their last block not on a byte boundary as often as not
+        let data = gen_large_data();
+        let mut chunks = vec![];
+        for (i, part) in data.chunks(300_001).enumerate() {
+            let deflate = match i % 3 {
+                0 => {
+                    let opts = CompressOptions::new(CompressionLevel::Three, Codec::Deflate);
+                    compress(part, opts)?
+                }
+                1 => {
+                    let mut encoder = flate2::write::DeflateEncoder::new(
+                        vec![],
+                        flate2::Compression::new(i as u32 % 10),
+                    );
+                    encoder.write_all(part)?;
+                    encoder.finish()?
+                }
+                _ => Chunk::compress(part, CompressionLevel::One)?.deflate,
+            };
+            chunks.push(Chunk::from_parts(deflate, part));
+        }
+        // a fixed Huffman block only
+        let opts = CompressOptions::new(CompressionLevel::Three, Codec::Deflate);
+        let deflate = compress(b"Hello, World!".as_slice(), opts)?;
+        chunks.push(Chunk::from_parts(deflate, b"Hello, World!"));
+        let compressed = stitch(&chunks, Codec::Gzip)?;
+
+        let mut decompressed = vec![];
+        flate2::read::GzDecoder::new(compressed.as_slice()).read_to_end(&mut decompressed)?;
+        assert!(same_same(
+            &[data, b"Hello, World!".to_vec()].concat(),
+            &decompressed
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid() -> Result<()> {
+        let opts = CompressOptions::new(CompressionLevel::Three, Codec::Deflate);
+        let deflate = compress(gen_large_data().as_slice(), opts)?;
+        let mut stitcher = Stitcher::new(vec![], Codec::Gzip);
+
+        // cut short, and with something after its end
+        let chunk = Chunk::from_raw_parts(deflate[..deflate.len() - 10].to_vec(), 0, 0, 1);
+        assert!(stitcher.push(&chunk).is_err());
+        let chunk = Chunk::from_raw_parts([deflate.as_slice(), b"junk"].concat(), 0, 0, 1);
+        assert!(stitcher.push(&chunk).is_err());
+
+        let chunk = Chunk::from_raw_parts(deflate, 0, 0, 1);
+        stitcher.push(&chunk)?;
+        stitcher.try_finish()?;
+        assert!(stitcher.push(&chunk).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,629 @@
+//! Compression and decompression streams over caller provided buffers, the latter
+//! also being the state machine shared by the `read` and `write` Decoders
+use crate::igzip::*;
+
+/// Outcome of a single call to `Compress::compress` or `Decompress::decompress`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Status {
+    /// Bytes taken from the input
+    pub consumed: usize,
+    /// Bytes written to the output
+    pub produced: usize,
+    /// The end of the stream (or gzip member) was reached; when decompressing,
+    /// its trailer was verified too
+    pub stream_end: bool,
+}
+
+/// Incremental compression of a single gzip member, zlib stream or deflate stream.
+///
+/// Input not consumed, or output not produced, in one call is left for the next;
+/// isa-l keeps what it needs of the input, so buffers can be reused between calls.
+///
+/// Example
+/// -------
+/// ```
+/// use isal::igzip::{Compress, CompressOptions, CompressionLevel, Codec, FlushFlags, decompress};
+///
+/// let data = b"Hello, World!".repeat(1000);
+/// let mut compress = Compress::new(CompressOptions::new(CompressionLevel::Three, Codec::Gzip));
+/// let mut compressed = vec![];
+/// let mut buf = [0u8; 256];
+///
+/// let mut input = data.as_slice();
+/// while !input.is_empty() {
+///     let status = compress.compress(input, &mut buf, FlushFlags::NoFlush).unwrap();
+///     input = &input[status.consumed..];
+///     compressed.extend_from_slice(&buf[..status.produced]);
+/// }
+/// loop {
+///     let status = compress.finish(&[], &mut buf).unwrap();
+///     compressed.extend_from_slice(&buf[..status.produced]);
+///     if status.stream_end {
+///         break;
+///     }
+/// }
+/// assert_eq!(compress.total_in(), data.len());
+/// assert_eq!(decompress(compressed.as_slice(), Codec::Gzip).unwrap(), data);
+/// ```
+pub struct Compress {
+    zstream: ZStream,
+    finished: bool,
+    total_in: usize,
+    total_out: usize,
+}
+
+impl Compress {
+    /// Create a new stream from `options`, ignoring their flush mode in favour of
+    /// the one given to each `Compress::compress` call.
+    pub fn new(options: CompressOptions) -> Self {
+        let mut zstream = ZStream::from_options(&options, ZStreamKind::Stateful);
+        zstream.stream.end_of_stream = 0;
+        Self {
+            zstream,
+            finished: false,
+            total_in: 0,
+            total_out: 0,
+        }
+    }
+
+    /// Compress as much of `input` into `output` as possible.
+    ///
+    /// A `SyncFlush` or `FullFlush` is complete once a call with no more input leaves
+    /// room in `output`.
+    pub fn compress(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        flush: FlushFlags,
+    ) -> Result<Status> {
+        self.deflate(input, output, flush, false)
+    }
+
+    /// Compress `input` and end the stream, writing the trailer; call again with
+    /// the rest of the input and more room until `Status::stream_end`.
+    pub fn finish(&mut self, input: &[u8], output: &mut [u8]) -> Result<Status> {
+        self.deflate(input, output, FlushFlags::NoFlush, true)
+    }
+
+    fn deflate(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        flush: FlushFlags,
+        end_of_stream: bool,
+    ) -> Result<Status> {
+        if self.finished {
+            return Ok(Status {
+                consumed: 0,
+                produced: 0,
+                stream_end: true,
+            });
+        }
+
+        let stream = &mut self.zstream.stream;
+        stream.next_in = input.as_ptr() as *mut _;
+        stream.avail_in = input.len() as _;
+        stream.next_out = output.as_mut_ptr();
+        stream.avail_out = output.len() as _;
+        stream.flush = flush as _;
+        stream.end_of_stream = end_of_stream as _;
+
+        let ret = self.zstream.deflate();
+
+        // Don't leave pointers into the caller's buffers behind
+        let stream = &mut self.zstream.stream;
+        let consumed = input.len() - stream.avail_in as usize;
+        let produced = output.len() - stream.avail_out as usize;
+        stream.next_in = std::ptr::null_mut();
+        stream.avail_in = 0;
+        stream.next_out = std::ptr::null_mut();
+        stream.avail_out = 0;
+        ret?;
+
+        self.total_in += consumed;
+        self.total_out += produced;
+        self.finished = stream.internal_state.state == isal::isal_zstate_state_ZSTATE_END;
+        Ok(Status {
+            consumed,
+            produced,
+            stream_end: self.finished,
+        })
+    }
+
+    /// Prepare to compress a new stream/member with the same options
+    pub fn reset(&mut self) {
+        self.zstream.reset();
+        self.zstream.stream.end_of_stream = 0;
+        self.finished = false;
+        self.total_in = 0;
+        self.total_out = 0;
+    }
+
+    /// If the end of the stream has been written
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Bytes of input consumed since the last reset
+    pub fn total_in(&self) -> usize {
+        self.total_in
+    }
+
+    /// Bytes of output produced since the last reset
+    pub fn total_out(&self) -> usize {
+        self.total_out
+    }
+}
+
+/// Incremental decompression of a single gzip member, zlib stream or deflate stream.
+///
+/// Wrapper headers are parsed here rather than by `isal_inflate`, so the body can be
+/// inflated with the `*_NO_HDR_VER` flags, which lets ISA-L verify the trailer and
+/// leaves us free to set a dictionary once the header asks for one.
+///
+/// Unless the `ChecksumPolicy` is `Verify`, the trailer is read here as well.
+///
+/// Example
+/// -------
+/// ```
+/// use isal::igzip::{Decompress, CompressOptions, Codec, compress};
+///
+/// let compressed = compress(b"Hello, World!".as_slice(), CompressOptions::default()).unwrap();
+/// let mut decompress = Decompress::new(Codec::Gzip);
+/// let mut decompressed = vec![];
+/// let mut buf = [0u8; 4];
+///
+/// let mut input = compressed.as_slice();
+/// loop {
+///     let status = decompress.decompress(input, &mut buf).unwrap();
+///     input = &input[status.consumed..];
+///     decompressed.extend_from_slice(&buf[..status.produced]);
+///     if status.stream_end {
+///         break;
+///     }
+/// }
+/// assert_eq!(decompressed, b"Hello, World!");
+/// assert_eq!(decompress.total_in(), compressed.len());
+/// ```
+pub struct Decompress {
+    zst: Box<InflateState>,
+    options: DecompressOptions,
+    // gzip header bytes seen so far, until the whole header can be parsed
+    gz_hdr_buf: Vec<u8>,
+    gz_hdr: Option<GzipHeader>,
+    zlib_hdr: [u8; 6],
+    zlib_hdr_len: usize,
+    zlib_header: Option<ZlibHeader>,
+    header_done: bool,
+    // trailer read by us rather than isa-l, of trailer_len bytes (0 when there is none)
+    trailer: [u8; 8],
+    trailer_len: usize,
+    trailer_pos: usize,
+    body_done: bool,
+    finished: bool,
+    total_in: usize,
+    total_out: usize,
+    // input consumed by previous streams/members, and where the last one ended
+    offset: u64,
+    member_end: Option<u64>,
+    mismatches: Vec<ChecksumMismatch>,
+}
+
+impl Decompress {
+    /// Create a new stream, accepting either a `Codec` or `DecompressOptions`.
+    pub fn new(options: impl Into<DecompressOptions>) -> Self {
+        let mut this = Self {
+            zst: Box::new(InflateState::new()),
+            options: options.into(),
+            gz_hdr_buf: vec![],
+            gz_hdr: None,
+            zlib_hdr: [0; 6],
+            zlib_hdr_len: 0,
+            zlib_header: None,
+            header_done: false,
+            trailer: [0; 8],
+            trailer_len: 0,
+            trailer_pos: 0,
+            body_done: false,
+            finished: false,
+            total_in: 0,
+            total_out: 0,
+            offset: 0,
+            member_end: None,
+            mismatches: vec![],
+        };
+        this.reset();
+        this
+    }
+
+    /// Prepare to decode a new stream/member
+    pub fn reset(&mut self) {
+        self.zst.reset();
+        self.zst.0.hist_bits = self.options.window_bits.unwrap_or(0) as _;
+        let (crc_flag, trailer_len) = match (self.options.checksum_policy, self.options.codec) {
+            (ChecksumPolicy::Verify, Codec::Gzip) => (isal::ISAL_GZIP_NO_HDR_VER, 0),
+            (ChecksumPolicy::Verify, Codec::Zlib) => (isal::ISAL_ZLIB_NO_HDR_VER, 0),
+            (ChecksumPolicy::Verify, codec) => (codec as _, 0),
+            (ChecksumPolicy::Report, Codec::Gzip | Codec::GzipNoHdrVer) => {
+                (isal::ISAL_GZIP_NO_HDR, 8)
+            }
+            (ChecksumPolicy::Report, Codec::Zlib | Codec::ZlibNoHdrVer) => {
+                (isal::ISAL_ZLIB_NO_HDR, 4)
+            }
+            (ChecksumPolicy::Report, codec) => (codec as _, 0),
+            (ChecksumPolicy::Skip, Codec::Gzip | Codec::GzipNoHdrVer) => (isal::ISAL_DEFLATE, 8),
+            (ChecksumPolicy::Skip, Codec::Zlib | Codec::ZlibNoHdrVer) => (isal::ISAL_DEFLATE, 4),
+            (ChecksumPolicy::Skip, _) => (isal::ISAL_DEFLATE, 0),
+        };
+        self.zst.0.crc_flag = crc_flag;
+        self.trailer_len = trailer_len;
+        self.trailer_pos = 0;
+        self.body_done = false;
+        self.gz_hdr_buf.clear();
+        self.gz_hdr = None;
+        self.zlib_hdr_len = 0;
+        self.zlib_header = None;
+        self.header_done = !matches!(self.options.codec, Codec::Gzip | Codec::Zlib);
+        self.finished = false;
+        self.offset += self.total_in as u64;
+        self.total_in = 0;
+        self.total_out = 0;
+
+        // zlib announces its dictionary in the header, others get it up front
+        if self.options.codec != Codec::Zlib {
+            if let Some(dict) = &self.options.dictionary {
+                // Can't fail on a freshly reset state
+                let ret = self.zst.set_dict(dict);
+                debug_assert!(ret.is_ok());
+            }
+        }
+    }
+
+    /// If the end of the stream has been reached
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Header of the current gzip member, once it has been read
+    pub fn header(&self) -> Option<&GzipHeader> {
+        self.gz_hdr.as_ref()
+    }
+
+    /// Header of the current zlib stream, once it has been read
+    pub fn zlib_header(&self) -> Option<&ZlibHeader> {
+        self.zlib_header.as_ref()
+    }
+
+    /// Bytes of input consumed since the last reset
+    pub fn total_in(&self) -> usize {
+        self.total_in
+    }
+
+    /// Bytes of output produced since the last reset
+    pub fn total_out(&self) -> usize {
+        self.total_out
+    }
+
+    /// Offset in the input just past the last stream/member to end, across resets
+    pub fn member_end(&self) -> Option<u64> {
+        self.member_end
+    }
+
+    /// If input beginning with `byte` after the end of a stream/member is another one
+    pub(crate) fn starts_member(&self, byte: u8) -> bool {
+        if !self.options.multi_member {
+            return false;
+        }
+        match self.options.codec {
+            Codec::Gzip => byte == 0x1f,
+            // deflate method and a window no larger than 32KiB
+            Codec::Zlib => byte & 0x0f == 8 && byte >> 4 <= 7,
+            _ => true,
+        }
+    }
+
+    /// The policy for input following the last stream/member
+    pub(crate) fn trailing_data(&self) -> TrailingData {
+        match (self.options.trailing_data, self.options.multi_member) {
+            (Some(policy), _) => policy,
+            (None, true) => TrailingData::Error,
+            (None, false) => TrailingData::Stop,
+        }
+    }
+
+    /// Mismatching trailers seen so far under `ChecksumPolicy::Report`, kept across resets
+    pub fn checksum_mismatches(&self) -> &[ChecksumMismatch] {
+        &self.mismatches
+    }
+
+    /// Decompress as much of `input` into `output` as possible.
+    ///
+    /// Once `Status::stream_end` is reported nothing more is taken, `Decompress::reset`
+    /// to go on with a following stream/member.
+    pub fn decompress(&mut self, input: &[u8], output: &mut [u8]) -> Result<Status> {
+        if self.finished {
+            return Ok(Status {
+                consumed: 0,
+                produced: 0,
+                stream_end: true,
+            });
+        }
+
+        let mut consumed = 0;
+        if !self.header_done {
+            consumed = self.read_header(input)?;
+            self.total_in += consumed;
+            if !self.header_done {
+                return Ok(Status {
+                    consumed,
+                    produced: 0,
+                    stream_end: false,
+                });
+            }
+        }
+
+        let mut produced = 0;
+        if !self.body_done {
+            let input = &input[consumed..];
+            self.zst.0.next_in = input.as_ptr() as *mut _;
+            self.zst.0.avail_in = input.len() as _;
+            self.zst.0.next_out = output.as_mut_ptr();
+            self.zst.0.avail_out = output.len() as _;
+
+            self.zst.step_inflate()?;
+
+            let n_in = input.len() - self.zst.0.avail_in as usize;
+            self.total_in += n_in;
+            consumed += n_in;
+            produced = output.len() - self.zst.0.avail_out as usize;
+            self.total_out += produced;
+            self.body_done = self.zst.block_state() == isal::isal_block_state_ISAL_BLOCK_FINISH;
+        }
+
+        if self.body_done {
+            let n_in = self.read_trailer(&input[consumed..]);
+            self.total_in += n_in;
+            consumed += n_in;
+            self.finished = self.trailer_pos == self.trailer_len;
+            if self.finished {
+                self.member_end = Some(self.offset + self.total_in as u64);
+                if self.options.checksum_policy == ChecksumPolicy::Report {
+                    self.check_trailer();
+                }
+            }
+        }
+
+        Ok(Status {
+            consumed,
+            produced,
+            stream_end: self.finished,
+        })
+    }
+
+    // Buffer what we can of the trailer, returning the number of bytes consumed
+    fn read_trailer(&mut self, input: &[u8]) -> usize {
+        let n = std::cmp::min(self.trailer_len - self.trailer_pos, input.len());
+        self.trailer[self.trailer_pos..self.trailer_pos + n].copy_from_slice(&input[..n]);
+        self.trailer_pos += n;
+        n
+    }
+
+    // Compare a complete trailer to what isa-l computed, recording any mismatch
+    fn check_trailer(&mut self) {
+        let trailer = &self.trailer[..self.trailer_len];
+        let computed = self.zst.0.crc;
+        let mismatch = match trailer.len() {
+            8 => {
+                let expected = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+                let expected_size = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+                let computed_size = self.zst.0.total_out;
+                (expected != computed || expected_size != computed_size).then_some(
+                    ChecksumMismatch {
+                        expected,
+                        computed,
+                        expected_size: Some(expected_size),
+                        computed_size: Some(computed_size),
+                    },
+                )
+            }
+            4 => {
+                let expected = u32::from_be_bytes(trailer.try_into().unwrap());
+                (expected != computed).then_some(ChecksumMismatch {
+                    expected,
+                    computed,
+                    expected_size: None,
+                    computed_size: None,
+                })
+            }
+            _ => None,
+        };
+        if let Some(mismatch) = mismatch {
+            if let Some(callback) = &self.options.on_checksum_mismatch {
+                callback(&mismatch);
+            }
+            self.mismatches.push(mismatch);
+        }
+    }
+
+    // Parse the wrapper header from `input`, returning the number of bytes consumed;
+    // headers may be split across any number of calls.
+    fn read_header(&mut self, input: &[u8]) -> Result<usize> {
+        match self.options.codec {
+            Codec::Gzip => {
+                // Buffer a growing chunk of input at a time, so neither the whole input
+                // is copied nor the header re-parsed once per byte
+                let mut consumed = 0;
+                while consumed < input.len() {
+                    let n = std::cmp::min(input.len() - consumed, self.gz_hdr_buf.len().max(512));
+                    let prev_len = self.gz_hdr_buf.len();
+                    self.gz_hdr_buf
+                        .extend_from_slice(&input[consumed..consumed + n]);
+
+                    if let Some((header, len)) = GzipHeader::parse_partial(&self.gz_hdr_buf)? {
+                        self.gz_hdr = Some(header);
+                        self.gz_hdr_buf.clear();
+                        self.header_done = true;
+                        return Ok(consumed + len - prev_len);
+                    }
+                    consumed += n;
+                }
+                Ok(consumed)
+            }
+            Codec::Zlib => {
+                // At most 6 bytes, so collect them one at a time until the header parses
+                let mut consumed = 0;
+                loop {
+                    let buf = &self.zlib_hdr[..self.zlib_hdr_len];
+                    if let Some((header, _)) = ZlibHeader::parse_partial(buf)? {
+                        // its window must fit in ours
+                        let max_window_bits = self.options.window_bits.unwrap_or(MAX_WINDOW_BITS);
+                        if header.get_window_bits() > max_window_bits {
+                            return Err(Error::DecompressionError(DecompCode::InvalidWrapper));
+                        }
+                        self.zst.0.hist_bits = header.get_window_bits() as _;
+                        if let Some(dict_id) = header.get_dict_id() {
+                            self.set_zlib_dict(dict_id)?;
+                        }
+                        self.zlib_header = Some(header);
+                        self.header_done = true;
+                        return Ok(consumed);
+                    }
+                    if consumed == input.len() {
+                        return Ok(consumed);
+                    }
+                    self.zlib_hdr[self.zlib_hdr_len] = input[consumed];
+                    self.zlib_hdr_len += 1;
+                    consumed += 1;
+                }
+            }
+            _ => {
+                self.header_done = true;
+                Ok(0)
+            }
+        }
+    }
+
+    // Find and set the dictionary a zlib header asked for by id
+    fn set_zlib_dict(&mut self, dict_id: u32) -> Result<()> {
+        let dict = match &self.options.dictionary_lookup {
+            Some(lookup) => lookup(dict_id),
+            None => None,
+        };
+        let dict = dict.or_else(|| {
+            self.options
+                .dictionary
+                .clone()
+                .filter(|dict| adler32(dict) == dict_id)
+        });
+        match dict {
+            Some(dict) => self.zst.set_dict(&dict),
+            None => Err(Error::DecompressionError(DecompCode::NeedDict)),
+        }
+    }
+}
+
+/// Adler32 of `data`, as used for zlib trailers and dictionary ids
+#[inline]
+pub fn adler32(data: &[u8]) -> u32 {
+    unsafe { isal::isal_adler32(1, data.as_ptr(), data.len() as _) }
+}
+
+/// CRC32 of `data`, as used for gzip trailers
+#[inline]
+pub fn crc32(data: &[u8]) -> u32 {
+    unsafe { isal_sys::crc::crc32_gzip_refl(0, data.as_ptr(), data.len() as _) }
+}
+
+// Reflected CRC32 polynomial used by gzip
+const CRC32_POLY: u32 = 0xedb8_8320;
+
+// x^(2^n) modulo the CRC32 polynomial, for n in 0..32
+const X2N_TABLE: [u32; 32] = x2n_table();
+
+// Multiply `a` by `b` modulo the CRC32 polynomial, both reflected
+const fn multmodp(a: u32, mut b: u32) -> u32 {
+    let mut m = 1u32 << 31;
+    let mut p = 0u32;
+    loop {
+        if a & m != 0 {
+            p ^= b;
+            if a & (m - 1) == 0 {
+                break;
+            }
+        }
+        m >>= 1;
+        b = if b & 1 != 0 {
+            (b >> 1) ^ CRC32_POLY
+        } else {
+            b >> 1
+        };
+    }
+    p
+}
+
+const fn x2n_table() -> [u32; 32] {
+    let mut table = [0u32; 32];
+    // x^1
+    let mut p = 1u32 << 30;
+    let mut n = 0;
+    while n < 32 {
+        table[n] = p;
+        p = multmodp(p, p);
+        n += 1;
+    }
+    table
+}
+
+/// CRC32 of two pieces of data one after the other, from the CRC32 of each and
+/// the length of the second.
+///
+/// Example
+/// -------
+/// ```
+/// use isal::igzip::{crc32, crc32_combine};
+///
+/// let crc = crc32_combine(crc32(b"Hello, "), crc32(b"World!"), 6);
+/// assert_eq!(crc, crc32(b"Hello, World!"));
+/// ```
+pub fn crc32_combine(crc1: u32, crc2: u32, len2: u64) -> u32 {
+    // crc1 shifted over len2 zero bytes, i.e. multiplied by x^(8 * len2)
+    let mut p = 1u32 << 31;
+    let mut n = len2;
+    let mut k = 3;
+    while n != 0 {
+        if n & 1 != 0 {
+            p = multmodp(X2N_TABLE[k & 31], p);
+        }
+        n >>= 1;
+        k += 1;
+    }
+    multmodp(p, crc1) ^ crc2
+}
+
+// Largest prime below 2^16, the modulus of Adler32
+const ADLER32_BASE: u32 = 65521;
+
+/// Adler32 of two pieces of data one after the other, from the Adler32 of each and
+/// the length of the second.
+pub fn adler32_combine(adler1: u32, adler2: u32, len2: u64) -> u32 {
+    // Each byte of the second piece adds the first's sum of bytes once more to its
+    // sum of sums
+    let rem = (len2 % ADLER32_BASE as u64) as u32;
+    let mut sum1 = adler1 & 0xffff;
+    let mut sum2 = (rem * sum1) % ADLER32_BASE;
+    sum1 += (adler2 & 0xffff) + ADLER32_BASE - 1;
+    sum2 += (adler1 >> 16) + (adler2 >> 16) + ADLER32_BASE - rem;
+    if sum1 >= ADLER32_BASE {
+        sum1 -= ADLER32_BASE;
+    }
+    if sum1 >= ADLER32_BASE {
+        sum1 -= ADLER32_BASE;
+    }
+    if sum2 >= ADLER32_BASE << 1 {
+        sum2 -= ADLER32_BASE << 1;
+    }
+    if sum2 >= ADLER32_BASE {
+        sum2 -= ADLER32_BASE;
+    }
+    sum1 | (sum2 << 16)
+}
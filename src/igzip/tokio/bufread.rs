@@ -0,0 +1,294 @@
+//! Encoder and Decoder implementing `tokio::io::AsyncRead`, over readers implementing
+//! `tokio::io::AsyncBufRead`
+//!
+//! Input is taken straight from the reader's buffer, consuming exactly what was
+//! (de)compressed, so whatever follows the stream is left in the reader.
+use crate::igzip::poll::{ReadDecoder, ReadEncoder};
+use crate::igzip::*;
+use ::tokio::io::{AsyncBufRead, AsyncRead, ReadBuf};
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+/// Streaming compression for async input streams implementing `tokio::io::AsyncBufRead`.
+///
+/// Example
+/// -------
+/// ```
+/// use tokio::io::AsyncReadExt;
+/// use isal::igzip::{tokio::bufread::Encoder, CompressOptions, CompressionLevel, decompress, Codec};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let data = b"Hello, World!".to_vec();
+///
+/// let opts = CompressOptions::new(CompressionLevel::Three, Codec::Gzip);
+/// let mut encoder = Encoder::new(data.as_slice(), opts);
+/// let mut compressed = vec![];
+/// encoder.read_to_end(&mut compressed).await.unwrap();
+///
+/// let decompressed = decompress(compressed.as_slice(), Codec::Gzip).unwrap();
+/// assert_eq!(decompressed.as_slice(), data);
+/// # }
+/// ```
+pub struct Encoder<R: AsyncBufRead + Unpin> {
+    inner: R,
+    encoder: ReadEncoder,
+}
+
+impl<R: AsyncBufRead + Unpin> Encoder<R> {
+    /// Create a new `Encoder` which implements the `tokio::io::AsyncRead` trait.
+    ///
+    /// Unless set in `options`, the flush mode defaults to `FlushFlags::NoFlush`.
+    pub fn new(reader: R, options: CompressOptions) -> Encoder<R> {
+        Self {
+            inner: reader,
+            encoder: ReadEncoder::new(options),
+        }
+    }
+
+    /// Mutable reference to underlying reader, not advisable to modify during reading.
+    pub fn get_ref_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    // Reference to underlying reader
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Give up the underlying reader
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncRead for Encoder<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if buf.remaining() == 0 || this.encoder.is_finished() {
+            return Poll::Ready(Ok(()));
+        }
+        loop {
+            let input = ready!(Pin::new(&mut this.inner).poll_fill_buf(cx))?;
+            let (consumed, read) = this.encoder.step(input, buf.initialize_unfilled())?;
+            Pin::new(&mut this.inner).consume(consumed);
+            if let Some(n) = read {
+                buf.advance(n);
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+}
+
+/// Streaming decompression for async input streams implementing `tokio::io::AsyncBufRead`.
+///
+/// Only the bytes making up the stream are consumed from the reader, see
+/// `bufread::Decoder` for how what follows it is treated.
+///
+/// Example
+/// -------
+/// ```
+/// use tokio::io::AsyncReadExt;
+/// use isal::igzip::{tokio::bufread::Decoder, CompressOptions, compress, Codec};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let data = b"Hello, World!".to_vec();
+/// let compressed = compress(data.as_slice(), CompressOptions::default()).unwrap();
+///
+/// let mut decoder = Decoder::new(compressed.as_slice(), Codec::Gzip);
+/// let mut decompressed = vec![];
+/// decoder.read_to_end(&mut decompressed).await.unwrap();
+/// assert_eq!(decompressed.as_slice(), data);
+/// # }
+/// ```
+pub struct Decoder<R: AsyncBufRead + Unpin> {
+    inner: R,
+    decoder: ReadDecoder,
+}
+
+impl<R: AsyncBufRead + Unpin> Decoder<R> {
+    /// Create a new `Decoder`, accepting either a `Codec` or `DecompressOptions`.
+    pub fn new(reader: R, options: impl Into<DecompressOptions>) -> Decoder<R> {
+        Self {
+            inner: reader,
+            decoder: ReadDecoder::new(options),
+        }
+    }
+
+    /// Mutable reference to underlying reader, not advisable to modify during reading.
+    pub fn get_ref_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    // Reference to underlying reader
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Give up the underlying reader, positioned just past what was decompressed
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Header of the current gzip member, `None` until it has been read or
+    /// if the codec isn't `Codec::Gzip`
+    pub fn header(&self) -> Option<&GzipHeader> {
+        self.decoder.decomp().header()
+    }
+
+    /// Header of the current zlib stream, `None` until it has been read or
+    /// if the codec isn't `Codec::Zlib`
+    pub fn zlib_header(&self) -> Option<&ZlibHeader> {
+        self.decoder.decomp().zlib_header()
+    }
+
+    /// Trailers which didn't match the data under `ChecksumPolicy::Report`, across all members
+    pub fn checksum_mismatches(&self) -> &[ChecksumMismatch] {
+        self.decoder.decomp().checksum_mismatches()
+    }
+
+    /// Offset in the input just past the last gzip member (or zlib/deflate stream)
+    /// to end, `None` until one has.
+    pub fn member_end(&self) -> Option<u64> {
+        self.decoder.decomp().member_end()
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncRead for Decoder<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        loop {
+            let input = ready!(Pin::new(&mut this.inner).poll_fill_buf(cx))?;
+            let (consumed, read) = this.decoder.step(input, buf.initialize_unfilled())?;
+            Pin::new(&mut this.inner).consume(consumed);
+            if let Some(n) = read {
+                buf.advance(n);
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::igzip::tests::{gen_large_data, same_same};
+    use ::tokio::io::{AsyncReadExt, BufReader};
+
+    // Reader handing out a few bytes at a time, returning `Poll::Pending` in between
+    struct Stutter<'a> {
+        data: &'a [u8],
+        ready: bool,
+    }
+
+    impl AsyncRead for Stutter<'_> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            let input = ready!(self.as_mut().poll_fill_buf(cx))?;
+            let n = std::cmp::min(input.len(), buf.remaining());
+            buf.put_slice(&input[..n]);
+            self.consume(n);
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncBufRead for Stutter<'_> {
+        fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+            let this = self.get_mut();
+            this.ready = !this.ready;
+            if !this.ready {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let n = std::cmp::min(this.data.len(), 7);
+            Poll::Ready(Ok(&this.data[..n]))
+        }
+        fn consume(self: Pin<&mut Self>, amt: usize) {
+            let this = self.get_mut();
+            this.data = &this.data[amt..];
+        }
+    }
+
+    #[::tokio::test(crate = "::tokio")]
+    async fn roundtrip() -> Result<()> {
+        let data = gen_large_data();
+        for codec in [Codec::Gzip, Codec::Zlib, Codec::Deflate] {
+            let opts = CompressOptions::new(CompressionLevel::Three, codec);
+            let reader = BufReader::with_capacity(1000, data.as_slice());
+            let mut compressed = vec![];
+            Encoder::new(reader, opts)
+                .read_to_end(&mut compressed)
+                .await?;
+            assert!(same_same(&data, &decompress(compressed.as_slice(), codec)?));
+
+            let mut decompressed = vec![];
+            Decoder::new(compressed.as_slice(), codec)
+                .read_to_end(&mut decompressed)
+                .await?;
+            assert!(same_same(&data, &decompressed));
+        }
+        Ok(())
+    }
+
+    #[::tokio::test(crate = "::tokio")]
+    async fn pending_reader() -> Result<()> {
+        let data = gen_large_data();
+        let reader = Stutter {
+            data: &data,
+            ready: false,
+        };
+        let mut compressed = vec![];
+        Encoder::new(reader, CompressOptions::default())
+            .read_to_end(&mut compressed)
+            .await?;
+        assert!(same_same(
+            &data,
+            &decompress(compressed.as_slice(), Codec::Gzip)?
+        ));
+
+        let reader = Stutter {
+            data: &compressed,
+            ready: false,
+        };
+        let mut decompressed = vec![];
+        Decoder::new(reader, Codec::Gzip)
+            .read_to_end(&mut decompressed)
+            .await?;
+        assert!(same_same(&data, &decompressed));
+        Ok(())
+    }
+
+    #[::tokio::test(crate = "::tokio")]
+    async fn stops_at_stream_end() -> Result<()> {
+        let mut input = compress(b"foo".as_slice(), CompressOptions::default())?;
+        input.extend(b"trailer");
+
+        let opts = DecompressOptions::new(Codec::Gzip).multi_member(false);
+        let mut decoder = Decoder::new(input.as_slice(), opts);
+        let mut decompressed = vec![];
+        decoder.read_to_end(&mut decompressed).await?;
+        assert_eq!(decompressed, b"foo");
+        assert_eq!(decoder.into_inner(), b"trailer");
+
+        let mut decoder = Decoder::new(input.as_slice(), Codec::Gzip);
+        assert!(decoder.read_to_end(&mut vec![]).await.is_err());
+        Ok(())
+    }
+}
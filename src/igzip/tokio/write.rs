@@ -0,0 +1,300 @@
+//! Encoder and Decoder implementing `tokio::io::AsyncWrite`
+use crate::igzip::poll::{WriteDecoder, WriteEncoder};
+use crate::igzip::*;
+use ::tokio::io::AsyncWrite;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+/// Streaming compression for async output streams implementing `tokio::io::AsyncWrite`.
+///
+/// `flush` performs a sync flush, keeping the stream open; `shutdown` ends the stream,
+/// writing the trailer, before shutting down the inner writer. Unlike `write::Encoder`
+/// nothing is written on drop, so the stream must be shut down to be complete.
+///
+/// Example
+/// -------
+/// ```
+/// use tokio::io::AsyncWriteExt;
+/// use isal::igzip::{tokio::write::Encoder, CompressOptions, CompressionLevel, decompress, Codec};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let data = b"Hello, World!".to_vec();
+///
+/// let opts = CompressOptions::new(CompressionLevel::Three, Codec::Gzip);
+/// let mut encoder = Encoder::new(vec![], opts);
+/// encoder.write_all(&data).await.unwrap();
+/// encoder.shutdown().await.unwrap();
+///
+/// let compressed = encoder.into_inner();
+/// let decompressed = decompress(compressed.as_slice(), Codec::Gzip).unwrap();
+/// assert_eq!(decompressed.as_slice(), data);
+/// # }
+/// ```
+pub struct Encoder<W: AsyncWrite + Unpin> {
+    inner: W,
+    encoder: WriteEncoder,
+}
+
+impl<W: AsyncWrite + Unpin> Encoder<W> {
+    /// Create a new `Encoder` which implements the `tokio::io::AsyncWrite` trait.
+    ///
+    /// Unless set in `options`, the flush mode defaults to `FlushFlags::NoFlush`.
+    pub fn new(writer: W, options: CompressOptions) -> Encoder<W> {
+        Self {
+            inner: writer,
+            encoder: WriteEncoder::new(options),
+        }
+    }
+
+    /// Mutable reference to underlying writer, not advisable to modify during writing.
+    pub fn get_ref_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    // Reference to underlying writer
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Give up the underlying writer, any output it has yet to take is lost
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Encoder<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let Self { inner, encoder } = self.get_mut();
+        encoder.poll_write(buf, |out| Pin::new(&mut *inner).poll_write(cx, out))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let Self { inner, encoder } = self.get_mut();
+        ready!(encoder.poll_flush(|out| Pin::new(&mut *inner).poll_write(cx, out)))?;
+        ready!(Pin::new(&mut *inner).poll_flush(cx))?;
+        encoder.flush_done();
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let Self { inner, encoder } = self.get_mut();
+        ready!(encoder.poll_finish(|out| Pin::new(&mut *inner).poll_write(cx, out)))?;
+        Pin::new(inner).poll_shutdown(cx)
+    }
+}
+
+/// Streaming decompression for async output streams implementing `tokio::io::AsyncWrite`.
+///
+/// Example
+/// -------
+/// ```
+/// use tokio::io::AsyncWriteExt;
+/// use isal::igzip::{tokio::write::Decoder, CompressOptions, compress, Codec};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let data = b"Hello, World!".to_vec();
+/// let compressed = compress(data.as_slice(), CompressOptions::default()).unwrap();
+///
+/// let mut decoder = Decoder::new(vec![], Codec::Gzip);
+/// decoder.write_all(&compressed).await.unwrap();
+/// decoder.shutdown().await.unwrap();
+/// assert_eq!(decoder.into_inner(), data);
+/// # }
+/// ```
+pub struct Decoder<W: AsyncWrite + Unpin> {
+    inner: W,
+    decoder: WriteDecoder,
+}
+
+impl<W: AsyncWrite + Unpin> Decoder<W> {
+    /// Create a new `Decoder`, accepting either a `Codec` or `DecompressOptions`.
+    pub fn new(writer: W, options: impl Into<DecompressOptions>) -> Decoder<W> {
+        Self {
+            inner: writer,
+            decoder: WriteDecoder::new(options),
+        }
+    }
+
+    /// Mutable reference to underlying writer, not advisable to modify during writing.
+    pub fn get_ref_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    // Reference to underlying writer
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Give up the underlying writer, any output it has yet to take is lost
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// Header of the current gzip member, `None` until it has been read or
+    /// if the codec isn't `Codec::Gzip`
+    pub fn header(&self) -> Option<&GzipHeader> {
+        self.decoder.decomp().header()
+    }
+
+    /// Header of the current zlib stream, `None` until it has been read or
+    /// if the codec isn't `Codec::Zlib`
+    pub fn zlib_header(&self) -> Option<&ZlibHeader> {
+        self.decoder.decomp().zlib_header()
+    }
+
+    /// Trailers which didn't match the data under `ChecksumPolicy::Report`, across all members
+    pub fn checksum_mismatches(&self) -> &[ChecksumMismatch] {
+        self.decoder.decomp().checksum_mismatches()
+    }
+
+    /// Offset in the input just past the last gzip member (or zlib/deflate stream)
+    /// to end, `None` until one has. Under `TrailingData::Stop` what follows is taken
+    /// and discarded as with `TrailingData::Ignore`, this being where it began.
+    pub fn member_end(&self) -> Option<u64> {
+        self.decoder.decomp().member_end()
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Decoder<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let Self { inner, decoder } = self.get_mut();
+        decoder.poll_write(buf, |out| Pin::new(&mut *inner).poll_write(cx, out))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let Self { inner, decoder } = self.get_mut();
+        ready!(decoder.poll_drain(|out| Pin::new(&mut *inner).poll_write(cx, out)))?;
+        Pin::new(inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let Self { inner, decoder } = self.get_mut();
+        ready!(decoder.poll_drain(|out| Pin::new(&mut *inner).poll_write(cx, out)))?;
+        Pin::new(inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::igzip::tests::{gen_large_data, same_same};
+    use ::tokio::io::AsyncWriteExt;
+
+    // Writer taking a few bytes at a time, returning `Poll::Pending` in between
+    #[derive(Default)]
+    struct Stutter {
+        data: Vec<u8>,
+        ready: bool,
+    }
+
+    impl AsyncWrite for Stutter {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            this.ready = !this.ready;
+            if !this.ready {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let n = std::cmp::min(buf.len(), 7);
+            this.data.extend_from_slice(&buf[..n]);
+            Poll::Ready(Ok(n))
+        }
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[::tokio::test(crate = "::tokio")]
+    async fn roundtrip() -> Result<()> {
+        let data = gen_large_data();
+        for codec in [Codec::Gzip, Codec::Zlib, Codec::Deflate] {
+            let opts = CompressOptions::new(CompressionLevel::Three, codec);
+            let mut encoder = Encoder::new(vec![], opts);
+            encoder.write_all(&data).await?;
+            encoder.shutdown().await?;
+            let compressed = encoder.into_inner();
+            assert!(same_same(&data, &decompress(compressed.as_slice(), codec)?));
+
+            let mut decoder = Decoder::new(vec![], codec);
+            for chunk in compressed.chunks(1000) {
+                decoder.write_all(chunk).await?;
+            }
+            decoder.shutdown().await?;
+            assert!(same_same(&data, &decoder.into_inner()));
+        }
+        Ok(())
+    }
+
+    #[::tokio::test(crate = "::tokio")]
+    async fn pending_writer() -> Result<()> {
+        let data = gen_large_data();
+        let mut encoder = Encoder::new(Stutter::default(), CompressOptions::default());
+        encoder.write_all(&data[..1000]).await?;
+        // a sync flush makes what was written so far decompressable
+        encoder.flush().await?;
+        let flushed = encoder.get_ref().data.clone();
+        let mut decoder = Decoder::new(Stutter::default(), Codec::Deflate);
+        decoder.write_all(&flushed[GZIP_HEADER_LEN..]).await?;
+        decoder.flush().await?;
+        assert_eq!(decoder.get_ref().data, &data[..1000]);
+
+        encoder.write_all(&data[1000..]).await?;
+        encoder.shutdown().await?;
+        let compressed = encoder.into_inner().data;
+        assert!(same_same(
+            &data,
+            &decompress(compressed.as_slice(), Codec::Gzip)?
+        ));
+
+        let mut decoder = Decoder::new(Stutter::default(), Codec::Gzip);
+        decoder.write_all(&compressed).await?;
+        decoder.shutdown().await?;
+        assert!(same_same(&data, &decoder.into_inner().data));
+        Ok(())
+    }
+
+    #[::tokio::test(crate = "::tokio")]
+    async fn decoder_trailing_data() -> Result<()> {
+        let mut input = compress(b"foo".as_slice(), CompressOptions::default())?;
+        input.extend(b"trailer");
+
+        let opts = DecompressOptions::new(Codec::Gzip).trailing_data(TrailingData::Ignore);
+        let mut decoder = Decoder::new(vec![], opts);
+        decoder.write_all(&input).await?;
+        decoder.shutdown().await?;
+        assert_eq!(decoder.into_inner(), b"foo");
+
+        let mut decoder = Decoder::new(vec![], Codec::Gzip);
+        assert!(decoder.write_all(&input).await.is_err());
+
+        // a single member, the rest discarded
+        let first_len = input.len() - 7;
+        input.extend(compress(b"bar".as_slice(), CompressOptions::default())?);
+        let opts = DecompressOptions::new(Codec::Gzip).multi_member(false);
+        let mut decoder = Decoder::new(vec![], opts);
+        decoder.write_all(&input).await?;
+        decoder.shutdown().await?;
+        assert_eq!(decoder.member_end(), Some(first_len as u64));
+        assert_eq!(decoder.into_inner(), b"foo");
+        Ok(())
+    }
+}
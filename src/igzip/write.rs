@@ -1,7 +1,6 @@
 //! Encoder and Decoder implementing `std::io::Write`
 use crate::igzip::*;
 use std::io;
-use std::io::Write;
 
 /// Streaming compression for input streams implementing `std::io::Write`.
 ///
@@ -10,94 +9,160 @@ use std::io::Write;
 /// One should consider using `crate::igzip::compress` or `crate::igzip::compress_into` if possible.
 /// In that context, we do not need to hold and maintain intermediate buffers for reading and writing.
 ///
+/// `flush` performs a sync flush, keeping the stream open; the stream is ended with
+/// `try_finish` or `finish`, or when the `Encoder` is dropped. Writing after `try_finish`
+/// begins a new stream (gzip member).
+///
 /// Example
 /// -------
 /// ```
 /// use std::{io, io::Write};
-/// use isal::igzip::{write::Encoder, CompressionLevel, decompress, Codec};
+/// use isal::igzip::{write::Encoder, CompressOptions, CompressionLevel, decompress, Codec};
 ///
 /// let data = b"Hello, World!".to_vec();
 /// let mut compressed = vec![];
 ///
-/// let mut encoder = Encoder::new(&mut compressed, CompressionLevel::Three, Codec::Gzip);
+/// let opts = CompressOptions::new(CompressionLevel::Three, Codec::Gzip);
+/// let mut encoder = Encoder::new(&mut compressed, opts);
 ///
 /// // Numbeer of compressed bytes written to `output`
 /// io::copy(&mut io::Cursor::new(&data), &mut encoder).unwrap();
 ///
-/// // call .flush to finish the stream
-/// encoder.flush().unwrap();
+/// // call .finish to finish the stream
+/// encoder.finish().unwrap();
 ///
 /// let decompressed = decompress(io::Cursor::new(&compressed), Codec::Gzip).unwrap();
 /// assert_eq!(decompressed.as_slice(), data);
 ///
 /// ```
 pub struct Encoder<W: io::Write> {
-    inner: W,
+    // only taken by `finish`
+    inner: Option<W>,
     stream: ZStream,
     out_buf: Vec<u8>,
     dsts: usize,
     dste: usize,
     total_in: usize,
     total_out: usize,
-    codec: Codec,
+    flush: FlushFlags,
+    finished: bool,
 }
 
 impl<W: io::Write> Encoder<W> {
-    /// Create a new `Encoder` which implements the `std::io::Read` trait.
-    pub fn new(writer: W, level: CompressionLevel, codec: Codec) -> Encoder<W> {
+    /// Create a new `Encoder` which implements the `std::io::Write` trait.
+    ///
+    /// Unless set in `options`, the flush mode defaults to `FlushFlags::NoFlush`.
+    pub fn new(writer: W, options: CompressOptions) -> Encoder<W> {
         let out_buf = Vec::with_capacity(BUF_SIZE);
 
-        let mut zstream = ZStream::new(level, ZStreamKind::Stateful);
+        let mut zstream = ZStream::from_options(&options, ZStreamKind::Stateful);
 
         zstream.stream.end_of_stream = 0;
-        zstream.stream.flush = FlushFlags::NoFlush as _;
-        zstream.stream.gzip_flag = codec as _;
+        zstream.stream.flush = options.flush.unwrap_or(FlushFlags::NoFlush) as _;
 
         Self {
-            inner: writer,
+            inner: Some(writer),
             stream: zstream,
             out_buf,
             dste: 0,
             dsts: 0,
             total_in: 0,
             total_out: 0,
-            codec,
+            flush: options.flush.unwrap_or(FlushFlags::NoFlush),
+            finished: false,
         }
     }
 
     /// Mutable reference to underlying reader, not advisable to modify during reading.
     pub fn get_ref_mut(&mut self) -> &mut W {
-        &mut self.inner
+        self.inner.as_mut().unwrap()
     }
 
     // Reference to underlying reader
     pub fn get_ref(&self) -> &W {
-        &self.inner
+        self.inner.as_ref().unwrap()
     }
 
     #[inline(always)]
     fn write_from_out_buf(&mut self) -> io::Result<usize> {
         let count = self.dste - self.dsts;
         self.inner
-            .write_all(&mut self.out_buf[self.dsts..self.dste])?;
+            .as_mut()
+            .unwrap()
+            .write_all(&self.out_buf[self.dsts..self.dste])?;
         self.out_buf.truncate(0);
         self.dsts = 0;
         self.dste = 0;
         Ok(count)
     }
 
-    /// Call flush and return the inner writer
+    // Deflate into the end of the output buffer
+    #[inline(always)]
+    fn deflate(&mut self) -> io::Result<()> {
+        self.out_buf.resize(self.dste + BUF_SIZE, 0);
+        self.stream.stream.avail_out = BUF_SIZE as _;
+        self.stream.stream.next_out = self.out_buf[self.dste..self.dste + BUF_SIZE].as_mut_ptr();
+        self.stream.deflate()?;
+        self.dste += BUF_SIZE - self.stream.stream.avail_out as usize;
+        Ok(())
+    }
+
+    // Compress all input given so far with `flush`, ending on a byte boundary
+    fn flush_with(&mut self, flush: FlushFlags) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.stream.stream.avail_in = 0;
+        self.stream.stream.flush = flush as _;
+        // isa-l is done flushing once it leaves room in the output buffer
+        loop {
+            self.deflate()?;
+            if self.stream.stream.avail_out > 0 {
+                break;
+            }
+        }
+        self.stream.stream.flush = self.flush as _;
+        self.write_from_out_buf()?;
+        Ok(())
+    }
+
+    /// Perform a full flush, after which decompression can restart from this point
+    /// without any of the preceding data, and flush the inner writer.
+    pub fn full_flush(&mut self) -> io::Result<()> {
+        self.flush_with(FlushFlags::FullFlush)?;
+        self.get_ref_mut().flush()
+    }
+
+    /// End the stream, writing the trailer, without giving up the inner writer.
+    ///
+    /// Does nothing if already finished, unless more data was written since, which
+    /// begins a new stream (gzip member) to be finished.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.stream.stream.avail_in = 0;
+        self.stream.stream.end_of_stream = 1;
+        while self.stream.stream.internal_state.state != isal::isal_zstate_state_ZSTATE_END {
+            self.deflate()?;
+        }
+        self.write_from_out_buf()?;
+        self.finished = true;
+        Ok(())
+    }
+
+    /// End the stream and return the inner writer
     pub fn finish(mut self) -> io::Result<W> {
-        self.flush()?;
-        Ok(self.inner)
+        self.try_finish()?;
+        Ok(self.inner.take().unwrap())
     }
 
-    /// total bytes written to the writer, inclusive of all streams if `flush` has been called before
+    /// total bytes written to the writer, inclusive of all streams
     pub fn total_out(&self) -> usize {
         self.stream.stream.total_out as usize + self.total_out
     }
 
-    /// total bytes processed, inclusive of all streams if `flush` has been called before
+    /// total bytes processed, inclusive of all streams
     pub fn total_in(&self) -> usize {
         self.stream.stream.total_in as usize + self.total_in
     }
@@ -108,51 +173,42 @@ impl<W: io::Write> io::Write for Encoder<W> {
         if buf.is_empty() {
             return Ok(0);
         }
+        if self.finished {
+            // Prep for the next stream; needs to store total_in/out separately
+            // as checksum is calculated from these values per stream
+            self.total_in += self.stream.stream.total_in as usize;
+            self.total_out += self.stream.stream.total_out as usize;
+            self.stream.reset();
+            self.stream.stream.flush = self.flush as _;
+            self.stream.stream.end_of_stream = 0;
+            self.finished = false;
+        }
         self.stream.stream.avail_in = buf.len() as _;
         self.stream.stream.next_in = buf.as_ptr() as *mut _;
 
         while self.stream.stream.avail_in > 0 {
-            self.out_buf.resize(self.dste + BUF_SIZE, 0);
-
-            self.stream.stream.avail_out = BUF_SIZE as _;
-            self.stream.stream.next_out =
-                self.out_buf[self.dste..self.dste + BUF_SIZE].as_mut_ptr();
-
-            self.stream.deflate()?;
-
-            self.dste += BUF_SIZE - self.stream.stream.avail_out as usize;
+            self.deflate()?;
         }
 
         self.write_from_out_buf()?;
 
         Ok(buf.len())
     }
+
+    /// Sync flush, writing out all data given so far, and flush the inner writer.
+    /// The stream is not ended, see `Encoder::finish`.
     fn flush(&mut self) -> io::Result<()> {
-        // Write footer and flush to inner
-        self.stream.stream.end_of_stream = 1;
-        self.stream.stream.flush = FlushFlags::FullFlush as _;
-        while self.stream.stream.internal_state.state != isal::isal_zstate_state_ZSTATE_END {
-            self.out_buf.resize(self.dste + BUF_SIZE, 0);
-            self.stream.stream.avail_out = BUF_SIZE as _;
-            self.stream.stream.next_out =
-                self.out_buf[self.dste..self.dste + BUF_SIZE].as_mut_ptr();
-            self.stream.deflate()?;
-            self.dste += BUF_SIZE - self.stream.stream.avail_out as usize;
+        self.flush_with(FlushFlags::SyncFlush)?;
+        self.get_ref_mut().flush()
+    }
+}
+
+impl<W: io::Write> Drop for Encoder<W> {
+    fn drop(&mut self) {
+        // Errors can't be reported from here, call `finish` to see them
+        if self.inner.is_some() {
+            let _ = self.try_finish();
         }
-        self.write_from_out_buf()?;
-        self.inner.flush()?;
-
-        // Prep for next stream should user call 'write' again after flush.
-        // needs to store total_in/out separately as checksum is calculated
-        // from these values per stream
-        self.total_in += self.stream.stream.total_in as usize;
-        self.total_out += self.stream.stream.total_out as usize;
-        unsafe { isal::isal_deflate_reset(&mut self.stream.stream) };
-
-        self.stream.stream.flush = FlushFlags::NoFlush as _;
-        self.stream.stream.end_of_stream = 0;
-        self.stream.stream.gzip_flag = self.codec as _;
-        Ok(())
     }
 }
 
@@ -167,10 +223,11 @@ impl<W: io::Write> io::Write for Encoder<W> {
 /// -------
 /// ```
 /// use std::{io, io::Write};
-/// use isal::igzip::{write::Decoder, CompressionLevel, compress, Codec};
+/// use isal::igzip::{write::Decoder, CompressOptions, CompressionLevel, compress, Codec};
 /// let data = b"Hello, World!".to_vec();
 ///
-/// let compressed = compress(io::Cursor::new(data.as_slice()), CompressionLevel::Three, Codec::Gzip).unwrap();
+/// let opts = CompressOptions::new(CompressionLevel::Three, Codec::Gzip);
+/// let compressed = compress(io::Cursor::new(data.as_slice()), opts).unwrap();
 ///
 /// let mut decompressed = vec![];
 /// let mut decoder = Decoder::new(&mut decompressed, Codec::Gzip);
@@ -182,27 +239,20 @@ impl<W: io::Write> io::Write for Encoder<W> {
 /// ```
 pub struct Decoder<W: io::Write> {
     inner: W,
-    zst: InflateState,
+    decomp: Decompress,
     out_buf: Vec<u8>,
-    dsts: usize,
-    dste: usize,
-    codec: Codec,
-    adler32: u32,
+    // trailing data seen under `TrailingData::Ignore` or `Stop`, discard all further input
+    ignore_rest: bool,
 }
 
 impl<W: io::Write> Decoder<W> {
-    pub fn new(writer: W, codec: Codec) -> Decoder<W> {
-        let mut zst = InflateState::new();
-        zst.0.crc_flag = codec as _;
-
+    /// Create a new `Decoder`, accepting either a `Codec` or `DecompressOptions`.
+    pub fn new(writer: W, options: impl Into<DecompressOptions>) -> Decoder<W> {
         Self {
             inner: writer,
-            zst,
-            out_buf: Vec::with_capacity(BUF_SIZE),
-            dste: 0,
-            dsts: 0,
-            codec,
-            adler32: 1,
+            decomp: Decompress::new(options.into()),
+            out_buf: vec![0u8; BUF_SIZE],
+            ignore_rest: false,
         }
     }
 
@@ -216,122 +266,75 @@ impl<W: io::Write> Decoder<W> {
         &self.inner
     }
 
-    #[inline(always)]
-    fn write_from_out_buf(&mut self) -> io::Result<usize> {
-        let count = self.dste - self.dsts;
-        self.inner
-            .write_all(&mut self.out_buf[self.dsts..self.dste])?;
-        self.out_buf.truncate(0);
-        self.dsts = 0;
-        self.dste = 0;
-        Ok(count)
+    /// Header of the current gzip member, `None` until it has been read or
+    /// if the codec isn't `Codec::Gzip`
+    pub fn header(&self) -> Option<&GzipHeader> {
+        self.decomp.header()
+    }
+
+    /// Header of the current zlib stream, `None` until it has been read or
+    /// if the codec isn't `Codec::Zlib`
+    pub fn zlib_header(&self) -> Option<&ZlibHeader> {
+        self.decomp.zlib_header()
+    }
+
+    /// Trailers which didn't match the data under `ChecksumPolicy::Report`, across all members
+    pub fn checksum_mismatches(&self) -> &[ChecksumMismatch] {
+        self.decomp.checksum_mismatches()
+    }
+
+    /// Offset in the input just past the last gzip member (or zlib/deflate stream)
+    /// to end, `None` until one has.
+    ///
+    /// With `DecompressOptions::multi_member(false)` this is where the only member
+    /// ended. Input can't be handed back through a writer, so under `TrailingData::Stop`
+    /// what follows is taken and discarded as with `TrailingData::Ignore`, this being
+    /// where it began.
+    pub fn member_end(&self) -> Option<u64> {
+        self.decomp.member_end()
     }
 }
 
 impl<W: io::Write> io::Write for Decoder<W> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        // Check if there is data left in out_buf, otherwise refill; if end state, return 0
-        // Read out next buf len worth to compress; filling intermediate out_buf
-        debug_assert_eq!(self.zst.0.avail_in, 0);
-        self.zst.0.avail_in = buf.len() as _;
-        self.zst.0.next_in = buf.as_ptr() as *mut _;
-
-        let mut n_bytes = 0;
-        while self.zst.0.avail_in > 0 {
-            if self.zst.block_state() == isal::isal_block_state_ISAL_BLOCK_NEW_HDR {
-                // Read gzip header
-                if self.codec == Codec::Gzip {
-                    // Read this member's gzip header
-                    let mut gz_hdr: mem::MaybeUninit<isal::isal_gzip_header> =
-                        mem::MaybeUninit::uninit();
-                    unsafe { isal::isal_gzip_header_init(gz_hdr.as_mut_ptr()) };
-                    let mut gz_hdr = unsafe { gz_hdr.assume_init() };
-                    read_gzip_header(&mut self.zst.0, &mut gz_hdr)?;
-
-                // Read zlib header
-                } else if self.codec == Codec::Zlib {
-                    self.zst.0.crc_flag = 0; // zlib uses adler-32
-
-                    let mut hdr: mem::MaybeUninit<isal::isal_zlib_header> =
-                        mem::MaybeUninit::uninit();
-                    unsafe { isal::isal_zlib_header_init(hdr.as_mut_ptr()) };
-                    let mut hdr = unsafe { hdr.assume_init() };
-                    read_zlib_header(&mut self.zst.0, &mut hdr)?;
-                    self.zst.0.next_in = buf[2..].as_ptr() as *mut _; // skip header now that it's read
-                                                                      // self.zst.0.avail_in -= 4; // skip adler-32 trailer
+        if self.ignore_rest {
+            return Ok(buf.len());
+        }
+        let mut pos = 0;
+        loop {
+            // Previous stream/member done, begin the next one if there is more input
+            if self.decomp.is_finished() {
+                if pos == buf.len() {
+                    break;
                 }
-            }
-
-            // decompress member
-            loop {
-                self.out_buf.resize(n_bytes + BUF_SIZE, 0);
-
-                self.zst.0.next_out = self.out_buf[n_bytes..n_bytes + BUF_SIZE].as_mut_ptr();
-                self.zst.0.avail_out = BUF_SIZE as _;
-
-                self.zst.step_inflate()?;
-
-                n_bytes += BUF_SIZE - self.zst.0.avail_out as usize;
-
-                let state = self.zst.block_state();
-                match self.codec {
-                    Codec::Deflate | Codec::Zlib => {
-                        // On block finished we're done done w/ the block,
-                        // on block coded, we need to move onto the next input buffer
-                        if state == isal::isal_block_state_ISAL_BLOCK_FINISH
-                            || state == isal::isal_block_state_ISAL_BLOCK_CODED
-                        {
-                            break;
+                if !self.decomp.starts_member(buf[pos]) {
+                    match self.decomp.trailing_data() {
+                        TrailingData::Error => {
+                            let offset = self.decomp.member_end().unwrap_or_default();
+                            return Err(Error::TrailingData(offset).into());
                         }
-                    }
-                    Codec::Gzip => {
-                        if state == isal::isal_block_state_ISAL_BLOCK_CODED
-                            || state == isal::isal_block_state_ISAL_BLOCK_TYPE0
-                            || state == isal::isal_block_state_ISAL_BLOCK_HDR
-                            || state == isal::isal_block_state_ISAL_BLOCK_FINISH
-                        {
-                            break;
+                        TrailingData::Ignore | TrailingData::Stop => {
+                            self.ignore_rest = true;
+                            pos = buf.len();
                         }
                     }
+                    break;
                 }
+                self.decomp.reset();
             }
-            if self.zst.0.block_state == isal::isal_block_state_ISAL_BLOCK_FINISH {
-                self.zst.reset();
-            }
-        }
-        // zlib adler32
-        if self.codec == Codec::Zlib && buf.len() > 4 {
-            // Update adler
-            self.adler32 =
-                unsafe { isal::isal_adler32(self.adler32, self.out_buf.as_ptr(), n_bytes as _) };
-
-            // when end of block, verify adler matches (state reset above on block finish)
-            if self.zst.block_state() == isal::isal_block_state_ISAL_BLOCK_NEW_HDR {
-                // unwrap ok, ensured buf len > 4 above
-                debug_assert!(buf.len() > 4);
-                let bytes: [u8; 4] = (buf[buf.len() - 4..buf.len()]).try_into().unwrap();
-                let expected_adler32 = u32::from_be_bytes(bytes);
-                if self.adler32 != expected_adler32 {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        Error::DecompressionError(DecompCode::IncorrectChecksum),
-                    ));
-                }
-            }
-        }
-        self.out_buf.truncate(n_bytes);
-        self.dste = n_bytes;
-        self.dsts = 0;
-        self.write_from_out_buf()?;
 
-        Ok(buf.len())
-    }
-    fn flush(&mut self) -> io::Result<()> {
-        loop {
-            if self.write_from_out_buf()? == 0 {
+            let status = self.decomp.decompress(&buf[pos..], &mut self.out_buf)?;
+            pos += status.consumed;
+            self.inner.write_all(&self.out_buf[..status.produced])?;
+
+            // All input taken and output drained
+            if pos == buf.len() && status.produced < self.out_buf.len() {
                 break;
             }
         }
+        Ok(pos)
+    }
+    fn flush(&mut self) -> io::Result<()> {
         self.inner.flush()
     }
 }
@@ -345,9 +348,19 @@ pub struct DeflateEncoder<R: io::Write> {
 impl<W: io::Write> DeflateEncoder<W> {
     pub fn new(writer: W, level: CompressionLevel) -> Self {
         Self {
-            inner: Encoder::new(writer, level, Codec::Deflate),
+            inner: Encoder::new(writer, CompressOptions::new(level, Codec::Deflate)),
         }
     }
+
+    /// End the stream without giving up the inner writer, see `Encoder::try_finish`
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        self.inner.try_finish()
+    }
+
+    /// End the stream and return the inner writer
+    pub fn finish(self) -> io::Result<W> {
+        self.inner.finish()
+    }
 }
 
 impl<W: io::Write> io::Write for DeflateEncoder<W> {
@@ -391,9 +404,19 @@ pub struct ZlibEncoder<R: io::Write> {
 impl<W: io::Write> ZlibEncoder<W> {
     pub fn new(writer: W, level: CompressionLevel) -> Self {
         Self {
-            inner: Encoder::new(writer, level, Codec::Zlib),
+            inner: Encoder::new(writer, CompressOptions::new(level, Codec::Zlib)),
         }
     }
+
+    /// End the stream without giving up the inner writer, see `Encoder::try_finish`
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        self.inner.try_finish()
+    }
+
+    /// End the stream and return the inner writer
+    pub fn finish(self) -> io::Result<W> {
+        self.inner.finish()
+    }
 }
 
 impl<W: io::Write> io::Write for ZlibEncoder<W> {
@@ -437,9 +460,19 @@ pub struct GzipEncoder<R: io::Write> {
 impl<W: io::Write> GzipEncoder<W> {
     pub fn new(writer: W, level: CompressionLevel) -> Self {
         Self {
-            inner: Encoder::new(writer, level, Codec::Gzip),
+            inner: Encoder::new(writer, CompressOptions::new(level, Codec::Gzip)),
         }
     }
+
+    /// End the stream without giving up the inner writer, see `Encoder::try_finish`
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        self.inner.try_finish()
+    }
+
+    /// End the stream and return the inner writer
+    pub fn finish(self) -> io::Result<W> {
+        self.inner.finish()
+    }
 }
 
 impl<W: io::Write> io::Write for GzipEncoder<W> {
@@ -492,14 +525,15 @@ pub mod tests {
     }
     fn test_encoder_basic(data: &[u8]) {
         let mut compressed = vec![];
-        let mut encoder = Encoder::new(&mut compressed, CompressionLevel::Three, Codec::Gzip);
+        let mut encoder = Encoder::new(&mut compressed, CompressOptions::default());
         let nbytes = io::copy(&mut io::Cursor::new(&data), &mut encoder).unwrap();
 
-        // Footer isn't written until .flush is called
+        // Footer isn't written until .finish is called
         let before_flush_bytes_out = encoder.total_out();
-        encoder.flush().unwrap();
+        encoder.try_finish().unwrap();
         let after_flush_bytes_out = encoder.total_out();
         assert!(before_flush_bytes_out < after_flush_bytes_out);
+        drop(encoder);
 
         // nbytes read equals data lenth; compressed is between 0 and data length
         assert_eq!(nbytes, data.len() as _);
@@ -521,21 +555,88 @@ pub mod tests {
         let second = b"bar";
 
         let mut compressed = vec![];
-        let mut encoder = Encoder::new(&mut compressed, CompressionLevel::Three, Codec::Gzip);
+        let mut encoder = Encoder::new(&mut compressed, CompressOptions::default());
 
         encoder.write_all(first).unwrap();
-        encoder.flush().unwrap();
+        encoder.try_finish().unwrap();
         assert_eq!(encoder.total_in(), first.len());
 
         encoder.write_all(second).unwrap();
-        encoder.flush().unwrap();
+        encoder.try_finish().unwrap();
         assert_eq!(encoder.total_in(), first.len() + second.len());
+        drop(encoder);
 
         let decompressed =
             crate::igzip::decompress(io::Cursor::new(&compressed), Codec::Gzip).unwrap();
         assert_eq!(&decompressed, b"foobar");
     }
 
+    #[test]
+    fn encoder_flush_is_sync() {
+        let mut compressed = vec![];
+        let opts = CompressOptions::new(CompressionLevel::Three, Codec::Deflate);
+        let mut encoder = Encoder::new(&mut compressed, opts);
+        encoder.write_all(b"foo").unwrap();
+        encoder.flush().unwrap();
+
+        // everything so far can be decompressed, ending on an empty stored block
+        let flushed = encoder.get_ref().to_vec();
+        assert!(flushed.ends_with(&[0, 0, 0xff, 0xff]));
+        let mut decompressed = Vec::with_capacity(16);
+        flate2::Decompress::new(false)
+            .decompress_vec(&flushed, &mut decompressed, flate2::FlushDecompress::Sync)
+            .unwrap();
+        assert_eq!(decompressed, b"foo");
+
+        // still the same stream
+        encoder.write_all(b"bar").unwrap();
+        encoder.finish().unwrap();
+        let decompressed = crate::igzip::decompress(compressed.as_slice(), Codec::Deflate).unwrap();
+        assert_eq!(decompressed, b"foobar");
+    }
+
+    #[test]
+    fn encoder_full_flush() {
+        let mut compressed = vec![];
+        let opts = CompressOptions::new(CompressionLevel::Three, Codec::Deflate);
+        let mut encoder = Encoder::new(&mut compressed, opts);
+        encoder.write_all(b"Hello, World!").unwrap();
+        encoder.full_flush().unwrap();
+        let n = encoder.get_ref().len();
+        encoder.write_all(b"Hello, World!").unwrap();
+        encoder.finish().unwrap();
+
+        // the rest doesn't refer back to data before the full flush
+        let mut decompressed = Vec::with_capacity(64);
+        flate2::Decompress::new(false)
+            .decompress_vec(
+                &compressed[n..],
+                &mut decompressed,
+                flate2::FlushDecompress::Finish,
+            )
+            .unwrap();
+        assert_eq!(decompressed, b"Hello, World!");
+    }
+
+    #[test]
+    fn encoder_finish_on_drop() {
+        let data = gen_large_data();
+        let mut compressed = vec![];
+        {
+            let mut encoder = GzipEncoder::new(&mut compressed, CompressionLevel::Three);
+            io::copy(&mut Cursor::new(&data), &mut encoder).unwrap();
+        }
+        let decompressed = crate::igzip::decompress(compressed.as_slice(), Codec::Gzip).unwrap();
+        assert!(same_same(&data, &decompressed));
+
+        // finishing twice, or finishing then dropping, doesn't add another member
+        let mut encoder = Encoder::new(vec![], CompressOptions::default());
+        encoder.write_all(&data).unwrap();
+        encoder.try_finish().unwrap();
+        encoder.try_finish().unwrap();
+        assert_eq!(encoder.finish().unwrap(), compressed);
+    }
+
     #[test]
     fn test_decoder_basic_small() {
         test_decoder_basic(b"foobar")
@@ -546,8 +647,7 @@ pub mod tests {
     }
     fn test_decoder_basic(data: &[u8]) {
         let compressed =
-            crate::igzip::compress(io::Cursor::new(&data), CompressionLevel::Three, Codec::Gzip)
-                .unwrap();
+            crate::igzip::compress(io::Cursor::new(&data), CompressOptions::default()).unwrap();
 
         let mut decompressed = vec![];
         let mut decoder = Decoder::new(&mut decompressed, Codec::Gzip);
@@ -556,24 +656,71 @@ pub mod tests {
         assert!(same_same(&decompressed, &data));
     }
 
+    #[test]
+    fn test_decoder_single_member() -> Result<()> {
+        let first = crate::igzip::compress(b"foo".as_slice(), CompressOptions::default())?;
+        let mut compressed = first.clone();
+        compressed.extend(crate::igzip::compress(
+            b"bar".as_slice(),
+            CompressOptions::default(),
+        )?);
+
+        let mut decompressed = vec![];
+        let opts = DecompressOptions::new(Codec::Gzip).multi_member(false);
+        let mut decoder = Decoder::new(&mut decompressed, opts.clone());
+
+        // decodes the first member only, discarding the rest
+        assert_eq!(decoder.write(&compressed[..5])?, 5);
+        assert_eq!(decoder.write(&compressed[5..])?, compressed.len() - 5);
+        assert_eq!(decoder.write(&compressed)?, compressed.len());
+        assert_eq!(decoder.member_end(), Some(first.len() as u64));
+        drop(decoder);
+        assert_eq!(&decompressed, b"foo");
+
+        // so whole writes succeed
+        let mut decoder = Decoder::new(vec![], opts.clone());
+        decoder.write_all(&compressed)?;
+        decoder.write_all(&[0u8; 512])?;
+        assert_eq!(decoder.member_end(), Some(first.len() as u64));
+        assert_eq!(decoder.get_ref().as_slice(), b"foo");
+
+        let mut decoder = Decoder::new(vec![], opts.trailing_data(TrailingData::Stop));
+        let n = io::copy(&mut compressed.as_slice(), &mut decoder)?;
+        assert_eq!(n, compressed.len() as u64);
+        assert_eq!(decoder.get_ref().as_slice(), b"foo");
+        Ok(())
+    }
+
+    #[test]
+    fn test_decoder_trailing_data() -> Result<()> {
+        let compressed = crate::igzip::compress(b"foo".as_slice(), CompressOptions::default())?;
+
+        let mut decoder = Decoder::new(vec![], Codec::Gzip);
+        decoder.write_all(&compressed)?;
+        match decoder.write(&[0u8; 512]) {
+            Err(err) => assert!(err.to_string().contains("Trailing data")),
+            r => panic!("Expected trailing data error, got {:?}", r),
+        }
+
+        // ignored, even should it look like another member later on
+        let opts = DecompressOptions::new(Codec::Gzip).trailing_data(TrailingData::Ignore);
+        let mut decoder = Decoder::new(vec![], opts);
+        decoder.write_all(&compressed)?;
+        decoder.write_all(&[0u8; 512])?;
+        decoder.write_all(&compressed)?;
+        assert_eq!(decoder.get_ref().as_slice(), b"foo");
+        Ok(())
+    }
+
     #[test]
     fn test_decoder_multi_stream() {
         let first = b"foo";
         let second = b"bar";
 
-        let mut compressed = crate::igzip::compress(
-            io::Cursor::new(&first),
-            CompressionLevel::Three,
-            Codec::Gzip,
-        )
-        .unwrap();
+        let mut compressed =
+            crate::igzip::compress(io::Cursor::new(&first), CompressOptions::default()).unwrap();
         compressed.extend(
-            crate::igzip::compress(
-                io::Cursor::new(&second),
-                CompressionLevel::Three,
-                Codec::Gzip,
-            )
-            .unwrap(),
+            crate::igzip::compress(io::Cursor::new(&second), CompressOptions::default()).unwrap(),
         );
 
         let mut decompressed = vec![];
@@ -596,9 +743,9 @@ pub mod tests {
         // our encoder
         let mut compressed = vec![];
         {
-            let mut encoder = Encoder::new(&mut compressed, CompressionLevel::Three, Codec::Gzip);
+            let mut encoder = Encoder::new(&mut compressed, CompressOptions::default());
             io::copy(&mut Cursor::new(&data), &mut encoder).unwrap();
-            encoder.flush().unwrap();
+            encoder.finish().unwrap();
         }
 
         // their decoder
@@ -655,7 +802,7 @@ pub mod tests {
         {
             let mut encoder = DeflateEncoder::new(&mut compressed, CompressionLevel::Three);
             io::copy(&mut Cursor::new(&data), &mut encoder).unwrap();
-            encoder.flush().unwrap(); // TODO: impl flush on drop
+            // finished when dropped
         }
 
         // their decoder